@@ -0,0 +1,134 @@
+// Concurrent downloads of a zstd compressed file, decompressed on the runtime's workers (through AllowStdIo, how
+// downloads used to be served) vs on the blocking pool (blocking.rs)
+//
+// Also reports the worst delay of a 1ms timer ticking next to them, which is what every other request would wait
+//
+// cargo bench -p back --bench download
+
+#[path = "../src/blocking.rs"]
+#[allow(unused_imports, dead_code)] // Its tests only run with the server's, and only new is benched
+mod blocking;
+
+// Few workers, like a busy server
+const WORKERS: usize = 2;
+const DOWNLOADS: usize = 32;
+const FILE_SIZE: usize = 32 * 1024 * 1024;
+
+fn main() {
+    use std::time::Instant;
+
+    let path =
+        std::env::temp_dir().join(format!("storage_server_bench_{}.zst", std::process::id()));
+
+    let start = Instant::now();
+    write_sample(&path);
+    println!(
+        "Sample file ({} MiB decompressed) written in {:.2?}",
+        FILE_SIZE / 1024 / 1024,
+        start.elapsed()
+    );
+    println!("{DOWNLOADS} concurrent downloads, {WORKERS} workers\n");
+
+    for (name, off_runtime) in [("AllowStdIo", false), ("BlockingReader", true)] {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(WORKERS)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let (total, elapsed, max_lag) = runtime.block_on(run(&path, off_runtime));
+
+        println!(
+            "{name:<16} {:>8.1} MiB/s ({:.2?}), timer lagged up to {:.2?}",
+            total as f64 / 1024. / 1024. / elapsed.as_secs_f64(),
+            elapsed,
+            max_lag
+        );
+    }
+
+    let _ = std::fs::remove_file(&path);
+}
+
+// Random words, compresses about as well as text does
+fn write_sample(path: &std::path::Path) {
+    use {rand::Rng as _, std::io::Write as _};
+
+    const WORDS: &[&str] = &[
+        "storage", "server", "upload", "download", "cache", "entry", "chunk", "zstd", "rocket",
+        "tokio", "the", "a", "of", "and", "to", "in", "is", "it", "that", "with",
+    ];
+
+    let mut rng = rand::thread_rng();
+    let mut encoder = zstd::Encoder::new(std::fs::File::create(path).unwrap(), 3).unwrap();
+
+    let mut written = 0;
+    while written < FILE_SIZE {
+        let word = WORDS[rng.gen_range(0..WORDS.len())];
+        encoder.write_all(word.as_bytes()).unwrap();
+        encoder.write_all(b" ").unwrap();
+        written += word.len() + 1;
+    }
+
+    encoder.finish().unwrap();
+}
+
+// Bytes read, how long it took, and the worst timer delay
+async fn run(
+    path: &std::path::Path,
+    off_runtime: bool,
+) -> (u64, std::time::Duration, std::time::Duration) {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let probe = tokio::spawn({
+        let stop = Arc::clone(&stop);
+        async move {
+            let mut max_lag = Duration::ZERO;
+            while !stop.load(Ordering::Relaxed) {
+                let start = Instant::now();
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                max_lag = max_lag.max(start.elapsed().saturating_sub(Duration::from_millis(1)));
+            }
+            max_lag
+        }
+    });
+
+    let start = Instant::now();
+
+    let downloads = (0..DOWNLOADS)
+        .map(|_| tokio::spawn(download(path.to_path_buf(), off_runtime)))
+        .collect::<Vec<_>>();
+
+    let mut total = 0;
+    for download in downloads {
+        total += download.await.unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    stop.store(true, Ordering::Relaxed);
+
+    (total, elapsed, probe.await.unwrap())
+}
+
+async fn download(path: std::path::PathBuf, off_runtime: bool) -> u64 {
+    use tokio_util::compat::FuturesAsyncReadCompatExt as _;
+
+    let decoder = zstd::Decoder::new(std::fs::File::open(path).unwrap()).unwrap();
+
+    let mut reader: Box<dyn tokio::io::AsyncRead + Send + Unpin> = if off_runtime {
+        Box::new(blocking::BlockingReader::new(decoder))
+    } else {
+        Box::new(futures::io::AllowStdIo::new(decoder).compat())
+    };
+
+    tokio::io::copy(&mut reader, &mut tokio::io::sink())
+        .await
+        .unwrap()
+}
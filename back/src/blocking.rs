@@ -0,0 +1,166 @@
+// Runs a blocking reader (disk reads, decryption and decompression of a stored file) on tokio's blocking pool,
+// and hands its output to the runtime through a bounded channel
+//
+// The pool is bounded by rocket's max_blocking setting. The reading thread only gets CHANNEL_CAPACITY chunks ahead
+// of the client, then waits for it to catch up. It stops (dropping the reader, and the entry's file lock with it)
+// as soon as the receiving side is dropped, when the download ends, fails or times out
+//
+// Opening it can block too (locks, meta, data file), see BlockingReader::open
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHANNEL_CAPACITY: usize = 4;
+
+pub struct BlockingReader {
+    receiver: tokio::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    // Chunk being read, and how much of it was already read
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl BlockingReader {
+    // Needs to be called from the runtime
+    pub fn new(reader: impl std::io::Read + Send + 'static) -> Self {
+        let (sender, receiver) = tokio::sync::mpsc::channel(CHANNEL_CAPACITY);
+
+        tokio::task::spawn_blocking(move || pump(reader, sender));
+
+        Self {
+            receiver,
+            current: Vec::new(),
+            position: 0,
+        }
+    }
+
+    // Same, the reader is opened by the reading task before it starts
+    // Errors while opening are returned instead, nothing was sent yet so they can still get their own response
+    // (None if it panicked)
+    pub async fn open<R, E>(
+        open: impl FnOnce() -> Result<R, E> + Send + 'static,
+    ) -> Result<Self, Option<E>>
+    where
+        R: std::io::Read + Send + 'static,
+        E: Send + 'static,
+    {
+        let (sender, receiver) = tokio::sync::mpsc::channel(CHANNEL_CAPACITY);
+        let (opened_sender, opened) = tokio::sync::oneshot::channel();
+
+        tokio::task::spawn_blocking(move || match open() {
+            Ok(reader) => {
+                let _ = opened_sender.send(Ok(()));
+                pump(reader, sender)
+            }
+            Err(e) => {
+                let _ = opened_sender.send(Err(e));
+            }
+        });
+
+        match opened.await {
+            Ok(Ok(())) => Ok(Self {
+                receiver,
+                current: Vec::new(),
+                position: 0,
+            }),
+            Ok(Err(e)) => Err(Some(e)),
+            Err(_) => Err(None),
+        }
+    }
+}
+
+// Sends the reader's content in chunks until it's done, fails or the receiver is dropped
+fn pump(
+    mut reader: impl std::io::Read,
+    sender: tokio::sync::mpsc::Sender<std::io::Result<Vec<u8>>>,
+) {
+    use std::io::ErrorKind;
+
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+
+        let chunk = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => {
+                chunk.truncate(read);
+                Ok(chunk)
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+
+        let failed = chunk.is_err();
+
+        // Waits for the client when it's full, errors once it's gone
+        if sender.blocking_send(chunk).is_err() || failed {
+            break;
+        }
+    }
+}
+
+impl tokio::io::AsyncRead for BlockingReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::task::Poll;
+
+        let this = &mut *self;
+
+        while this.position == this.current.len() {
+            match this.receiver.poll_recv(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    this.current = chunk;
+                    this.position = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                // Done
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let read = buf.remaining().min(this.current.len() - this.position);
+        buf.put_slice(&this.current[this.position..this.position + read]);
+        this.position += read;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::BlockingReader, rocket::tokio::io::AsyncReadExt as _};
+
+    #[rocket::async_test]
+    async fn test_blocking_reader() {
+        // Bigger than what the channel holds, so the reading thread has to wait
+        let data = (0..super::CHUNK_SIZE * super::CHANNEL_CAPACITY * 3)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+
+        let mut reader = BlockingReader::new(std::io::Cursor::new(data.clone()));
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+
+        assert_eq!(read, data);
+    }
+
+    #[rocket::async_test]
+    async fn test_blocking_reader_open() {
+        let mut reader =
+            BlockingReader::open(|| Ok::<_, ()>(std::io::Cursor::new(b"opened".to_vec())))
+                .await
+                .unwrap();
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"opened");
+
+        let opened = BlockingReader::open(|| Err::<std::io::Empty, _>("not found")).await;
+        assert!(matches!(opened, Err(Some("not found"))));
+
+        let opened =
+            BlockingReader::open(|| -> Result<std::io::Empty, ()> { panic!("opening") }).await;
+        assert!(matches!(opened, Err(None)));
+    }
+}
@@ -0,0 +1,45 @@
+// Process wide pool of byte buffers, used by the upload pipelines (see cache::stream_to_file and stream_to_chunks) so each upload doesn't allocate its own
+//
+// A buffer goes back to the pool when dropped, only MAX_POOLED are kept, extra ones are freed
+
+const MAX_POOLED: usize = 64;
+
+lazy_static! {
+    static ref POOL: std::sync::Mutex<Vec<Vec<u8>>> = std::sync::Mutex::new(Vec::new());
+}
+
+// A buffer of the given size, only zero filled where it grew, so it can hold what was read in it before
+pub fn get(size: usize) -> Buffer {
+    let mut buffer = POOL.lock().unwrap().pop().unwrap_or_default();
+
+    // Sizes can differ between readers, or after a config change
+    buffer.resize(size, 0);
+
+    Buffer(buffer)
+}
+
+pub struct Buffer(Vec<u8>);
+
+impl std::ops::Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let mut pool = POOL.lock().unwrap();
+
+        if pool.len() < MAX_POOLED {
+            pool.push(std::mem::take(&mut self.0));
+        }
+    }
+}
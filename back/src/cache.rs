@@ -1,8 +1,9 @@
 /*
-    Fs: 3 file types
+    Fs: 3 file types, all under the configured cache directory (see config.rs)
     - Data files:
-        Name is a uuid with no extension
-        Raw content of a file, compressed using zstd with COMPRESSION_LEVEL level
+        Name is the sha256 of the original content, with no extension
+        Raw content of a file, compressed using zstd with the configured compression level
+        then encrypted if a master key is configured, see encryption.rs
     - Meta files:
         The name is a uuid (not related to the data file) with .meta at the end
         Stores data about an uploaded file.
@@ -11,32 +12,67 @@
     - Duplicate file:
         On disk duplicate tracking storage.
         A serialized version of the duplicates::DuplicateMap struct.
+    - Dictionaries directory:
+        Trained zstd dictionaries, named <extension>-<version>.dict, see dictionaries.rs
+    - Quarantine directory:
+        Meta files the recovery pass could not make sense of, kept there for manual inspection
+    - Chunked storage (storage.storage_mode = "chunked"):
+        Manifest files, named <uuid>.manifest, take the place of the data file and list the entry's chunks in order
+        The chunks directory holds the chunks, each compressed on its own and named after the sha256 of its original bytes
+        The chunk file (chunks.json) is a serialized version of the chunks::ChunkMap struct, counting references to each chunk
 */
 
+mod chunking;
+mod chunks;
+mod dictionaries;
 mod duplicates;
+mod encryption;
 mod entry;
+mod eviction;
 mod fs;
+mod fsck;
+mod ids;
+mod manifest;
 mod metadata;
+mod migration;
+mod password;
+mod recompression;
+mod recovery;
 mod size;
+mod stats;
+mod storage_mode;
 mod upload_info;
 
+pub use chunks::ChunkMap;
+pub use dictionaries::train as train_dictionary;
 pub use duplicates::DuplicateMap;
+pub use encryption::{rotate as rotate_master_key, MasterKey};
 pub use entry::CacheEntry;
+pub use eviction::make_room;
+pub use fsck::{fsck, repair};
+pub use ids::{is_valid_alias, IdMap, SHORT_ID_LENGTH};
+pub use manifest::Manifest;
 pub use metadata::Metadata;
+pub use migration::migrate;
+pub use password::{hash as hash_password, verify as verify_password};
+pub use recompression::{recompress, run_periodically as run_recompression};
+pub use recovery::{collect_garbage, rebuild_index, recover};
 pub use size::Size;
-pub use upload_info::UploadInfo;
-
-const COMPRESSION_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL; // 3, 1..=22 (zstd)
+pub use stats::stats;
+pub use storage_mode::StorageMode;
+pub use upload_info::{UploadDetails, UploadInfo};
 
 pub type CacheEntryMap = dashmap::DashMap<uuid::Uuid, CacheEntry>;
 
-pub fn init_cache_list_from_cache_dir() -> Option<CacheEntryMap> {
+pub fn init_cache_list_from_cache_dir(
+    config: &crate::config::StorageConfig,
+) -> Option<CacheEntryMap> {
     use {
         std::{path::PathBuf, str::FromStr as _, sync::Arc},
         uuid::Uuid,
     };
 
-    let files = fs::read_cache_dir().ok()?;
+    let files = fs::read_cache_dir(config).ok()?;
 
     // The default one is bad
     let display_path = |path: PathBuf| -> String { path.display().to_string().replace("\\", "/") };
@@ -44,6 +80,12 @@ pub fn init_cache_list_from_cache_dir() -> Option<CacheEntryMap> {
     let inner = files
         .flatten()
         .flat_map(|entry| {
+            if entry.path() == fs::quarantine_dir(config) || entry.path() == fs::chunks_dir(config)
+            {
+                // Expected, see recovery.rs and stream_to_chunks
+                return None;
+            }
+
             let metadata = entry
                 .metadata()
                 .map_err(|e| {
@@ -95,77 +137,122 @@ pub fn init_cache_list_from_cache_dir() -> Option<CacheEntryMap> {
     Some(inner)
 }
 
-/// Takes an incomming data stream, compresses and stores it in a given 'data' file.
-/// Returns the file size before compression and the resulting file size
+// Timeouts come from the upload's timeout::Watchdog
+fn read_error(e: std::io::Error) -> crate::error::CacheError {
+    use crate::error::CacheError;
+
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => CacheError::Timeout { why: e },
+        _ => CacheError::Compression { why: e },
+    }
+}
+
+/// Takes an incomming data stream, compresses (using the given dictionary, if any), encrypts and stores it in a given 'data' file.
+/// Returns the file size before compression and the resulting file size,
+/// along with the hash of the original bytes, used for duplicate detection
+///
+/// Reading stays on the runtime, hashing, compressing and writing run on the blocking pool (using storage.compression_workers
+/// of zstd's own threads), with at most PIPELINE_DEPTH buffers between the two
 async fn stream_to_file(
+    config: &crate::config::StorageConfig,
     uuid: &uuid::Uuid,
-    mut original_data: rocket::data::DataStream<'_>,
+    mut original_data: impl rocket::tokio::io::AsyncRead + Unpin,
     data_file: &mut std::fs::File,
-) -> Result<Size, crate::error::CacheError> {
+    dictionary: Option<std::sync::Arc<Vec<u8>>>,
+) -> Result<(Size, String), crate::error::CacheError> {
     use {
         crate::error::CacheError,
-        rocket::data::{ByteUnit, ToByteUnit as _},
         rocket::tokio::io::AsyncReadExt as _,
+        sha2::{Digest as _, Sha256},
         std::io::Write as _,
         zstd::stream::Encoder,
     };
 
-    let mut encoder = Encoder::new(data_file, COMPRESSION_LEVEL)
-        .map_err(|e| CacheError::Compression { why: e })?;
+    const PIPELINE_DEPTH: usize = 4;
 
-    // I am not too happy with that allocation, but since we're in an async context
-    // (and i don't really know how async task works, so idk if thread local is usable here), we don't have much choice
-    // The other way could be to make it on the stack, but if we do that, we would be very limited in size
-    // since tokio's threads don't have a big stack size
-    const BUFFER_SIZE: usize = 500_000; // 500kb
-    #[rustfmt::skip]
-    let mut buffer = vec![0; BUFFER_SIZE];
-
-    let byte_size_limit: ByteUnit = unsafe {
-        // SAFETY:
-        //     This static is ONLY EVER mutated at the program's init, before the webserer is even running
-        crate::FILE_REQ_SIZE_LIMIT.bytes()
-    };
+    let data_file = data_file.try_clone().map_err(|e| CacheError::FileOpen {
+        file: format!("(data file for uuid ({uuid})"),
+        why: e,
+    })?;
 
-    let mut total_read = 0;
-    loop {
-        let read = original_data
-            .read(&mut buffer)
-            .await
-            .map_err(|e| CacheError::Compression { why: e })?;
-
-        if read == 0 {
-            break;
+    let (sender, mut receiver) =
+        rocket::tokio::sync::mpsc::channel::<crate::buffers::Buffer>(PIPELINE_DEPTH);
+
+    let compression_level = config.compression_level;
+    let workers = config.compression_workers;
+    let master_key = config.master_key.clone();
+
+    // Returns the compressed file's size and the hash
+    let compression = rocket::tokio::task::spawn_blocking(move || {
+        let data_file = encryption::Writer::new(data_file, master_key.as_ref())?;
+
+        let mut encoder = match dictionary.as_deref() {
+            Some(dictionary) => Encoder::with_dictionary(data_file, compression_level, dictionary),
+            None => Encoder::new(data_file, compression_level),
+        }?;
+
+        if workers != 0 {
+            encoder.multithread(workers)?;
+        }
+
+        // Hashing the original bytes here saves reading the whole file again once it's written,
+        // and makes the hash independent of the compression settings
+        let mut hasher = Sha256::default();
+
+        while let Some(buffer) = receiver.blocking_recv() {
+            hasher.update(&*buffer);
+            encoder.write_all(&buffer)?;
         }
 
-        total_read += read;
+        let data_file = encoder.finish().and_then(encryption::Writer::finish)?;
+
+        // This is a bit ugly, but since `decoder.finish()` also writes things to the file
+        // using the total written bytes count as 'total file size' yields incorrect results.
+        // Since I don't know how to predict how many more bytes are written in that `encoder.finish()`
+        // I directly use the file metadata.
+        let file_size = data_file.metadata()?.len();
+
+        Ok::<_, std::io::Error>((file_size, format!("{:x}", hasher.finalize())))
+    });
+
+    let mut total_read = 0;
+    let read_result = async {
+        loop {
+            let mut buffer = crate::buffers::get(config.buffer_size());
+
+            let read = original_data.read(&mut buffer).await.map_err(read_error)?;
 
-        encoder
-            .write_all(&buffer[..read])
-            .map_err(|e| CacheError::Compression { why: e })?;
+            if read == 0 {
+                return Ok(());
+            }
+
+            total_read += read;
+
+            if total_read > config.file_limit {
+                error!("Max size reached");
+                return Err(CacheError::FileSizeExceeded {
+                    limit: config.file_limit,
+                });
+            }
+
+            buffer.truncate(read);
 
-        if total_read > byte_size_limit {
-            error!("Max size reached");
-            return Err(CacheError::FileSizeExceeded);
+            // Compression failed, its error is the one returned below
+            if sender.send(buffer).await.is_err() {
+                return Ok(());
+            }
         }
     }
+    .await;
 
-    let data_file = encoder
-        .finish()
-        .map_err(|e| CacheError::Compression { why: e })?;
+    // Lets the compression end, even when reading failed, so the caller's cleanup doesn't race with it
+    drop(sender);
+    let compression_result = compression
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)));
 
-    // This is a bit ugly, but since `decoder.finish()` also writes things to the file
-    // using the total written bytes count as 'total file size' yields incorrect results.
-    // Since I don't know how to predict how many more bytes are written in that `encoder.finish()`
-    // I directly use the file metadata.
-    let file_size = {
-        let metadata = data_file.metadata().map_err(|e| CacheError::FileRead {
-            file: format!("(data file for uuid ({uuid})"),
-            why: e,
-        })?;
-
-        metadata.len()
-    };
+    read_result?;
+    let (file_size, hash) = compression_result.map_err(|e| CacheError::Compression { why: e })?;
 
     debug!(
         "totals:\nRead: {}\nWrote: {}\nDelta: {}%",
@@ -181,5 +268,218 @@ async fn stream_to_file(
         }
     );
 
-    Ok(Size::new(total_read as u64, file_size))
+    Ok((Size::new(total_read as u64, file_size), hash))
+}
+
+/// Takes an incomming data stream, splits it in content-defined chunks and stores the ones that are not already stored.
+/// Writes the list of chunks in the given manifest file.
+/// Returns the size before compression and the total compressed size of the chunks
+///
+/// Like stream_to_file, reading and splitting stay on the runtime, hashing, compressing and writing the chunks run on the
+/// blocking pool, with at most PIPELINE_DEPTH chunks between the two
+async fn stream_to_chunks(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+    mut original_data: impl rocket::tokio::io::AsyncRead + Unpin,
+    manifest_file: &mut std::fs::File,
+    chunk_map: &std::sync::Arc<rocket::tokio::sync::Mutex<ChunkMap>>,
+) -> Result<Size, crate::error::CacheError> {
+    use {
+        crate::error::CacheError,
+        rocket::{serde::json::serde_json, tokio::io::AsyncReadExt as _},
+        sha2::{Digest as _, Sha256},
+    };
+
+    const PIPELINE_DEPTH: usize = 4;
+
+    let chunks_dir = fs::chunks_dir(config);
+    std::fs::create_dir_all(&chunks_dir).map_err(|e| CacheError::DirCreate {
+        dir: chunks_dir.display().to_string(),
+        why: e,
+    })?;
+
+    let (sender, mut receiver) =
+        rocket::tokio::sync::mpsc::channel::<crate::buffers::Buffer>(PIPELINE_DEPTH);
+
+    // Returns the manifest and compressed size of what was stored, even when it failed, so the caller can give it back
+    let storing = rocket::tokio::task::spawn_blocking({
+        let config = config.clone();
+        let uuid = *uuid;
+        let chunk_map = std::sync::Arc::clone(chunk_map);
+
+        move || {
+            let mut manifest = Manifest::default();
+            let mut compressed_size = 0;
+
+            let mut store = || {
+                while let Some(chunk) = receiver.blocking_recv() {
+                    let hash = format!("{:x}", Sha256::digest(&*chunk));
+
+                    compressed_size += store_chunk(
+                        &config,
+                        &uuid,
+                        manifest.chunks().len(),
+                        &hash,
+                        &chunk,
+                        &chunk_map,
+                    )?;
+                    manifest.push(hash, chunk.len() as u64);
+                }
+                Ok(())
+            };
+            let result = store();
+
+            (manifest, compressed_size, result)
+        }
+    });
+
+    let read_size = config.buffer_size();
+    let mut total_read = 0;
+
+    // Bytes read but not yet part of a chunk, never more than the max chunk size once the chunks are taken out
+    let mut pending = crate::buffers::get(chunking::MAX_SIZE + read_size);
+    let mut filled = 0;
+
+    let read_result: Result<(), CacheError> = async {
+        loop {
+            let read = original_data
+                .read(&mut pending[filled..filled + read_size])
+                .await
+                .map_err(read_error)?;
+            filled += read;
+
+            total_read += read;
+
+            if total_read > config.file_limit {
+                error!("Max size reached");
+                return Err(CacheError::FileSizeExceeded {
+                    limit: config.file_limit,
+                });
+            }
+
+            let eof = read == 0;
+
+            let mut consumed = 0;
+            while let Some(len) = chunking::next_chunk_len(&pending[consumed..filled], eof) {
+                let mut chunk = crate::buffers::get(len);
+                chunk.copy_from_slice(&pending[consumed..consumed + len]);
+
+                // Storing failed, its error is the one returned below
+                if sender.send(chunk).await.is_err() {
+                    return Ok(());
+                }
+
+                consumed += len;
+            }
+            pending.copy_within(consumed..filled, 0);
+            filled -= consumed;
+
+            if eof {
+                return Ok(());
+            }
+        }
+    }
+    .await;
+
+    // Lets the storing end, even when reading failed, so the chunks it took can be given back
+    drop(sender);
+    let (manifest, compressed_size, store_result) = storing.await.map_err(|e| {
+        // The chunks it took are only given back by the next boot's recovery
+        CacheError::Compression {
+            why: std::io::Error::other(e),
+        }
+    })?;
+
+    let result = read_result.and(store_result).and_then(|_| {
+        serde_json::to_writer(&mut *manifest_file, &manifest).map_err(|e| {
+            CacheError::Serialization {
+                context: String::from("writing manifest"),
+                why: e,
+            }
+        })
+    });
+
+    let mut chunk_map_guard = chunk_map.lock().await;
+
+    if let Err(e) = result {
+        // Give back what this upload took, the manifest itself is removed by the caller
+        chunks::release_all(
+            config,
+            &mut chunk_map_guard,
+            manifest.chunks().iter().map(|chunk| chunk.hash()),
+        );
+        return Err(e);
+    }
+
+    chunk_map_guard.write_to_file()?;
+
+    debug!(
+        "[{uuid}] Stored {} chunks, read: {total_read}, compressed: {compressed_size}",
+        manifest.chunks().len()
+    );
+
+    Ok(Size::new(total_read as u64, compressed_size))
+}
+
+// Stores a single chunk if it's not already stored, and takes a reference to it
+// Returns the compressed size of the chunk
+// Blocking, see stream_to_chunks
+fn store_chunk(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+    index: usize,
+    hash: &String,
+    chunk: &[u8],
+    chunk_map: &rocket::tokio::sync::Mutex<ChunkMap>,
+) -> Result<u64, crate::error::CacheError> {
+    use crate::error::CacheError;
+
+    {
+        let mut chunk_map_guard = chunk_map.blocking_lock();
+        if chunk_map_guard.acquire(hash) {
+            return Ok(chunk_map_guard.get(hash).unwrap().compressed()); // Cannot fail, it was just acquired
+        }
+    }
+
+    // Compress to a temporary file first, so a chunk file is never seen half written
+    let temp_path = fs::temp_chunk_path(config, uuid, index);
+    let compressed = zstd::encode_all(chunk, config.compression_level)
+        .and_then(|compressed| {
+            use std::io::Write as _;
+
+            let mut writer = encryption::Writer::new(
+                Vec::with_capacity(compressed.len()),
+                config.master_key.as_ref(),
+            )?;
+            writer.write_all(&compressed)?;
+            writer.finish()
+        })
+        .map_err(|e| CacheError::Compression { why: e })?;
+    std::fs::write(&temp_path, &compressed).map_err(|e| CacheError::FileWrite {
+        file: temp_path.display().to_string(),
+        why: e,
+    })?;
+
+    let mut chunk_map_guard = chunk_map.blocking_lock();
+
+    // Someone else could have stored it in the meantime
+    if chunk_map_guard.acquire(hash) {
+        if let Err(e) = std::fs::remove_file(&temp_path) {
+            error!(
+                "Failed to remove temporary chunk '{}' due to: {e}",
+                temp_path.display()
+            );
+        }
+        return Ok(chunk_map_guard.get(hash).unwrap().compressed());
+    }
+
+    let path = fs::chunk_path(config, hash);
+    std::fs::rename(&temp_path, &path).map_err(|e| CacheError::FileRename {
+        file: temp_path.display().to_string(),
+        why: e,
+    })?;
+
+    chunk_map_guard.insert(hash.clone(), compressed.len() as u64);
+
+    Ok(compressed.len() as u64)
 }
@@ -0,0 +1,154 @@
+// FastCDC style content-defined chunking
+// https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia
+//
+// Boundaries only depend on the bytes around them, so inserting or removing data in a file
+// only changes the chunks around the edit, the others are deduplicated
+
+pub const MIN_SIZE: usize = 16 * 1024;
+pub const AVG_SIZE: usize = 64 * 1024;
+pub const MAX_SIZE: usize = 256 * 1024;
+
+// Normalized chunking: harder to cut before the average size, easier after
+const MASK_S: u64 = top_bits_mask(AVG_SIZE.trailing_zeros() + 2);
+const MASK_L: u64 = top_bits_mask(AVG_SIZE.trailing_zeros() - 2);
+
+const fn top_bits_mask(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+// Changing this table changes every boundary, which would stop new uploads from deduplicating with the stored ones
+const GEAR: [u64; 256] = {
+    // splitmix64
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Returns the length of the first chunk of the given data,
+/// or None if more data is needed to find it (and `eof` is false)
+pub fn next_chunk_len(data: &[u8], eof: bool) -> Option<usize> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let len = cut(data);
+
+    // If the cut is at the end of the available data, it may not be a real boundary
+    if len < data.len() || len == MAX_SIZE || eof {
+        return Some(len);
+    }
+
+    None
+}
+
+fn cut(data: &[u8]) -> usize {
+    let len = data.len().min(MAX_SIZE);
+
+    if len <= MIN_SIZE {
+        return len;
+    }
+
+    let normal = AVG_SIZE.min(len);
+
+    let mut hash = 0u64;
+    let mut i = MIN_SIZE;
+
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_S == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+
+    while i < len {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_L == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_chunk_len, MAX_SIZE, MIN_SIZE};
+
+    fn random_bytes(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                // xorshift
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    // Feeds the data in small reads, like an upload would
+    fn chunk(data: &[u8]) -> Vec<&[u8]> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut available = 0;
+
+        loop {
+            available = (available + 10_000).min(data.len());
+            let eof = available == data.len();
+
+            while let Some(len) = next_chunk_len(&data[start..available], eof) {
+                chunks.push(&data[start..start + len]);
+                start += len;
+            }
+
+            if eof {
+                return chunks;
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_sizes() {
+        let data = random_bytes(5_000_000, 42);
+
+        let chunks = chunk(&data);
+
+        assert_eq!(chunks.concat(), data);
+
+        let (last, others) = chunks.split_last().unwrap();
+        assert!(last.len() <= MAX_SIZE);
+        for chunk in others {
+            assert!(chunk.len() > MIN_SIZE && chunk.len() <= MAX_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_chunk_insertion() {
+        let data = random_bytes(5_000_000, 42);
+
+        let mut edited = data.clone();
+        edited.splice(2_000_000..2_000_000, random_bytes(1000, 7));
+
+        let chunks = chunk(&data);
+        let edited_chunks = chunk(&edited);
+
+        let shared = edited_chunks
+            .iter()
+            .filter(|chunk| chunks.contains(chunk))
+            .count();
+
+        // Only the chunks around the insertion should differ
+        assert!(shared >= chunks.len() - 3, "{shared}/{}", chunks.len());
+    }
+}
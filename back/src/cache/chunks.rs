@@ -0,0 +1,182 @@
+// Reference counted chunk storage, used by the chunked storage mode
+// Chunks are compressed on their own and stored in cache/chunks/, named after the hash of their original bytes
+type Hash = String;
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChunkInfo {
+    // One per occurence in a manifest
+    refs: u32,
+    compressed: u64,
+}
+
+#[derive(Debug)]
+pub struct ChunkMap {
+    inner: std::collections::HashMap<Hash, ChunkInfo>,
+    // Where it's written, in the cache directory it was loaded from
+    path: std::path::PathBuf,
+}
+
+impl ChunkMap {
+    pub fn init_from_cache_dir(config: &crate::config::StorageConfig) -> Self {
+        use {rocket::serde::json::serde_json, std::fs::OpenOptions};
+
+        let path = super::fs::chunks_map_path(config);
+
+        let Ok(map_file) = OpenOptions::new().read(true).open(&path) else {
+            // Expected if the chunked mode was never used
+            return Self {
+                inner: Default::default(),
+                path,
+            };
+        };
+
+        let map = serde_json::from_reader(std::io::BufReader::new(map_file)).unwrap_or_else(|e| {
+            error!("Failed to parse the chunk map due to: {e}\nFalling back to default");
+            Default::default()
+        });
+
+        Self { inner: map, path }
+    }
+
+    // Unlike the duplicate map, this isn't written on every change, as an upload can add thousands of chunks
+    // A crash in between is fixed by the recovery pass, which recounts every reference from the manifests
+    // Written under a temp name then renamed, like metas, so a crash mid write never leaves it truncated
+    // Only one writer at a time, the caller holds the map's mutex
+    pub fn write_to_file(&self) -> Result<(), crate::error::CacheError> {
+        use {
+            crate::error::CacheError, rocket::serde::json::serde_json::to_string,
+            std::io::Write as _,
+        };
+
+        let path = &self.path;
+        let temp_path = path.with_extension("temp_json");
+
+        let json = to_string(&self.inner).map_err(|e| CacheError::Serialization {
+            context: "serializing chunk map".to_string(),
+            why: e,
+        })?;
+
+        let mut file = std::fs::File::create(&temp_path).map_err(|e| CacheError::FileCreate {
+            file: temp_path.display().to_string(),
+            why: e,
+        })?;
+
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| CacheError::FileWrite {
+                file: temp_path.display().to_string(),
+                why: e,
+            })?;
+
+        std::fs::rename(&temp_path, path).map_err(|e| CacheError::FileRename {
+            file: temp_path.display().to_string(),
+            why: e,
+        })
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&ChunkInfo> {
+        self.inner.get(hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash, &ChunkInfo)> {
+        self.inner.iter()
+    }
+
+    /// Adds a reference to a stored chunk, returns false if there is no such chunk
+    pub fn acquire(&mut self, hash: &Hash) -> bool {
+        let Some(info) = self.inner.get_mut(hash) else {
+            return false;
+        };
+
+        info.refs += 1;
+        true
+    }
+
+    /// Registers a newly stored chunk, with a single reference
+    pub fn insert(&mut self, hash: Hash, compressed: u64) {
+        self.inner.insert(
+            hash,
+            ChunkInfo {
+                refs: 1,
+                compressed,
+            },
+        );
+    }
+
+    /// Removes a reference, returns true if it was the last one, meaning that the chunk file should be removed
+    pub fn release(&mut self, hash: &Hash) -> bool {
+        let Some(info) = self.inner.get_mut(hash) else {
+            warn!("Tried to release unknown chunk {hash}");
+            return false;
+        };
+
+        info.refs = info.refs.saturating_sub(1);
+
+        if info.refs != 0 {
+            return false;
+        }
+
+        self.inner.remove(hash);
+        true
+    }
+
+    // Replaces the map content with what's actually on disk
+    // Returns the amount of chunks whose entry was wrong
+    pub fn reconcile(
+        &mut self,
+        expected: std::collections::HashMap<Hash, ChunkInfo>,
+    ) -> Result<usize, crate::error::CacheError> {
+        let fixes = expected
+            .iter()
+            .filter(|(hash, info)| self.inner.get(*hash) != Some(info))
+            .count()
+            + self
+                .inner
+                .keys()
+                .filter(|hash| !expected.contains_key(*hash))
+                .count();
+
+        if fixes == 0 {
+            return Ok(0);
+        }
+
+        self.inner = expected;
+        self.write_to_file().map(|_| fixes)
+    }
+}
+
+impl ChunkInfo {
+    pub fn new(refs: u32, compressed: u64) -> Self {
+        Self { refs, compressed }
+    }
+
+    pub fn refs(&self) -> u32 {
+        self.refs
+    }
+
+    pub fn compressed(&self) -> u64 {
+        self.compressed
+    }
+}
+
+/// Releases the given chunks and removes the ones that are no longer used
+pub fn release_all<'h>(
+    config: &crate::config::StorageConfig,
+    chunk_map: &mut ChunkMap,
+    hashes: impl IntoIterator<Item = &'h Hash>,
+) {
+    for hash in hashes {
+        if !chunk_map.release(hash) {
+            continue;
+        }
+
+        let path = super::fs::chunk_path(config, hash);
+        if let Err(e) = std::fs::remove_file(&path) {
+            error!("Could not remove chunk '{}' due to: {e}", path.display());
+        }
+    }
+
+    if let Err(e) = chunk_map.write_to_file() {
+        error!("Could not save the chunk map due to: {e}");
+    }
+}
@@ -0,0 +1,338 @@
+// Trained zstd dictionaries, small uploads (a few KB of json, logs, etc..) compress poorly without one
+// Stored in cache/dictionaries/, one file per extension and version, named <extension>-<version>.dict
+//
+// New uploads use the latest version for their extension, older versions are never removed
+// as the entries compressed with them still need them to be read back
+
+// Samples bigger than this don't help much, and would make the training really slow
+const MAX_SAMPLE_SIZE: u64 = 128 * 1024;
+
+// Same as the zstd cli
+const DEFAULT_MAX_SIZE: usize = 110 * 1024;
+
+lazy_static! {
+    // Dictionaries are never modified once written, so they can be kept around
+    // Keyed by path, every instance has its own cache directory
+    static ref LOADED: dashmap::DashMap<std::path::PathBuf, std::sync::Arc<Vec<u8>>> = Default::default();
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct TrainReport {
+    pub id: String,
+    pub samples: usize,
+    pub size: usize,
+}
+
+impl std::fmt::Display for TrainReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Trained dictionary '{}' ({}) from {} samples",
+            self.id,
+            rocket::data::ByteUnit::Byte(self.size as u64),
+            self.samples
+        )
+    }
+}
+
+fn parse_id(id: &str) -> Option<(&str, u32)> {
+    let (extension, version) = id.rsplit_once('-')?;
+    Some((extension, version.parse().ok()?))
+}
+
+// Every stored dictionary id, with its extension and version
+fn list(config: &crate::config::StorageConfig) -> Vec<(String, String, u32)> {
+    let Ok(dir) = std::fs::read_dir(super::fs::dictionaries_dir(config)) else {
+        // No dictionary was trained yet
+        return Vec::new();
+    };
+
+    dir.flatten()
+        .flat_map(|entry| {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("dict") {
+                return None;
+            }
+            let id = path.file_stem()?.to_str()?;
+            let (extension, version) = parse_id(id)?;
+            Some((id.to_string(), extension.to_string(), version))
+        })
+        .collect()
+}
+
+/// The id of the dictionary new uploads with the given extension should use, if any
+pub fn latest(config: &crate::config::StorageConfig, extension: &str) -> Option<String> {
+    if extension.is_empty() {
+        return None;
+    }
+
+    list(config)
+        .into_iter()
+        .filter(|(_, ext, _)| ext == extension)
+        .max_by_key(|(_, _, version)| *version)
+        .map(|(id, _, _)| id)
+}
+
+pub fn load(
+    config: &crate::config::StorageConfig,
+    id: &str,
+) -> Result<std::sync::Arc<Vec<u8>>, crate::error::CacheError> {
+    use crate::error::CacheError;
+
+    let path = super::fs::dictionary_path(config, id);
+
+    if let Some(dictionary) = LOADED.get(&path) {
+        return Ok(dictionary.clone());
+    }
+
+    let dictionary =
+        std::sync::Arc::new(std::fs::read(&path).map_err(|e| CacheError::FileRead {
+            file: path.display().to_string(),
+            why: e,
+        })?);
+
+    LOADED.insert(path, dictionary.clone());
+
+    Ok(dictionary)
+}
+
+/// Finds which dictionary the given compressed file was made with, using the dictionary id zstd writes in the frame header
+/// Used when an upload turns out to be a duplicate, as the stored file may have been made with another version
+pub fn used_by(
+    config: &crate::config::StorageConfig,
+    data_path: &std::path::Path,
+) -> Result<Option<String>, crate::error::CacheError> {
+    use {
+        crate::error::CacheError,
+        std::io::Read as _,
+        zstd::zstd_safe::{get_dict_id_from_dict, get_dict_id_from_frame},
+    };
+
+    // A frame header is at most 18 bytes, after the encryption's header if there is one
+    let mut header = Vec::with_capacity(18);
+    std::fs::File::open(data_path)
+        .and_then(|file| super::encryption::reader(file, config.master_key.as_ref()))
+        .and_then(|file| file.take(18).read_to_end(&mut header))
+        .map_err(|e| CacheError::FileRead {
+            file: data_path.display().to_string(),
+            why: e,
+        })?;
+
+    let Some(frame_dict_id) = get_dict_id_from_frame(&header) else {
+        return Ok(None);
+    };
+
+    for (id, _, _) in list(config) {
+        if get_dict_id_from_dict(&load(config, &id)?) == Some(frame_dict_id) {
+            return Ok(Some(id));
+        }
+    }
+
+    warn!(
+        "'{}' was compressed with an unknown dictionary ({frame_dict_id})",
+        data_path.display()
+    );
+
+    Ok(None)
+}
+
+pub fn decoder<R: std::io::Read>(
+    config: &crate::config::StorageConfig,
+    reader: R,
+    dictionary: Option<&str>,
+) -> Result<zstd::stream::Decoder<'static, std::io::BufReader<R>>, crate::error::CacheError> {
+    use crate::error::CacheError;
+
+    let decoder = match dictionary {
+        Some(id) => zstd::stream::Decoder::with_dictionary(
+            std::io::BufReader::new(reader),
+            &load(config, id)?,
+        ),
+        None => zstd::stream::Decoder::new(reader),
+    };
+
+    decoder.map_err(|e| CacheError::Compression { why: e })
+}
+
+/// Trains a new version of the dictionary of the given extension, from the small stored uploads that have it
+pub fn train(
+    config: &crate::config::StorageConfig,
+    extension: &str,
+    max_size: Option<usize>,
+) -> Result<TrainReport, crate::error::CacheError> {
+    use {crate::error::CacheError, std::io::Read as _};
+
+    let scan = super::recovery::scan(config)?;
+
+    let mut data_files = std::collections::HashSet::new();
+    let mut samples = Vec::new();
+
+    for (uuid, meta) in scan.metas.iter() {
+        if meta.extension() != extension
+            || meta.storage() != super::StorageMode::Whole
+            || meta.size().original() > MAX_SAMPLE_SIZE
+            // Random looking bytes, nothing to learn there
+            || meta.client_encrypted()
+            // Duplicates would skew the training
+            || !data_files.insert(meta.data_file_name())
+        {
+            continue;
+        }
+
+        let path = super::fs::data_path(config, meta.data_file_name());
+
+        let sample = std::fs::File::open(&path)
+            .and_then(|file| super::encryption::reader(file, config.master_key.as_ref()))
+            .map_err(|e| CacheError::FileOpen {
+                file: path.display().to_string(),
+                why: e,
+            })
+            .and_then(|file| decoder(config, file, meta.dictionary()))
+            .and_then(|mut decoder| {
+                let mut sample = Vec::new();
+                decoder
+                    .read_to_end(&mut sample)
+                    .map_err(|e| CacheError::FileRead {
+                        file: path.display().to_string(),
+                        why: e,
+                    })?;
+                Ok(sample)
+            });
+
+        match sample {
+            Ok(sample) => samples.push(sample),
+            Err(e) => warn!("[{uuid}] Skipping sample due to: {e}"),
+        }
+    }
+
+    let dictionary = zstd::dict::from_samples(&samples, max_size.unwrap_or(DEFAULT_MAX_SIZE))
+        .map_err(|e| CacheError::DictionaryTraining {
+            extension: extension.to_string(),
+            why: e,
+        })?;
+
+    let version = list(config)
+        .into_iter()
+        .filter(|(_, ext, _)| ext == extension)
+        .map(|(_, _, version)| version)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let id = format!("{extension}-{version}");
+
+    let dir = super::fs::dictionaries_dir(config);
+    std::fs::create_dir_all(&dir).map_err(|e| CacheError::DirCreate {
+        dir: dir.display().to_string(),
+        why: e,
+    })?;
+
+    // Written under a temp name, a half written dictionary would be picked up by new uploads
+    let path = super::fs::dictionary_path(config, &id);
+    let temp_path = path.with_extension("temp_dict");
+    std::fs::write(&temp_path, &dictionary).map_err(|e| CacheError::FileWrite {
+        file: temp_path.display().to_string(),
+        why: e,
+    })?;
+    std::fs::rename(&temp_path, &path).map_err(|e| CacheError::FileRename {
+        file: temp_path.display().to_string(),
+        why: e,
+    })?;
+
+    Ok(TrainReport {
+        id,
+        samples: samples.len(),
+        size: dictionary.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::cache::fs,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    async fn upload(client: &Client, name: &str, content: &str) -> uuid::Uuid {
+        let response = client
+            .put(format!("/{name}"))
+            .body(content)
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        uuid::Uuid::parse_str(&response.into_string().await.unwrap()).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn test_dictionaries() {
+        let client = Client::tracked(
+            crate::build_test_rocket_from(
+                rocket::Config::figment()
+                    .merge(("rate_limit.upload.requests_per_minute", 0))
+                    .merge(("hot_cache.capacity", 0)),
+            )
+            .await,
+        )
+        .await
+        .expect("valid rocket instance");
+
+        let config = client
+            .rocket()
+            .state::<crate::config::StorageConfig>()
+            .unwrap();
+
+        for i in 0..100 {
+            let sample = format!(
+                r#"{{"id":{i},"name":"sample {i}","tags":["storage","server","{}"],"size":{}}}"#,
+                i * 7,
+                i * 1024
+            );
+            upload(&client, &format!("sample_{i}.json"), &sample).await;
+        }
+        assert_eq!(super::latest(config, "json"), None);
+
+        let report = super::train(config, "json", Some(4096)).unwrap();
+        assert_eq!(report.id, "json-1");
+        assert_eq!(report.samples, 100);
+        assert_eq!(super::latest(config, "json").as_deref(), Some("json-1"));
+        assert_eq!(super::latest(config, "txt"), None);
+
+        // Only new uploads with that extension use it, and it can be found back from the data file
+        let content = r#"{"id":1000,"name":"with a dictionary","tags":["storage","server"]}"#;
+        let with = upload(&client, "with.json", content).await;
+        let without = upload(&client, "without.txt", "Some notes").await;
+
+        let meta = fs::read_meta(config, &with).unwrap();
+        assert_eq!(meta.dictionary(), Some("json-1"));
+        let data_path = fs::data_path(config, meta.data_file_name());
+        assert_eq!(
+            super::used_by(config, &data_path).unwrap().as_deref(),
+            Some("json-1")
+        );
+
+        let meta = fs::read_meta(config, &without).unwrap();
+        assert_eq!(meta.dictionary(), None);
+        let data_path = fs::data_path(config, meta.data_file_name());
+        assert_eq!(super::used_by(config, &data_path).unwrap(), None);
+
+        // The next version takes over, entries made with the previous one still read back
+        assert_eq!(
+            super::train(config, "json", Some(4096)).unwrap().id,
+            "json-2"
+        );
+        assert_eq!(super::latest(config, "json").as_deref(), Some("json-2"));
+
+        for (uuid, content) in [(with, content), (without, "Some notes")] {
+            let response = client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_string().await.unwrap(), content);
+        }
+    }
+}
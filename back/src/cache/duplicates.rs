@@ -1,29 +1,67 @@
 // While this map doesn't help in reducing storage time, it helps with taking less space on disk
 type Hash = String;
 
-#[derive(Debug, Default)]
+// 1: Hashes of the compressed data files, stored as a bare map
+// 2: Hashes of the original bytes
+const VERSION: u32 = 2;
+
+#[derive(Debug)]
 pub struct DuplicateMap {
     // hashbrown could be used here
     inner: std::collections::HashMap<Hash, Vec<uuid::Uuid>>,
+    // 0 if the map was not found on disk, so we can't tell what's in the cache directory
+    version: u32,
+    // Where it's written, in the cache directory it was loaded from
+    path: std::path::PathBuf,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum OnDisk {
+    Versioned {
+        version: u32,
+        map: std::collections::HashMap<Hash, Vec<uuid::Uuid>>,
+    },
+    Legacy(std::collections::HashMap<Hash, Vec<uuid::Uuid>>),
+}
+
+// Same as OnDisk::Versioned, without having to clone the map on every write
+#[derive(serde::Serialize)]
+struct OnDiskRef<'a> {
+    version: u32,
+    map: &'a std::collections::HashMap<Hash, Vec<uuid::Uuid>>,
 }
 
 impl DuplicateMap {
-    pub fn init_from_cache_dir() -> Self {
+    pub fn init_from_cache_dir(config: &crate::config::StorageConfig) -> Self {
         use {rocket::serde::json::serde_json, std::fs::OpenOptions};
 
-        let Ok(map_file) = OpenOptions::new()
-            .read(true)
-            .open(super::fs::duplicates_path())
-            .map_err(|e| {
-                error!("Failed to load duplicate map from fs due to: {e}\nFalling back to default")
-            })
-        else {
-            return Default::default();
+        let path = super::fs::duplicates_path(config);
+
+        let Ok(map_file) = OpenOptions::new().read(true).open(&path).map_err(|e| {
+            error!("Failed to load duplicate map from fs due to: {e}\nFalling back to default")
+        }) else {
+            return Self {
+                inner: Default::default(),
+                version: 0,
+                path,
+            };
         };
 
-        let map = serde_json::from_reader(map_file).unwrap_or_default();
+        let (inner, version) = match serde_json::from_reader(map_file) {
+            Ok(OnDisk::Versioned { version, map }) => (map, version),
+            Ok(OnDisk::Legacy(map)) => (map, 1),
+            Err(e) => {
+                error!("Failed to parse duplicate map due to: {e}\nFalling back to default");
+                (Default::default(), 0)
+            }
+        };
 
-        Self { inner: map }
+        Self {
+            inner,
+            version,
+            path,
+        }
     }
 
     pub fn write_to_file(&self) -> Result<(), crate::error::CacheError> {
@@ -33,19 +71,24 @@ impl DuplicateMap {
             std::{fs::OpenOptions, io::Write as _},
         };
 
-        let path = super::fs::duplicates_path();
+        let path = &self.path;
 
         let mut file = OpenOptions::new()
             .create(true) // In case it does not yet exist
             .write(true)
             .truncate(true) // Rewrite all
-            .open(path.clone())
+            .open(path)
             .map_err(|e| CacheError::FileOpen {
                 file: path.display().to_string(),
                 why: e,
             })?;
 
-        let json = to_string(&self.inner).map_err(|e| CacheError::Serialization {
+        let on_disk = OnDiskRef {
+            version: self.version,
+            map: &self.inner,
+        };
+
+        let json = to_string(&on_disk).map_err(|e| CacheError::Serialization {
             context: "serializing duplicate map".to_string(),
             why: e,
         })?;
@@ -59,9 +102,21 @@ impl DuplicateMap {
         Ok(())
     }
 
+    /// The keys are not hashes of the original bytes, see migration.rs
+    pub fn is_outdated(&self) -> bool {
+        self.version < VERSION
+    }
+
+    pub fn set_up_to_date(&mut self) {
+        self.version = VERSION;
+    }
+
     pub fn get(&self, hash: &Hash) -> Option<&[uuid::Uuid]> {
         self.inner.get(hash).map(|v| v.as_slice())
     }
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash, &[uuid::Uuid])> {
+        self.inner.iter().map(|(hash, v)| (hash, v.as_slice()))
+    }
     pub fn add(&mut self, hash: Hash, uuid: uuid::Uuid) -> Result<(), crate::error::CacheError> {
         self.inner.entry(hash).or_default().push(uuid);
         self.write_to_file()
@@ -95,17 +150,50 @@ impl DuplicateMap {
 
         self.write_to_file().map(|_| out)
     }
+
+    // Replaces the map content with the given (hash, uuid) pairs, which are what's actually on disk
+    // Returns the amount of pairs that were either missing or shouldn't have been there
+    pub fn reconcile(
+        &mut self,
+        expected: impl IntoIterator<Item = (Hash, uuid::Uuid)>,
+    ) -> Result<usize, crate::error::CacheError> {
+        use std::collections::HashMap;
+
+        let mut rebuilt = HashMap::<Hash, Vec<uuid::Uuid>>::new();
+        for (hash, uuid) in expected {
+            rebuilt.entry(hash).or_default().push(uuid);
+        }
+
+        let count_missing = |from: &HashMap<Hash, Vec<uuid::Uuid>>,
+                             into: &HashMap<Hash, Vec<uuid::Uuid>>| {
+            from.iter()
+                .flat_map(|(hash, uuids)| uuids.iter().map(move |uuid| (hash, uuid)))
+                .filter(|(hash, uuid)| !into.get(*hash).is_some_and(|uuids| uuids.contains(uuid)))
+                .count()
+        };
+
+        let fixes = count_missing(&rebuilt, &self.inner) + count_missing(&self.inner, &rebuilt);
+
+        if fixes == 0 {
+            return Ok(0);
+        }
+
+        self.inner = rebuilt;
+        self.write_to_file().map(|_| fixes)
+    }
 }
 
 /// This function moves or remove the data file depending on if it already exists
-// Can't do it in store_data since we only have access to the original bytes
-// which is pre compression, so much more than if we read after
+/// Returns true if it already existed
+// The hash is the one of the original bytes, computed while streaming them to the data file
 // FIXME: This is dirty and REALLY ugly
 pub async fn handle_duplicates(
+    config: &crate::config::StorageConfig,
     data_file_path: &mut std::path::PathBuf,
     uuid: &uuid::Uuid,
+    hash: Hash,
     duplicate_map: &std::sync::Arc<rocket::tokio::sync::Mutex<DuplicateMap>>,
-) -> Result<(), crate::error::CacheError> {
+) -> Result<bool, crate::error::CacheError> {
     use {
         crate::error::CacheError,
         rocket::tokio::{
@@ -114,9 +202,7 @@ pub async fn handle_duplicates(
         },
         std::sync::LazyLock,
     };
-    let (r, duration) = time::timeit_async(async || hash_file(data_file_path.clone()).await).await;
-    debug!("Hash: {:?} in {}", r, time::format(duration, -1));
-    let hash = r?;
+    debug!("[{uuid}] Hash: {hash}");
 
     // Quick and dirty way to avoid all possibilities of data races on file moves
     static MUTEX: LazyLock<Mutex<()>> = LazyLock::new(Mutex::default);
@@ -141,8 +227,7 @@ pub async fn handle_duplicates(
         is_dup.unwrap_or(false)
     };
 
-    // let new_dp = fs::CACHE_DIRECTORY.join(hash.clone());
-    let new_data_file_path = super::fs::data_path(&hash);
+    let new_data_file_path = super::fs::data_path(config, &hash);
 
     if is_duplicate {
         remove_file(data_file_path.clone())
@@ -164,32 +249,5 @@ pub async fn handle_duplicates(
 
     drop(guard);
 
-    Ok(())
-}
-
-async fn hash_file<P: AsRef<std::path::Path>>(path: P) -> Result<String, crate::error::CacheError> {
-    use {
-        crate::error::CacheError,
-        rocket::tokio::{fs::File, io::AsyncReadExt as _},
-        sha2::{Digest as _, Sha256},
-    };
-
-    let path = path.as_ref();
-
-    let mut file = File::open(path).await.map_err(|e| CacheError::FileOpen {
-        file: path.display().to_string(),
-        why: e,
-    })?;
-
-    let mut hasher = Sha256::default();
-    let mut buffer = [0; 8192];
-
-    while let Ok(bytes_read) = file.read(&mut buffer).await {
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
-    }
-
-    Ok(format!("{:x}", hasher.finalize()))
+    Ok(is_duplicate)
 }
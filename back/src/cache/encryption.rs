@@ -0,0 +1,593 @@
+// Encryption at rest of the data files and chunks
+//
+// Each file gets its own random key, used to encrypt the compressed stream with XChaCha20-Poly1305 in STREAM mode:
+// segments of SEGMENT_SIZE bytes, each one authenticated, the last one being marked as such so a truncated file is detected.
+// The file key is stored in the file's header, wrapped (encrypted) by the master key from the config,
+// so rotating the master key only rewrites the headers.
+//
+// Files without that header are read as is, so files stored before this, or without master key, keep working
+
+const MAGIC: [u8; 4] = *b"SSE1"; // Can't be mistaken for a zstd frame (28 B5 2F FD)
+const SEGMENT_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+
+const KEY_ID_SIZE: usize = 8;
+const WRAP_NONCE_SIZE: usize = 24;
+const WRAPPED_KEY_SIZE: usize = 32 + TAG_SIZE;
+const STREAM_NONCE_SIZE: usize = 19; // 24 bytes minus the segment counter and the last flag
+
+const HEADER_SIZE: usize =
+    MAGIC.len() + KEY_ID_SIZE + WRAP_NONCE_SIZE + WRAPPED_KEY_SIZE + STREAM_NONCE_SIZE;
+
+#[derive(Clone)]
+pub struct MasterKey {
+    key: [u8; 32],
+}
+
+// Keeps the key out of the logs
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MasterKey({:02x?})", self.id())
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct Config {
+    // Hex encoded
+    key: Option<String>,
+    // Raw or hex encoded
+    key_file: Option<std::path::PathBuf>,
+}
+
+impl MasterKey {
+    /// Reads the `encryption` table of the config, returns None if no key is configured
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Option<Self>, crate::error::CacheError> {
+        use crate::error::CacheError;
+
+        let config = match figment.find_value("encryption") {
+            Ok(value) => value
+                .deserialize::<Config>()
+                .map_err(|e| CacheError::MasterKey { why: e.to_string() })?,
+            Err(_) => Config::default(),
+        };
+
+        match config {
+            Config {
+                key: Some(_),
+                key_file: Some(_),
+            } => Err(CacheError::MasterKey {
+                why: String::from("both encryption.key and encryption.key_file are set"),
+            }),
+            Config { key: Some(key), .. } => Self::parse(key.as_bytes()).map(Some),
+            Config {
+                key_file: Some(path),
+                ..
+            } => Self::from_file(&path).map(Some),
+            Config { .. } => Ok(None),
+        }
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<Self, crate::error::CacheError> {
+        use crate::error::CacheError;
+
+        let content = std::fs::read(path).map_err(|e| CacheError::FileRead {
+            file: path.display().to_string(),
+            why: e,
+        })?;
+
+        Self::parse(&content)
+    }
+
+    // 32 raw bytes, or 64 hex characters
+    fn parse(content: &[u8]) -> Result<Self, crate::error::CacheError> {
+        use crate::error::CacheError;
+
+        let mut key = [0; 32];
+
+        if content.len() == key.len() {
+            key.copy_from_slice(content);
+            return Ok(Self { key });
+        }
+
+        let hex = content.trim_ascii();
+        if hex.len() != key.len() * 2 {
+            return Err(CacheError::MasterKey {
+                why: format!(
+                    "expected 32 bytes or 64 hex characters, got {} bytes",
+                    hex.len()
+                ),
+            });
+        }
+
+        for (byte, pair) in key.iter_mut().zip(hex.chunks(2)) {
+            *byte = std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| CacheError::MasterKey {
+                    why: String::from("invalid hex character"),
+                })?;
+        }
+
+        Ok(Self { key })
+    }
+
+    // Tells which master key wrapped a file key, without having to try them
+    fn id(&self) -> [u8; KEY_ID_SIZE] {
+        use sha2::{Digest as _, Sha256};
+
+        let mut hasher = Sha256::default();
+        hasher.update(b"storage_server master key id");
+        hasher.update(self.key);
+
+        let mut id = [0; KEY_ID_SIZE];
+        id.copy_from_slice(&hasher.finalize()[..KEY_ID_SIZE]);
+        id
+    }
+
+    fn cipher(&self) -> chacha20poly1305::XChaCha20Poly1305 {
+        use chacha20poly1305::KeyInit as _;
+
+        chacha20poly1305::XChaCha20Poly1305::new(&self.key.into())
+    }
+}
+
+fn aead_error(_: chacha20poly1305::aead::Error) -> std::io::Error {
+    // The error type is opaque on purpose
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "authentication failed, the file is corrupted or the key is wrong",
+    )
+}
+
+struct Header {
+    key_id: [u8; KEY_ID_SIZE],
+    wrap_nonce: [u8; WRAP_NONCE_SIZE],
+    wrapped_key: [u8; WRAPPED_KEY_SIZE],
+    stream_nonce: [u8; STREAM_NONCE_SIZE],
+}
+
+impl Header {
+    // Wraps the given file key with the master key
+    fn seal(
+        master_key: &MasterKey,
+        file_key: &[u8; 32],
+        stream_nonce: [u8; STREAM_NONCE_SIZE],
+    ) -> std::io::Result<Self> {
+        use {chacha20poly1305::aead::Aead as _, rand::RngCore as _};
+
+        let mut wrap_nonce = [0; WRAP_NONCE_SIZE];
+        rand::rngs::OsRng.fill_bytes(&mut wrap_nonce);
+
+        let wrapped = master_key
+            .cipher()
+            .encrypt(&wrap_nonce.into(), file_key.as_slice())
+            .map_err(aead_error)?;
+
+        let mut wrapped_key = [0; WRAPPED_KEY_SIZE];
+        wrapped_key.copy_from_slice(&wrapped);
+
+        Ok(Self {
+            key_id: master_key.id(),
+            wrap_nonce,
+            wrapped_key,
+            stream_nonce,
+        })
+    }
+
+    fn unwrap_key(&self, master_key: &MasterKey) -> std::io::Result<[u8; 32]> {
+        use chacha20poly1305::aead::Aead as _;
+
+        if self.key_id != master_key.id() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "the file key was wrapped by an other master key ({:02x?})",
+                    self.key_id
+                ),
+            ));
+        }
+
+        let unwrapped = master_key
+            .cipher()
+            .decrypt(&self.wrap_nonce.into(), self.wrapped_key.as_slice())
+            .map_err(aead_error)?;
+
+        let mut file_key = [0; 32];
+        file_key.copy_from_slice(&unwrapped);
+        Ok(file_key)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.key_id);
+        bytes.extend_from_slice(&self.wrap_nonce);
+        bytes.extend_from_slice(&self.wrapped_key);
+        bytes.extend_from_slice(&self.stream_nonce);
+        bytes
+    }
+
+    // None if it's not an encrypted file
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.strip_prefix(&MAGIC)?;
+        if bytes.len() < HEADER_SIZE - MAGIC.len() {
+            return None;
+        }
+
+        let (key_id, bytes) = bytes.split_at(KEY_ID_SIZE);
+        let (wrap_nonce, bytes) = bytes.split_at(WRAP_NONCE_SIZE);
+        let (wrapped_key, bytes) = bytes.split_at(WRAPPED_KEY_SIZE);
+
+        Some(Self {
+            key_id: key_id.try_into().ok()?,
+            wrap_nonce: wrap_nonce.try_into().ok()?,
+            wrapped_key: wrapped_key.try_into().ok()?,
+            stream_nonce: bytes[..STREAM_NONCE_SIZE].try_into().ok()?,
+        })
+    }
+}
+
+/// Encrypts what's written to it if a master key is configured, passes it through otherwise
+pub enum Writer<W: std::io::Write> {
+    Plain(W),
+    Encrypted {
+        inner: W,
+        // Taken by the last segment
+        encryptor: Option<
+            chacha20poly1305::aead::stream::EncryptorBE32<chacha20poly1305::XChaCha20Poly1305>,
+        >,
+        buffer: Vec<u8>,
+    },
+}
+
+impl<W: std::io::Write> Writer<W> {
+    // The config's master key (see StorageConfig::master_key), None stores in clear
+    pub fn new(mut inner: W, master_key: Option<&MasterKey>) -> std::io::Result<Self> {
+        use {
+            chacha20poly1305::{aead::stream::EncryptorBE32, KeyInit as _, XChaCha20Poly1305},
+            rand::RngCore as _,
+        };
+
+        let Some(master_key) = master_key else {
+            return Ok(Self::Plain(inner));
+        };
+
+        let mut file_key = [0; 32];
+        let mut stream_nonce = [0; STREAM_NONCE_SIZE];
+        rand::rngs::OsRng.fill_bytes(&mut file_key);
+        rand::rngs::OsRng.fill_bytes(&mut stream_nonce);
+
+        inner.write_all(&Header::seal(master_key, &file_key, stream_nonce)?.to_bytes())?;
+
+        Ok(Self::Encrypted {
+            inner,
+            encryptor: Some(EncryptorBE32::from_aead(
+                XChaCha20Poly1305::new(&file_key.into()),
+                &stream_nonce.into(),
+            )),
+            buffer: Vec::with_capacity(SEGMENT_SIZE),
+        })
+    }
+
+    /// Writes the last segment, must be called or the file will be seen as truncated
+    pub fn finish(self) -> std::io::Result<W> {
+        match self {
+            Self::Plain(inner) => Ok(inner),
+            Self::Encrypted {
+                mut inner,
+                encryptor,
+                buffer,
+            } => {
+                let Some(encryptor) = encryptor else {
+                    return Err(std::io::Error::other("encryptor already finished"));
+                };
+                inner.write_all(
+                    &encryptor
+                        .encrypt_last(buffer.as_slice())
+                        .map_err(aead_error)?,
+                )?;
+                Ok(inner)
+            }
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let (inner, encryptor, buffer) = match self {
+            Self::Plain(inner) => return inner.write(buf),
+            Self::Encrypted {
+                inner,
+                encryptor: Some(encryptor),
+                buffer,
+            } => (inner, encryptor, buffer),
+            Self::Encrypted { .. } => {
+                return Err(std::io::Error::other("encryptor already finished"))
+            }
+        };
+
+        buffer.extend_from_slice(buf);
+
+        // A full segment is never the last one, see Reader::read_segment
+        while buffer.len() >= SEGMENT_SIZE {
+            inner.write_all(
+                &encryptor
+                    .encrypt_next(&buffer[..SEGMENT_SIZE])
+                    .map_err(aead_error)?,
+            )?;
+            buffer.drain(..SEGMENT_SIZE);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        // Partial segments are kept until they're full, or until finish
+        match self {
+            Self::Plain(inner) | Self::Encrypted { inner, .. } => inner.flush(),
+        }
+    }
+}
+
+/// Decrypts the given file content if it's encrypted, passes it through otherwise
+pub fn reader<R: std::io::Read + Send + 'static>(
+    mut inner: R,
+    master_key: Option<&MasterKey>,
+) -> std::io::Result<Box<dyn std::io::Read + Send>> {
+    use {
+        chacha20poly1305::{aead::stream::DecryptorBE32, KeyInit as _, XChaCha20Poly1305},
+        std::io::Read as _,
+    };
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    (&mut inner)
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+
+    let Some(header) = Header::from_bytes(&header) else {
+        // Give back what was read
+        return Ok(Box::new(std::io::Cursor::new(header).chain(inner)));
+    };
+
+    let Some(master_key) = master_key else {
+        return Err(std::io::Error::other(
+            "the file is encrypted but no master key is configured",
+        ));
+    };
+
+    let file_key = header.unwrap_key(master_key)?;
+
+    Ok(Box::new(Reader {
+        inner,
+        decryptor: Some(DecryptorBE32::from_aead(
+            XChaCha20Poly1305::new(&file_key.into()),
+            &header.stream_nonce.into(),
+        )),
+        segment: Vec::new(),
+        position: 0,
+    }))
+}
+
+struct Reader<R> {
+    inner: R,
+    // Taken by the last segment
+    decryptor:
+        Option<chacha20poly1305::aead::stream::DecryptorBE32<chacha20poly1305::XChaCha20Poly1305>>,
+    // Decrypted
+    segment: Vec<u8>,
+    position: usize,
+}
+
+impl<R: std::io::Read> Reader<R> {
+    fn read_segment(&mut self) -> std::io::Result<()> {
+        use std::io::Read as _;
+
+        let Some(decryptor) = self.decryptor.as_mut() else {
+            // Done
+            self.segment.clear();
+            self.position = 0;
+            return Ok(());
+        };
+
+        let mut encrypted = Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE);
+        (&mut self.inner)
+            .take((SEGMENT_SIZE + TAG_SIZE) as u64)
+            .read_to_end(&mut encrypted)?;
+
+        self.segment = if encrypted.len() == SEGMENT_SIZE + TAG_SIZE {
+            decryptor
+                .decrypt_next(encrypted.as_slice())
+                .map_err(aead_error)?
+        } else {
+            // A missing last segment fails here, as it's authenticated as the last one
+            self.decryptor
+                .take()
+                .unwrap() // Checked above
+                .decrypt_last(encrypted.as_slice())
+                .map_err(aead_error)?
+        };
+        self.position = 0;
+
+        Ok(())
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.segment.len() {
+            self.read_segment()?;
+        }
+
+        let read = buf.len().min(self.segment.len() - self.position);
+        buf[..read].copy_from_slice(&self.segment[self.position..self.position + read]);
+        self.position += read;
+
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RotationReport {
+    pub rotated: usize,
+    pub already_rotated: usize,
+    pub unencrypted: usize,
+    pub errors: usize,
+}
+
+impl std::fmt::Display for RotationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Re-wrapped {} file keys, {} were already using the new key, {} files are not encrypted ({} errors)",
+            self.rotated, self.already_rotated, self.unencrypted, self.errors
+        )
+    }
+}
+
+/// Re-wraps the file keys wrapped by the old master key with the configured one
+/// Only the headers are rewritten, and files already using the new key are skipped, so it can be run again if interrupted
+pub fn rotate(
+    config: &crate::config::StorageConfig,
+    old_key: &MasterKey,
+) -> Result<RotationReport, crate::error::CacheError> {
+    use crate::error::CacheError;
+
+    let Some(new_key) = config.master_key.as_ref() else {
+        return Err(CacheError::MasterKey {
+            why: String::from("no master key configured to rotate to"),
+        });
+    };
+
+    let scan = super::recovery::scan(config)?;
+
+    let paths = scan
+        .data_files
+        .iter()
+        .map(|name| super::fs::data_path(config, name))
+        .chain(
+            scan.chunks
+                .iter()
+                .map(|hash| super::fs::chunk_path(config, hash)),
+        );
+
+    let mut report = RotationReport::default();
+
+    for path in paths {
+        match rewrap(&path, old_key, new_key) {
+            Ok(Rewrap::Rotated) => report.rotated += 1,
+            Ok(Rewrap::AlreadyRotated) => report.already_rotated += 1,
+            Ok(Rewrap::Unencrypted) => report.unencrypted += 1,
+            Err(e) => {
+                error!(
+                    "Could not rotate the key of '{}' due to: {e}",
+                    path.display()
+                );
+                report.errors += 1;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+enum Rewrap {
+    Rotated,
+    AlreadyRotated,
+    Unencrypted,
+}
+
+fn rewrap(
+    path: &std::path::Path,
+    old_key: &MasterKey,
+    new_key: &MasterKey,
+) -> std::io::Result<Rewrap> {
+    use std::io::{Read as _, Seek as _, Write as _};
+
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE);
+    (&mut file)
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut bytes)?;
+
+    let Some(header) = Header::from_bytes(&bytes) else {
+        return Ok(Rewrap::Unencrypted);
+    };
+
+    if header.key_id == new_key.id() {
+        return Ok(Rewrap::AlreadyRotated);
+    }
+
+    let file_key = header.unwrap_key(old_key)?;
+
+    // Same size, so it's rewritten in place
+    let header = Header::seal(new_key, &file_key, header.stream_nonce)?;
+    file.seek(std::io::SeekFrom::Start(0))?;
+    file.write_all(&header.to_bytes())?;
+    file.sync_data()?;
+
+    Ok(Rewrap::Rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{reader, MasterKey, Writer, SEGMENT_SIZE},
+        std::io::{Read as _, Write as _},
+    };
+
+    fn encrypt(data: &[u8], key: &MasterKey) -> Vec<u8> {
+        let mut writer = Writer::new(Vec::new(), Some(key)).unwrap();
+        // Odd writes, to not always end on a segment
+        for part in data.chunks(10_000) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(data: Vec<u8>, key: &MasterKey) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        reader(std::io::Cursor::new(data), Some(key))?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_encryption_roundtrip() {
+        let key = MasterKey::parse(&[7; 32]).unwrap();
+
+        for len in [0, 1, SEGMENT_SIZE - 1, SEGMENT_SIZE, 3 * SEGMENT_SIZE + 5] {
+            let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+
+            let encrypted = encrypt(&data, &key);
+            assert_eq!(decrypt(encrypted, &key).unwrap(), data, "len: {len}");
+        }
+
+        // Unencrypted files are passed through
+        let plain = b"(\xb5/\xfd not encrypted".to_vec();
+        assert_eq!(decrypt(plain.clone(), &key).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_encryption_tampering() {
+        let key = MasterKey::parse(&[7; 32]).unwrap();
+        let data = vec![42; 2 * SEGMENT_SIZE + 100];
+        let encrypted = encrypt(&data, &key);
+
+        // Whole segments removed from the end
+        let mut truncated = encrypted.clone();
+        truncated.truncate(encrypted.len() - 100 - 16);
+        assert!(decrypt(truncated, &key).is_err());
+
+        let mut flipped = encrypted.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(decrypt(flipped, &key).is_err());
+
+        let other_key = MasterKey::parse(&[8; 32]).unwrap();
+        assert!(decrypt(encrypted, &other_key).is_err());
+    }
+}
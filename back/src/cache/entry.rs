@@ -8,6 +8,21 @@ pub struct CacheEntry {
 
     #[serde(skip_serializing)]
     file_lock: std::sync::Arc<parking_lot::RwLock<()>>,
+    // Edits and last accesses only hold file_lock's read guard, this keeps their meta rewrites apart
+    // Taken after file_lock
+    #[serde(skip_serializing)]
+    meta_lock: std::sync::Arc<parking_lot::Mutex<()>>,
+
+    #[serde(rename = "last_access", serialize_with = "as_secs")]
+    access: std::sync::Arc<Access>,
+
+    // Seconds since the epoch, the last edit, the upload or the meta file's last modification for older entries
+    // Used for the download's 'Last-Modified' and 'ETag'
+    #[serde(skip_serializing)]
+    modified: u64,
+    // Number of edits, see edit
+    #[serde(skip_serializing)]
+    revision: u64,
 }
 
 // Getters / Setters, easier to read if they are separated
@@ -15,6 +30,261 @@ impl CacheEntry {
     pub fn uuid(&self) -> uuid::Uuid {
         self.uuid
     }
+
+    pub fn upload_info(&self) -> &super::UploadInfo {
+        &self.upload_info
+    }
+
+    pub fn size(&self) -> super::Size {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: super::Size) {
+        self.size = size;
+    }
+
+    pub fn last_access(&self) -> u64 {
+        self.access
+            .last_access
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.access.hits.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn modified(&self) -> u64 {
+        self.modified
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    // Without waiting, for background jobs that can come back later
+    pub fn try_lock_files(
+        &self,
+    ) -> Option<parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()>> {
+        self.file_lock.try_write_arc()
+    }
+
+    // Once EntryHandle::edit wrote the meta, the map's guard is only held for this
+    pub fn set_edited(&mut self, upload_info: super::UploadInfo, modified: u64, revision: u64) {
+        self.upload_info = upload_info;
+        self.modified = modified;
+        self.revision = revision;
+    }
+}
+
+// Shared with the downloads in progress, see EntryHandle
+#[derive(Debug)]
+struct Access {
+    // See eviction.rs
+    // Milliseconds since the epoch, entries loaded from disk start at the one in their meta (or its last modification)
+    last_access: std::sync::atomic::AtomicU64,
+    hits: std::sync::atomic::AtomicU64,
+    // Seconds, last one written to the meta, see EntryHandle::touch
+    written_access: std::sync::atomic::AtomicU64,
+}
+
+impl Access {
+    fn new(last_access: u64) -> Self {
+        Self {
+            last_access: last_access.into(),
+            hits: Default::default(),
+            written_access: (last_access / 1000).into(),
+        }
+    }
+}
+
+const LAST_ACCESS_WRITE_INTERVAL: u64 = 60 * 60;
+
+// Milliseconds in memory, seconds like the other times of /info
+fn as_secs<S: serde::Serializer>(
+    access: &std::sync::Arc<Access>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(
+        access
+            .last_access
+            .load(std::sync::atomic::Ordering::Relaxed)
+            / 1000,
+    )
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// See EntryHandle::lock_meta
+pub struct MetaGuard {
+    _file_lock: parking_lot::ArcRwLockReadGuard<parking_lot::RawRwLock, ()>,
+    _meta_lock: parking_lot::ArcMutexGuard<parking_lot::RawMutex, ()>,
+}
+
+// See CacheEntry::handle
+pub struct EntryHandle {
+    uuid: uuid::Uuid,
+    file_lock: std::sync::Arc<parking_lot::RwLock<()>>,
+    meta_lock: std::sync::Arc<parking_lot::Mutex<()>>,
+    access: std::sync::Arc<Access>,
+}
+
+impl EntryHandle {
+    // Counts a download, load does it
+    // The last access is written to the meta at most every LAST_ACCESS_WRITE_INTERVAL, not to rewrite it on each download
+    pub fn touch(&self, config: &crate::config::StorageConfig) {
+        use std::sync::atomic::Ordering;
+
+        let now = now();
+        self.access.last_access.store(now, Ordering::Relaxed);
+        self.access.hits.fetch_add(1, Ordering::Relaxed);
+
+        let now = now / 1000;
+        let written = self.access.written_access.load(Ordering::Relaxed);
+
+        if now < written + LAST_ACCESS_WRITE_INTERVAL
+            || self
+                .access
+                .written_access
+                .compare_exchange(written, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+
+        // Keeps the recompression job and deletions away, their locks would wait on the caller's otherwise
+        let _lock = self.file_lock.read();
+        let _meta_lock = self.meta_lock.lock();
+
+        let written = super::fs::read_meta(config, &self.uuid).and_then(|mut metadata| {
+            metadata.set_last_access(now);
+            super::fs::write_meta(config, &self.uuid, &metadata)
+        });
+
+        if let Err(e) = written {
+            error!(
+                "[{}] Failed to write the last access due to: {e}",
+                self.uuid
+            );
+        }
+    }
+
+    // Keeps the recompression job, deletions and other meta rewrites away until dropped, without waiting for downloads
+    // Blocking, and only called once the map's guard is dropped (the recompression job gets the entry while holding it)
+    pub fn lock_meta(&self) -> MetaGuard {
+        MetaGuard {
+            _file_lock: self.file_lock.read_arc(),
+            _meta_lock: self.meta_lock.lock_arc(),
+        }
+    }
+
+    // Replaces the name and details in the meta, see routes/patch.rs
+    // The caller holds lock_meta's guard, the edit's time and the new revision go to CacheEntry::set_edited
+    pub fn edit(
+        &self,
+        config: &crate::config::StorageConfig,
+        upload_info: &super::UploadInfo,
+    ) -> Result<(u64, u64), crate::error::CacheError> {
+        let now = now() / 1000;
+
+        let mut metadata = super::fs::read_meta(config, &self.uuid)?;
+        metadata.edit(upload_info, now);
+        super::fs::write_meta(config, &self.uuid, &metadata)?;
+
+        Ok((now, metadata.revision()))
+    }
+
+    // Load a stored cache entry
+    // Everything that blocks (the last access, the lock and opening the files) is done by the reading task
+    pub async fn load(
+        self,
+        config: &crate::config::StorageConfig,
+    ) -> Result<crate::blocking::BlockingReader, crate::error::CacheError> {
+        let file = super::fs::meta_path(config, &self.uuid)
+            .display()
+            .to_string();
+        let config = config.clone();
+
+        crate::blocking::BlockingReader::open(move || self.open(&config))
+            .await
+            .map_err(|e| e.unwrap_or(crate::error::CacheError::BlockingTask { file }))
+    }
+
+    fn open(
+        self,
+        config: &crate::config::StorageConfig,
+    ) -> Result<Box<dyn std::io::Read + Send>, crate::error::CacheError> {
+        use {super::StorageMode, crate::error::CacheError, std::fs::OpenOptions};
+
+        struct DecoderWrapper<T, U> {
+            decoder: T,
+            _file_lock: U,
+        }
+
+        impl<T, U> std::io::Read for DecoderWrapper<T, U>
+        where
+            T: std::io::Read + Send,
+        {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.decoder.read(buf)
+            }
+        }
+
+        // Before locking, it might lock on its own
+        self.touch(config);
+
+        let (lock, duration) = time::timeit(|| self.file_lock.read_arc());
+
+        debug!(
+            "Download of cache {}, acquired lock in {}",
+            self.uuid,
+            time::format(duration, 1)
+        );
+
+        let metadata = super::fs::read_meta(config, &self.uuid)?;
+        let data_path = super::fs::data_path(config, metadata.data_file_name());
+
+        let decoder: Box<dyn std::io::Read + Send> =
+            match metadata.storage() {
+                StorageMode::Whole => {
+                    let file = OpenOptions::new()
+                        .read(true)
+                        .open(&data_path)
+                        .map_err(|e| CacheError::FileOpen {
+                            file: data_path.display().to_string(),
+                            why: e,
+                        })?;
+
+                    let file = super::encryption::reader(file, config.master_key.as_ref())
+                        .map_err(|e| CacheError::FileRead {
+                            file: data_path.display().to_string(),
+                            why: e,
+                        })?;
+
+                    Box::new(super::dictionaries::decoder(
+                        config,
+                        file,
+                        metadata.dictionary(),
+                    )?)
+                }
+                StorageMode::Chunked => Box::new(ChunkReader {
+                    config: config.clone(),
+                    chunks: super::Manifest::from_file(&data_path)?
+                        .into_chunks()
+                        .into_iter(),
+                    current: None,
+                }),
+            };
+
+        Ok(Box::new(DecoderWrapper {
+            decoder,
+            _file_lock: lock,
+        }))
+    }
 }
 
 // Init methods
@@ -23,11 +293,7 @@ impl CacheEntry {
         use {
             super::Metadata,
             crate::error::CacheError,
-            rocket::serde::json::serde_json,
-            std::{
-                fs::{File, OpenOptions},
-                str::FromStr as _,
-            },
+            std::{fs::OpenOptions, str::FromStr as _},
             uuid::Uuid,
         };
 
@@ -59,52 +325,70 @@ impl CacheEntry {
                 why: e,
             })?;
 
-        let metadata = serde_json::from_reader::<File, Metadata>(file).map_err(|e| {
+        let modified = file
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_else(now);
+
+        let metadata = Metadata::from_reader(std::io::BufReader::new(file)).map_err(|e| {
             CacheError::Deserialization {
                 file: path.display().to_string(),
                 why: e,
             }
         })?;
 
+        let last_access = metadata
+            .last_access()
+            .map(|last_access| last_access * 1000)
+            .unwrap_or(modified);
+
         Ok(Self {
             uuid,
             upload_info: super::UploadInfo::new(
                 metadata.name().clone(),
                 metadata.extension().clone(),
+                metadata.client_encrypted(),
+                metadata.password_hash().map(str::to_string),
+                metadata.pinned(),
+                metadata.details().clone(),
             ),
             size: *metadata.size(),
 
             file_lock: Default::default(),
+            meta_lock: Default::default(),
+            access: std::sync::Arc::new(Access::new(last_access)),
+
+            modified: metadata
+                .edited()
+                .or(metadata.details().upload_time)
+                .unwrap_or(modified / 1000),
+            revision: metadata.revision(),
         })
     }
 }
 
 impl CacheEntry {
-    pub fn load_meta(&self) -> Result<super::Metadata, crate::error::CacheError> {
-        use {crate::error::CacheError, rocket::serde::json::serde_json, std::fs::OpenOptions};
-
-        let meta_path = super::fs::meta_path(&self.uuid);
-        let meta_file = OpenOptions::new()
-            .read(true)
-            .open(&meta_path)
-            .map_err(|e| CacheError::FileOpen {
-                file: meta_path.display().to_string(),
-                why: e,
-            })?;
-
-        serde_json::from_reader(meta_file).map_err(|e| CacheError::Deserialization {
-            file: meta_path.display().to_string(),
-            why: e,
-        })
+    pub fn load_meta(
+        &self,
+        config: &crate::config::StorageConfig,
+    ) -> Result<super::Metadata, crate::error::CacheError> {
+        super::fs::read_meta(config, &self.uuid)
     }
 
     pub async fn store_new(
+        config: &crate::config::StorageConfig,
         uuid: uuid::Uuid,
         upload_info: super::UploadInfo,
-        data_stream: rocket::data::DataStream<'_>,
+        data_stream: impl rocket::tokio::io::AsyncRead + Unpin,
+        storage: super::StorageMode,
         duplicate_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::DuplicateMap>>,
+        chunk_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::ChunkMap>>,
     ) -> Result<Self, crate::error::CacheError> {
         use {
+            super::StorageMode,
             crate::error::CacheError,
             rocket::{data::ByteUnit, serde::json::serde_json, tokio::fs::remove_file},
             std::path::PathBuf,
@@ -112,11 +396,15 @@ impl CacheEntry {
 
         let start_time = std::time::Instant::now();
 
-        let meta_path = super::fs::meta_path(&uuid);
+        let meta_path = super::fs::meta_path(config, &uuid);
         // Data path needs to be mutable since since it may be swapped for an already exising file
         // in the duplicate detection
         // Could also return a new one but eh
-        let mut data_path = super::fs::temp_data_path(&uuid);
+        let mut data_path = match storage {
+            StorageMode::Whole => super::fs::temp_data_path(config, &uuid),
+            // The manifest takes the place of the data file
+            StorageMode::Chunked => super::fs::manifest_path(config, &uuid),
+        };
 
         let cleanup_files = |meta_path: PathBuf, data_path: PathBuf| async {
             match futures::join!(remove_file(meta_path), remove_file(data_path)) {
@@ -134,17 +422,56 @@ impl CacheEntry {
         let (meta_file, mut data_file) =
             super::fs::create_cache_files(meta_path.clone(), data_path.clone())?;
 
-        // Stream the upload to the data file, returning the original and the end file sizes
-        let data_size = {
-            let (data_store_result, data_store_duration) = time::timeit_async(async || {
-                super::stream_to_file(&uuid, data_stream, &mut data_file).await
-            })
-            .await;
+        // Chunks are always compressed on their own, they're big enough not to need a dictionary
+        // and a dictionary can't do anything for already encrypted content
+        let dictionary = match storage {
+            StorageMode::Whole if !upload_info.client_encrypted() => {
+                super::dictionaries::latest(config, upload_info.extension())
+            }
+            _ => None,
+        };
+        let dictionary_data = match dictionary
+            .as_deref()
+            .map(|id| super::dictionaries::load(config, id))
+        {
+            Some(Ok(data)) => Some(data),
+            Some(Err(e)) => {
+                cleanup_files(meta_path, data_path).await;
+                return Err(e);
+            }
+            None => None,
+        };
+
+        // Stream the upload to the data file (or chunks), returning the original and the end file sizes,
+        // and for whole entries, the content's hash
+        let (data_size, hash) = {
+            let (data_store_result, data_store_duration) =
+                time::timeit_async(async || match storage {
+                    StorageMode::Whole => super::stream_to_file(
+                        config,
+                        &uuid,
+                        data_stream,
+                        &mut data_file,
+                        dictionary_data.clone(),
+                    )
+                    .await
+                    .map(|(size, hash)| (size, Some(hash))),
+                    StorageMode::Chunked => super::stream_to_chunks(
+                        config,
+                        &uuid,
+                        data_stream,
+                        &mut data_file,
+                        &chunk_map,
+                    )
+                    .await
+                    .map(|size| (size, None)),
+                })
+                .await;
 
             debug!("Data store took: {}", time::format(data_store_duration, -1));
 
             match data_store_result {
-                Ok(size) => size,
+                Ok(stored) => stored,
                 Err(e) => {
                     // Cleanup the files if we encounter any error
                     // We know that the files were created, so any error here are important
@@ -156,29 +483,60 @@ impl CacheEntry {
 
         // Make sure the file is not a duplicate, in what case we'll remove the data file we just created
         // and use the exising one
+        // Chunks are already deduplicated one by one
         // FIXME: The implementation of this is really ugly
-        if let Err(e) =
-            super::duplicates::handle_duplicates(&mut data_path, &uuid, &duplicate_map).await
-        {
-            cleanup_files(meta_path, data_path).await;
+        let mut dictionary = dictionary;
+        if let Some(hash) = hash {
+            let is_duplicate = match super::duplicates::handle_duplicates(
+                config,
+                &mut data_path,
+                &uuid,
+                hash,
+                &duplicate_map,
+            )
+            .await
+            {
+                Ok(is_duplicate) => is_duplicate,
+                Err(e) => {
+                    cleanup_files(meta_path, data_path).await;
 
-            return Err(e);
+                    return Err(e);
+                }
+            };
+
+            // The stored file might have been compressed with an other version (or none)
+            if is_duplicate {
+                dictionary = match super::dictionaries::used_by(config, &data_path) {
+                    Ok(dictionary) => dictionary,
+                    Err(e) => {
+                        // Only the meta, the data file is used by the other entries
+                        if let Err(e) = remove_file(&meta_path).await {
+                            error!("[{uuid}] Failed to cleanup after error due to: {e}")
+                        }
+                        return Err(e);
+                    }
+                };
+            }
         }
 
         // Build new metadata
         let metadata = super::Metadata::new(
-            upload_info.name().to_string(),
-            upload_info.extension().to_string(),
+            &upload_info,
             data_size,
             data_path
                 .file_name()
                 .and_then(|s| s.to_str())
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| format!("{uuid}.data")),
+            storage,
+            dictionary,
         );
 
         // Store that newly built metadata
         if let Err(e) = serde_json::to_writer(meta_file, &metadata) {
+            if storage == StorageMode::Chunked {
+                release_manifest(config, &data_path, &chunk_map).await;
+            }
             cleanup_files(meta_path, data_path).await;
             return Err(CacheError::Serialization {
                 context: String::from("writing meta data"),
@@ -199,74 +557,35 @@ impl CacheEntry {
             size: data_size,
 
             file_lock: Default::default(),
+            meta_lock: Default::default(),
+            access: std::sync::Arc::new(Access::new(now())),
+
+            modified: metadata.details().upload_time.unwrap_or(now() / 1000),
+            revision: 0,
         })
     }
 
-    // Load a stored cache entry
-    pub async fn load(
-        &self,
-    ) -> Result<(super::UploadInfo, Box<dyn std::io::Read + Send>), crate::error::CacheError> {
-        use {crate::error::CacheError, std::fs::OpenOptions, zstd::stream::Decoder};
-
-        struct DecoderWrapper<'t, T, U> {
-            decoder: zstd::stream::Decoder<'t, T>,
-            _file_lock: U,
+    // What a download needs once the map's guard is dropped, the map isn't held while it waits on files
+    pub fn handle(&self) -> EntryHandle {
+        EntryHandle {
+            uuid: self.uuid,
+            file_lock: std::sync::Arc::clone(&self.file_lock),
+            meta_lock: std::sync::Arc::clone(&self.meta_lock),
+            access: std::sync::Arc::clone(&self.access),
         }
-
-        impl<'t, T, U> std::io::Read for DecoderWrapper<'t, T, U>
-        where
-            T: std::io::Read + Send + std::io::BufRead,
-        {
-            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-                self.decoder.read(buf)
-            }
-        }
-
-        let (lock, duration) = time::timeit(|| self.file_lock.read_arc());
-
-        debug!(
-            "Download of cache {}, acquired lock in {}",
-            self.uuid,
-            time::format(duration, 1)
-        );
-
-        let metadata = self.load_meta()?;
-
-        let decoder = {
-            let data_path = super::fs::data_path(metadata.data_file_name());
-
-            let file = OpenOptions::new()
-                .read(true)
-                .open(&data_path)
-                .map_err(|e| CacheError::FileOpen {
-                    file: data_path.display().to_string(),
-                    why: e,
-                })?;
-
-            Decoder::new(file).map_err(|e| CacheError::FileOpen {
-                file: data_path.display().to_string(),
-                why: e,
-            })?
-        };
-
-        Ok((
-            self.upload_info.clone(),
-            Box::new(DecoderWrapper {
-                decoder,
-                _file_lock: lock,
-            }),
-        ))
     }
 
     /// Delete a cache entry
     pub async fn delete(
         &self,
+        config: &crate::config::StorageConfig,
         duplicate_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::DuplicateMap>>,
+        chunk_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::ChunkMap>>,
     ) -> Result<(), crate::error::CacheError> {
         // #![allow(clippy::await_holding_lock)]
         // This was for the file_lock but it's fixed using the arc guard
 
-        use {crate::error::CacheError, tokio::fs::remove_file};
+        use {super::StorageMode, crate::error::CacheError, tokio::fs::remove_file};
 
         let (lock, duration) = time::timeit(|| self.file_lock.write_arc());
 
@@ -279,35 +598,39 @@ impl CacheEntry {
         // warn!("Tried to delete a file currently accessed !\n{:?}", self.file_lock.read());
         // return Err(CacheError::NotReady { uuid: self.uuid });
 
-        let metadata = self.load_meta()?;
+        let metadata = self.load_meta(config)?;
 
-        let mut duplicate_map_guard = duplicate_map.lock().await;
-        let hashes = duplicate_map_guard.remove(&self.uuid)?;
+        let meta_path = super::fs::meta_path(config, &self.uuid);
+        let data_path = super::fs::data_path(config, metadata.data_file_name());
 
-        if hashes.len() != 1 {
-            return Err(CacheError::DuplicateMapLogic(
-                format!("Deletion of [{}] failled: the duplicate map returned {} matches ({:?}) for uuid: {}", self.uuid, hashes.len(), hashes, self.uuid)
-            ));
-        }
+        if metadata.storage() == StorageMode::Chunked {
+            // The manifest is only used by this entry, the chunks it lists are the shared part
+            release_manifest(config, &data_path, &chunk_map).await;
+        } else {
+            let mut duplicate_map_guard = duplicate_map.lock().await;
+            let hashes = duplicate_map_guard.remove(&self.uuid)?;
 
-        let data_hash = hashes.first().unwrap(); // Cannot fail
+            if hashes.len() != 1 {
+                return Err(CacheError::DuplicateMapLogic(
+                    format!("Deletion of [{}] failled: the duplicate map returned {} matches ({:?}) for uuid: {}", self.uuid, hashes.len(), hashes, self.uuid)
+                ));
+            }
 
-        // Meaning that the current uuid was NOT the last holder of that data hash
-        if duplicate_map_guard.get(data_hash).is_some() {
-            // Just remove the meta file, and leave
-            let meta_path = super::fs::meta_path(&self.uuid);
-            remove_file(&meta_path)
-                .await
-                .map_err(|e| CacheError::FileRemove {
-                    file: meta_path.display().to_string(),
-                    why: e,
-                })?;
-            return Ok(());
+            let data_hash = hashes.first().unwrap(); // Cannot fail
+
+            // Meaning that the current uuid was NOT the last holder of that data hash
+            if duplicate_map_guard.get(data_hash).is_some() {
+                // Just remove the meta file, and leave
+                remove_file(&meta_path)
+                    .await
+                    .map_err(|e| CacheError::FileRemove {
+                        file: meta_path.display().to_string(),
+                        why: e,
+                    })?;
+                return Ok(());
+            }
         }
 
-        let meta_path = super::fs::meta_path(&self.uuid);
-        let data_path = super::fs::data_path(metadata.data_file_name());
-
         let res = match futures::join!(remove_file(&meta_path), remove_file(&data_path)) {
             (Ok(_), Ok(_)) => Ok(()),
             (Ok(_), Err(e)) => Err(CacheError::FileRemove {
@@ -336,3 +659,57 @@ impl CacheEntry {
         res
     }
 }
+
+// Decrypts and decompresses the chunks listed in a manifest, one after the other
+struct ChunkReader {
+    config: crate::config::StorageConfig,
+    chunks: std::vec::IntoIter<super::manifest::ChunkRef>,
+    current:
+        Option<zstd::stream::Decoder<'static, std::io::BufReader<Box<dyn std::io::Read + Send>>>>,
+}
+
+impl std::io::Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(decoder) = self.current.as_mut() {
+                let read = decoder.read(buf)?;
+                if read != 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+
+            let file = std::fs::File::open(super::fs::chunk_path(&self.config, chunk.hash()))?;
+            self.current = Some(zstd::stream::Decoder::new(super::encryption::reader(
+                file,
+                self.config.master_key.as_ref(),
+            )?)?);
+        }
+    }
+}
+
+// Releases every chunk of the given manifest
+async fn release_manifest(
+    config: &crate::config::StorageConfig,
+    manifest_path: &std::path::Path,
+    chunk_map: &std::sync::Arc<rocket::tokio::sync::Mutex<super::ChunkMap>>,
+) {
+    let manifest = match super::Manifest::from_file(manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            // The recovery pass will recount the references
+            error!("Could not release chunks due to: {e}");
+            return;
+        }
+    };
+
+    let mut chunk_map_guard = chunk_map.lock().await;
+    super::chunks::release_all(
+        config,
+        &mut chunk_map_guard,
+        manifest.chunks().iter().map(|chunk| chunk.hash()),
+    );
+}
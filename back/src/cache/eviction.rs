@@ -0,0 +1,121 @@
+// Optional capacity bound of the cache, see config::EvictionConfig
+//
+// Once an upload brings the total compressed size over the capacity, the least valuable entries are deleted
+// (like a DELETE request would) until it fits again. Pinned entries and the new upload itself are never evicted
+//
+// Entries sharing a data file (duplicates) or chunks each count their whole size, so the total is on the high side
+
+// Evicts entries until the cache fits in its capacity, returns how many were evicted
+pub async fn make_room(
+    config: &crate::config::StorageConfig,
+    eviction: &crate::config::EvictionConfig,
+    cache: &super::CacheEntryMap,
+    new_uuid: uuid::Uuid,
+    duplicate_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::DuplicateMap>>,
+    chunk_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::ChunkMap>>,
+    hot_cache: &crate::hot_cache::HotCache,
+) -> Result<usize, crate::error::CacheError> {
+    use {crate::error::CacheError, rocket::data::ByteUnit};
+
+    let capacity = eviction.capacity.as_u64();
+    if capacity == 0 {
+        return Ok(0);
+    }
+
+    let mut total = cache
+        .iter()
+        .map(|entry| entry.size().compressed())
+        .sum::<u64>();
+
+    if total <= capacity {
+        return Ok(0);
+    }
+
+    // Wouldn't fit even alone, no need to empty the cache for it
+    if cache
+        .get(&new_uuid)
+        .is_some_and(|entry| entry.size().compressed() > capacity)
+    {
+        return Err(CacheError::CapacityExceeded {
+            capacity: eviction.capacity,
+        });
+    }
+
+    let candidates = candidates(eviction.policy, cache, new_uuid);
+
+    let mut evicted = 0;
+    for uuid in candidates {
+        if total <= capacity {
+            break;
+        }
+
+        // Might have been deleted (or evicted by an other upload) in the meantime
+        let Some((_uuid, entry)) = cache.remove(&uuid) else {
+            continue;
+        };
+
+        if let Err(e) = entry
+            .delete(
+                config,
+                std::sync::Arc::clone(&duplicate_map),
+                std::sync::Arc::clone(&chunk_map),
+            )
+            .await
+        {
+            error!("[{uuid}] Failed to evict due to: {e}");
+            cache.insert(entry.uuid(), entry);
+            continue;
+        }
+
+        hot_cache.remove(&uuid);
+
+        debug!(
+            "[{uuid}] Evicted ({}, {} downloads)",
+            ByteUnit::Byte(entry.size().compressed()),
+            entry.hits()
+        );
+
+        total = total.saturating_sub(entry.size().compressed());
+        evicted += 1;
+    }
+
+    if evicted != 0 {
+        info!(
+            "Evicted {evicted} entries to stay under the {} capacity",
+            eviction.capacity
+        );
+    }
+
+    if total > capacity {
+        return Err(CacheError::CapacityExceeded {
+            capacity: eviction.capacity,
+        });
+    }
+
+    Ok(evicted)
+}
+
+// Evictable entries, least valuable first
+fn candidates(
+    policy: crate::config::EvictionPolicy,
+    cache: &super::CacheEntryMap,
+    new_uuid: uuid::Uuid,
+) -> Vec<uuid::Uuid> {
+    use crate::config::EvictionPolicy;
+
+    // Read once, they could change while sorting
+    let mut candidates = cache
+        .iter()
+        .filter(|entry| entry.uuid() != new_uuid && !entry.upload_info().pinned())
+        .map(|entry| (entry.uuid(), entry.hits(), entry.last_access()))
+        .collect::<Vec<_>>();
+
+    match policy {
+        EvictionPolicy::Lru => candidates.sort_by_key(|(_, _, last_access)| *last_access),
+        EvictionPolicy::Lfu => {
+            candidates.sort_by_key(|(_, hits, last_access)| (*hits, *last_access))
+        }
+    }
+
+    candidates.into_iter().map(|(uuid, _, _)| uuid).collect()
+}
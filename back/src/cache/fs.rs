@@ -1,35 +1,131 @@
-#[cfg(not(test))]
-lazy_static! {
-    static ref CACHE_DIRECTORY: std::path::PathBuf =
-        std::str::FromStr::from_str("./cache").unwrap();
+// Every path is under the configured cache directory, see config.rs
+
+pub fn meta_path(config: &crate::config::StorageConfig, uuid: &uuid::Uuid) -> std::path::PathBuf {
+    config
+        .cache_dir
+        .join(format!("{}.meta", uuid.as_hyphenated()))
+}
+
+pub fn data_path(config: &crate::config::StorageConfig, name: &str) -> std::path::PathBuf {
+    config.cache_dir.join(name)
+}
+
+pub fn temp_data_path(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+) -> std::path::PathBuf {
+    config
+        .cache_dir
+        .join(format!("{}.temp_data", uuid.as_hyphenated()))
+}
+
+// Recompressed version of a data file, see recompression.rs
+pub fn temp_recompressed_path(
+    config: &crate::config::StorageConfig,
+    name: &str,
+) -> std::path::PathBuf {
+    config.cache_dir.join(format!("{name}.temp_data"))
+}
+
+// One per rewrite, so two of them can never write the same file
+pub fn temp_meta_path(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+) -> std::path::PathBuf {
+    config.cache_dir.join(format!(
+        "{}.{:016x}.temp_meta",
+        uuid.as_hyphenated(),
+        rand::random::<u64>()
+    ))
+}
+
+pub fn duplicates_path(config: &crate::config::StorageConfig) -> std::path::PathBuf {
+    config.cache_dir.join("duplicates.json")
+}
+
+pub fn manifest_path(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+) -> std::path::PathBuf {
+    config
+        .cache_dir
+        .join(format!("{}.manifest", uuid.as_hyphenated()))
 }
-#[cfg(test)]
-lazy_static! {
-    static ref CACHE_DIRECTORY: std::path::PathBuf =
-        std::str::FromStr::from_str("../cache").unwrap();
+
+pub fn chunks_dir(config: &crate::config::StorageConfig) -> std::path::PathBuf {
+    config.cache_dir.join("chunks")
+}
+
+pub fn chunk_path(config: &crate::config::StorageConfig, hash: &str) -> std::path::PathBuf {
+    chunks_dir(config).join(hash)
+}
+
+pub fn temp_chunk_path(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+    index: usize,
+) -> std::path::PathBuf {
+    chunks_dir(config).join(format!("{}-{index}.temp_chunk", uuid.as_hyphenated()))
+}
+
+pub fn chunks_map_path(config: &crate::config::StorageConfig) -> std::path::PathBuf {
+    config.cache_dir.join("chunks.json")
 }
 
-pub fn meta_path(uuid: &uuid::Uuid) -> std::path::PathBuf {
-    CACHE_DIRECTORY.join(format!("{}.meta", uuid.as_hyphenated()))
+pub fn dictionaries_dir(config: &crate::config::StorageConfig) -> std::path::PathBuf {
+    config.cache_dir.join("dictionaries")
 }
 
-pub fn data_path(name: &str) -> std::path::PathBuf {
-    CACHE_DIRECTORY.join(name)
+pub fn dictionary_path(config: &crate::config::StorageConfig, id: &str) -> std::path::PathBuf {
+    dictionaries_dir(config).join(format!("{id}.dict"))
 }
 
-pub fn temp_data_path(uuid: &uuid::Uuid) -> std::path::PathBuf {
-    CACHE_DIRECTORY.join(format!("{}.temp_data", uuid.as_hyphenated()))
+pub fn quarantine_dir(config: &crate::config::StorageConfig) -> std::path::PathBuf {
+    config.cache_dir.join("quarantine")
 }
 
-pub fn duplicates_path() -> std::path::PathBuf {
-    CACHE_DIRECTORY.join("duplicates.json")
+// Data files are named after the sha256 of their content
+pub fn is_data_file_name(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Moves a file we don't know how to handle out of the way, without loosing it
+pub fn quarantine(
+    config: &crate::config::StorageConfig,
+    path: &std::path::Path,
+) -> Result<std::path::PathBuf, crate::error::CacheError> {
+    use crate::error::CacheError;
+
+    let dir = quarantine_dir(config);
+
+    std::fs::create_dir_all(&dir).map_err(|e| CacheError::DirCreate {
+        dir: dir.display().to_string(),
+        why: e,
+    })?;
+
+    let Some(file_name) = path.file_name() else {
+        return Err(CacheError::InvalidId {
+            value: path.display().to_string(),
+        });
+    };
+
+    let new_path = dir.join(file_name);
+
+    std::fs::rename(path, &new_path).map_err(|e| CacheError::FileRename {
+        file: path.display().to_string(),
+        why: e,
+    })?;
+
+    Ok(new_path)
 }
 
-pub fn read_cache_dir() -> Result<std::fs::ReadDir, crate::error::CacheError> {
-    std::fs::read_dir(CACHE_DIRECTORY.clone()).map_err(|e| {
+pub fn read_cache_dir(
+    config: &crate::config::StorageConfig,
+) -> Result<std::fs::ReadDir, crate::error::CacheError> {
+    std::fs::read_dir(&config.cache_dir).map_err(|e| {
         error!("Could not open cache dir due to: {e}");
         crate::error::CacheError::CacheDirRead {
-            dir: CACHE_DIRECTORY.display().to_string(),
+            dir: config.cache_dir.display().to_string(),
             why: e,
         }
     })
@@ -75,3 +171,63 @@ pub fn create_cache_files(
 
     Ok((meta_file, data_file))
 }
+
+// Rewrites an existing meta file, going through a temp file so a crash can't leave it half written
+// Read, changed and written back by the caller, which holds the entry's meta lock or its file lock's write guard so
+// no other rewrite runs in between (see CacheEntry), or runs before the server starts
+pub fn write_meta(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+    metadata: &super::Metadata,
+) -> Result<(), crate::error::CacheError> {
+    use {crate::error::CacheError, rocket::serde::json::serde_json, std::io::Write as _};
+
+    let temp_path = temp_meta_path(config, uuid);
+    let path = meta_path(config, uuid);
+
+    let json = serde_json::to_vec(metadata).map_err(|e| CacheError::Serialization {
+        context: String::from("writing meta data"),
+        why: e,
+    })?;
+
+    let mut file = std::fs::File::create(&temp_path).map_err(|e| CacheError::FileCreate {
+        file: temp_path.display().to_string(),
+        why: e,
+    })?;
+
+    file.write_all(&json)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| CacheError::FileWrite {
+            file: temp_path.display().to_string(),
+            why: e,
+        })?;
+
+    std::fs::rename(&temp_path, &path).map_err(|e| CacheError::FileRename {
+        file: temp_path.display().to_string(),
+        why: e,
+    })
+}
+
+// The entry's meta as currently on disk, the caller holds its file lock when it matters
+pub fn read_meta(
+    config: &crate::config::StorageConfig,
+    uuid: &uuid::Uuid,
+) -> Result<super::Metadata, crate::error::CacheError> {
+    use {crate::error::CacheError, std::fs::OpenOptions};
+
+    let meta_path = super::fs::meta_path(config, uuid);
+    let meta_file = OpenOptions::new()
+        .read(true)
+        .open(&meta_path)
+        .map_err(|e| CacheError::FileOpen {
+            file: meta_path.display().to_string(),
+            why: e,
+        })?;
+
+    super::Metadata::from_reader(std::io::BufReader::new(meta_file)).map_err(|e| {
+        CacheError::Deserialization {
+            file: meta_path.display().to_string(),
+            why: e,
+        }
+    })
+}
@@ -0,0 +1,459 @@
+// Offline consistency check of the cache directory
+// Unlike the recovery pass, this reads every data file, so it's way too slow to run at boot
+
+#[derive(Debug, serde::Serialize)]
+pub struct Issue {
+    file: String,
+    why: String,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct FsckReport {
+    pub checked_metas: usize,
+    pub checked_data_files: usize,
+    pub checked_chunks: usize,
+
+    pub temp_files: Vec<String>,
+    pub empty_metas: Vec<String>,
+    pub unparsable_metas: Vec<Issue>,
+    pub missing_data: Vec<Issue>,
+    pub corrupted_data: Vec<Issue>,
+    pub duplicate_map: Vec<Issue>,
+    pub unreferenced_data: Vec<String>,
+    pub chunk_map: Vec<Issue>,
+    pub unreferenced_chunks: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.temp_files.is_empty()
+            && self.empty_metas.is_empty()
+            && self.unparsable_metas.is_empty()
+            && self.missing_data.is_empty()
+            && self.corrupted_data.is_empty()
+            && self.duplicate_map.is_empty()
+            && self.unreferenced_data.is_empty()
+            && self.chunk_map.is_empty()
+            && self.unreferenced_chunks.is_empty()
+    }
+}
+
+impl std::fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Checked {} metas, {} data files and {} chunks",
+            self.checked_metas, self.checked_data_files, self.checked_chunks
+        )?;
+
+        if self.is_clean() {
+            return write!(f, "No issue found");
+        }
+
+        let mut section = |title: &str, lines: Vec<String>| -> std::fmt::Result {
+            if lines.is_empty() {
+                return Ok(());
+            }
+            writeln!(f, "{title} ({}):", lines.len())?;
+            for line in lines {
+                writeln!(f, "    {line}")?;
+            }
+            Ok(())
+        };
+
+        let issues = |issues: &[Issue]| {
+            issues
+                .iter()
+                .map(|issue| format!("{}: {}", issue.file, issue.why))
+                .collect::<Vec<String>>()
+        };
+
+        section("Stale temp files", self.temp_files.clone())?;
+        section("Empty metas", self.empty_metas.clone())?;
+        section("Unparsable metas", issues(&self.unparsable_metas))?;
+        section("Metas with missing data", issues(&self.missing_data))?;
+        section("Corrupted data files", issues(&self.corrupted_data))?;
+        section("Duplicate map", issues(&self.duplicate_map))?;
+        section("Unreferenced data files", self.unreferenced_data.clone())?;
+        section("Chunk map", issues(&self.chunk_map))?;
+        section("Unreferenced chunks", self.unreferenced_chunks.clone())
+    }
+}
+
+pub fn fsck(config: &crate::config::StorageConfig) -> Result<FsckReport, crate::error::CacheError> {
+    use std::collections::HashMap;
+
+    let display = |path: &std::path::PathBuf| path.display().to_string();
+
+    let scan = super::recovery::scan(config)?;
+    let mut report = FsckReport {
+        checked_metas: scan.metas.len(),
+        checked_data_files: scan.data_files.len(),
+        checked_chunks: scan.chunks.len(),
+        temp_files: scan.temp_files.iter().map(display).collect(),
+        empty_metas: scan.empty_metas.iter().map(display).collect(),
+        unparsable_metas: scan
+            .unparsable_metas
+            .iter()
+            .map(|(path, why)| Issue {
+                file: display(path),
+                why: why.clone(),
+            })
+            .collect(),
+        unreferenced_data: super::recovery::unreferenced_data(config, &scan, &scan.metas)
+            .iter()
+            .map(display)
+            .collect(),
+        ..Default::default()
+    };
+
+    let duplicate_map = super::DuplicateMap::init_from_cache_dir(config);
+    let outdated = duplicate_map.is_outdated();
+
+    if outdated {
+        report.duplicate_map.push(Issue {
+            file: super::fs::duplicates_path(config).display().to_string(),
+            why: String::from("keyed by compressed hashes, needs to be migrated"),
+        });
+    }
+
+    // Every meta pointing to a given data file, with the original size and the dictionary they expect
+    let mut expected = HashMap::<&String, Vec<(uuid::Uuid, u64, Option<&str>)>>::new();
+    // Same for chunks, but the size comes from the manifests
+    let mut expected_chunks = HashMap::<String, u64>::new();
+    for (uuid, meta) in scan.metas.iter() {
+        if meta.storage() == super::StorageMode::Chunked {
+            match check_manifest(config, &scan, meta, &mut expected_chunks) {
+                Ok(()) => (),
+                Err(why) => report.missing_data.push(Issue {
+                    file: display(&super::fs::meta_path(config, uuid)),
+                    why,
+                }),
+            }
+            continue;
+        }
+
+        if !scan.data_files.contains(meta.data_file_name()) {
+            report.missing_data.push(Issue {
+                file: display(&super::fs::meta_path(config, uuid)),
+                why: format!("data file '{}' does not exist", meta.data_file_name()),
+            });
+            continue;
+        }
+
+        expected.entry(meta.data_file_name()).or_default().push((
+            *uuid,
+            meta.size().original(),
+            meta.dictionary(),
+        ));
+    }
+
+    for (name, holders) in expected.iter() {
+        let path = super::fs::data_path(config, name);
+
+        // Holders can't disagree, as that's the file's dictionary, see dictionaries::used_by
+        let dictionary = holders.first().and_then(|(_, _, dictionary)| *dictionary);
+
+        let (hash, original_size) = match check_data_file(config, &path, dictionary) {
+            Ok(checked) => checked,
+            Err(why) => {
+                report.corrupted_data.push(Issue {
+                    file: display(&path),
+                    why,
+                });
+                continue;
+            }
+        };
+
+        // Old data files are named after their compressed bytes until migrated
+        if !outdated && &&hash != name {
+            report.corrupted_data.push(Issue {
+                file: display(&path),
+                why: format!("content hash is {hash}"),
+            });
+        }
+
+        for (uuid, expected_size, _) in holders {
+            if *expected_size != original_size {
+                report.corrupted_data.push(Issue {
+                    file: display(&path),
+                    why: format!(
+                        "[{uuid}] expected {expected_size} bytes once decompressed, got {original_size}"
+                    ),
+                });
+            }
+        }
+    }
+
+    for (hash, expected_size) in expected_chunks.iter() {
+        let path = super::fs::chunk_path(config, hash);
+
+        match check_data_file(config, &path, None) {
+            Ok((content_hash, _)) if &content_hash != hash => report.corrupted_data.push(Issue {
+                file: display(&path),
+                why: format!("content hash is {content_hash}"),
+            }),
+            Ok((_, size)) if size != *expected_size => report.corrupted_data.push(Issue {
+                file: display(&path),
+                why: format!("expected {expected_size} bytes once decompressed, got {size}"),
+            }),
+            Ok(_) => (),
+            Err(why) => report.corrupted_data.push(Issue {
+                file: display(&path),
+                why,
+            }),
+        }
+    }
+
+    check_chunk_map(config, &scan, &mut report);
+
+    for (uuid, meta) in scan.metas.iter() {
+        if meta.storage() == super::StorageMode::Chunked {
+            // Not in the duplicate map
+            continue;
+        }
+
+        let registered = duplicate_map
+            .get(meta.data_file_name())
+            .is_some_and(|uuids| uuids.contains(uuid));
+
+        if !registered {
+            report.duplicate_map.push(Issue {
+                file: meta.data_file_name().clone(),
+                why: format!("{uuid} is missing"),
+            });
+        }
+    }
+
+    let metas = scan
+        .metas
+        .iter()
+        .filter(|(_, meta)| meta.storage() == super::StorageMode::Whole)
+        .map(|(uuid, meta)| (uuid, meta.data_file_name()))
+        .collect::<HashMap<&uuid::Uuid, &String>>();
+
+    for (hash, uuids) in duplicate_map.iter() {
+        for uuid in uuids {
+            if metas.get(uuid) != Some(&hash) {
+                report.duplicate_map.push(Issue {
+                    file: hash.clone(),
+                    why: format!("{uuid} is registered but has no matching meta"),
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Makes sure the manifest of a chunked entry lists existing chunks, adding up to the entry's size
+fn check_manifest(
+    config: &crate::config::StorageConfig,
+    scan: &super::recovery::Scan,
+    meta: &super::Metadata,
+    expected_chunks: &mut std::collections::HashMap<String, u64>,
+) -> Result<(), String> {
+    let name = meta.data_file_name();
+
+    if !scan.manifests.contains(name) {
+        return Err(format!("manifest '{name}' does not exist"));
+    }
+
+    let manifest = super::Manifest::from_file(&super::fs::data_path(config, name))
+        .map_err(|e| format!("manifest could not be read: {e}"))?;
+
+    let mut total = 0;
+    for chunk in manifest.chunks() {
+        if !scan.chunks.contains(chunk.hash()) {
+            return Err(format!("chunk '{}' does not exist", chunk.hash()));
+        }
+        total += chunk.size();
+        expected_chunks.insert(chunk.hash().clone(), chunk.size());
+    }
+
+    if total != meta.size().original() {
+        return Err(format!(
+            "manifest adds up to {total} bytes, expected {}",
+            meta.size().original()
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_chunk_map(
+    config: &crate::config::StorageConfig,
+    scan: &super::recovery::Scan,
+    report: &mut FsckReport,
+) {
+    let (references, errors) = super::recovery::chunk_references(config, &scan.metas);
+
+    if errors == 0 {
+        // Otherwise some of them are only unreferenced because of an unreadable manifest
+        report.unreferenced_chunks =
+            super::recovery::unreferenced_chunks(config, scan, &references)
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+    }
+
+    let chunk_map = super::ChunkMap::init_from_cache_dir(config);
+
+    for (hash, refs) in references.iter() {
+        let registered = chunk_map.get(hash).map(|info| info.refs()).unwrap_or(0);
+        if registered != *refs {
+            report.chunk_map.push(Issue {
+                file: hash.clone(),
+                why: format!("{registered} references registered, {refs} found"),
+            });
+        }
+    }
+
+    for (hash, _) in chunk_map.iter() {
+        if !references.contains_key(hash) {
+            report.chunk_map.push(Issue {
+                file: hash.clone(),
+                why: String::from("registered but no manifest lists it"),
+            });
+        }
+    }
+}
+
+/// Fixes what fsck found: the duplicate map is migrated if needed, corrupted data files and chunks
+/// (and the metas pointing to them) are quarantined, then the recovery pass cleans the rest
+pub fn repair(
+    config: &crate::config::StorageConfig,
+    report: &FsckReport,
+) -> Result<super::recovery::RecoveryReport, crate::error::CacheError> {
+    use std::collections::HashSet;
+
+    let corrupted = report
+        .corrupted_data
+        .iter()
+        .map(|issue| issue.file.as_str())
+        .collect::<HashSet<&str>>();
+
+    super::migration::migrate(config)?;
+
+    if !corrupted.is_empty() {
+        for (uuid, meta) in super::recovery::scan(config)?.metas {
+            let data_path = super::fs::data_path(config, meta.data_file_name());
+            if !corrupted.contains(data_path.display().to_string().as_str()) {
+                continue;
+            }
+
+            warn!("[{uuid}] Quarantining meta as its data file is corrupted");
+            super::fs::quarantine(config, &super::fs::meta_path(config, &uuid))?;
+        }
+
+        for file in corrupted {
+            super::fs::quarantine(config, std::path::Path::new(file))?;
+        }
+    }
+
+    super::recovery::recover(config)
+}
+
+/// Decrypts and decompresses the whole data file (or chunk), returning the hash of its original bytes and its original size
+pub fn check_data_file(
+    config: &crate::config::StorageConfig,
+    path: &std::path::Path,
+    dictionary: Option<&str>,
+) -> Result<(String, u64), String> {
+    use {
+        sha2::{Digest as _, Sha256},
+        std::io::Read as _,
+    };
+
+    let file = std::fs::File::open(path)
+        .and_then(|file| super::encryption::reader(file, config.master_key.as_ref()))
+        .map_err(|e| e.to_string())?;
+
+    let mut decoder =
+        super::dictionaries::decoder(config, file, dictionary).map_err(|e| e.to_string())?;
+
+    let mut hasher = Sha256::default();
+    let mut original_size = 0;
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = decoder
+            .read(&mut buffer)
+            .map_err(|e| format!("decompression failed: {e}"))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        original_size += read as u64;
+    }
+
+    Ok((format!("{:x}", hasher.finalize()), original_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::cache::fs,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_fsck_repair() {
+        let client = Client::tracked(crate::build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let mut uuids = Vec::new();
+        for content in ["Some notes", "Some notes", "Other notes"] {
+            let response = client
+                .put("/notes.txt")
+                .body(content)
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            let uuid = response.into_string().await.unwrap();
+            uuids.push(uuid::Uuid::parse_str(&uuid).unwrap());
+        }
+
+        let config = client
+            .rocket()
+            .state::<crate::config::StorageConfig>()
+            .unwrap();
+
+        let report = super::fsck(config).unwrap();
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(report.checked_metas, 3);
+        assert_eq!(report.checked_data_files, 2);
+
+        let stats = crate::cache::stats(config).unwrap();
+        assert_eq!((stats.entries, stats.data_files), (3, 2));
+        assert_eq!(stats.original_bytes, 10 + 10 + 11);
+
+        // Bit rot in the last one's data file
+        let data_file = fs::read_meta(config, &uuids[2])
+            .unwrap()
+            .data_file_name()
+            .clone();
+        std::fs::write(fs::data_path(config, &data_file), "not zstd").unwrap();
+
+        let report = super::fsck(config).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.corrupted_data.len(), 1);
+
+        // Quarantined with its meta, the others stay
+        let repaired = super::repair(config, &report).unwrap();
+        assert_eq!(repaired.errors, 0);
+        assert!(!fs::meta_path(config, &uuids[2]).exists());
+        assert!(fs::quarantine_dir(config).join(&data_file).exists());
+        assert!(fs::meta_path(config, &uuids[0]).exists());
+        assert!(fs::meta_path(config, &uuids[1]).exists());
+
+        let report = super::fsck(config).unwrap();
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(report.checked_metas, 2);
+    }
+}
@@ -0,0 +1,199 @@
+// Short ids and aliases, the other names an entry can be reached with (uuids are a pain to read out or type)
+//
+// Every new upload gets a random short id (SHORT_ID_LENGTH base62 characters), and can ask for an alias with the
+// 'X-Alias' header. Both are stored in the entry's meta (see UploadDetails) and share this index, built from the
+// entries at startup
+//
+// Names of deleted or evicted entries are dropped lazily: they don't resolve anymore and can be taken again
+// Uploads claim their names before being stored, they stay pending (taken, but not resolving) until stored or removed
+
+pub const SHORT_ID_LENGTH: usize = 8;
+
+const MIN_ALIAS_LENGTH: usize = 3;
+pub const MAX_ALIAS_LENGTH: usize = 64;
+
+// First segment of the server's own routes, an alias with these names could never be reached
+const RESERVED: &[&str] = &[
+    "404",
+    "contact",
+    "css",
+    "download",
+    "health",
+    "home",
+    "info",
+    "p",
+    "paste",
+    "resources",
+    "upload",
+];
+
+#[derive(Debug, Default)]
+pub struct IdMap {
+    names: dashmap::DashMap<String, uuid::Uuid>,
+    // Uploads with claimed names that are not in the cache yet, see stored and remove
+    pending: dashmap::DashSet<uuid::Uuid>,
+}
+
+impl IdMap {
+    pub fn init_from_cache(cache: &super::CacheEntryMap) -> Self {
+        let ids = Self::default();
+
+        for entry in cache.iter() {
+            let details = entry.upload_info().details();
+
+            for name in details.short_id.iter().chain(details.alias.iter()) {
+                if let Some(other) = ids.names.insert(name.clone(), entry.uuid()) {
+                    warn!("'{name}' is used by both {other} and {}", entry.uuid());
+                }
+            }
+        }
+
+        ids
+    }
+
+    // The uuid of the entry with that short id or alias, if it still exists
+    pub fn resolve(&self, name: &str, cache: &super::CacheEntryMap) -> Option<uuid::Uuid> {
+        let uuid = *self.names.get(name)?;
+
+        // Before the cache, entries are inserted in it before leaving the pending set
+        let pending = self.pending.contains(&uuid);
+
+        if cache.contains_key(&uuid) {
+            return Some(uuid);
+        }
+
+        if !pending {
+            self.release(name, uuid);
+        }
+
+        None
+    }
+
+    // Takes the name for that entry, false if another one has it
+    // Entries that are not in the cache yet are pending until stored or removed
+    pub fn claim(&self, name: &str, uuid: uuid::Uuid, cache: &super::CacheEntryMap) -> bool {
+        use dashmap::mapref::entry::Entry;
+
+        // While the name's shard is locked, so resolve never sees the name without it
+        let mark_pending = || {
+            if !cache.contains_key(&uuid) {
+                self.pending.insert(uuid);
+            }
+        };
+
+        match self.names.entry(name.to_string()) {
+            Entry::Occupied(mut occupied) => {
+                let owner = *occupied.get();
+
+                // Taken unless its entry is gone
+                if owner != uuid && (self.pending.contains(&owner) || cache.contains_key(&owner)) {
+                    return false;
+                }
+
+                mark_pending();
+                occupied.insert(uuid);
+                true
+            }
+            Entry::Vacant(vacant) => {
+                mark_pending();
+                vacant.insert(uuid);
+                true
+            }
+        }
+    }
+
+    // Picks an unused short id, and claims it
+    pub fn new_short_id(&self, uuid: uuid::Uuid, cache: &super::CacheEntryMap) -> String {
+        use rand::{distributions::Alphanumeric, Rng as _};
+
+        loop {
+            let short_id = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(SHORT_ID_LENGTH)
+                .map(char::from)
+                .collect::<String>();
+
+            if self.claim(&short_id, uuid, cache) {
+                break short_id;
+            }
+        }
+    }
+
+    // The entry is in the cache, its names resolve from now on
+    pub fn stored(&self, uuid: uuid::Uuid) {
+        self.pending.remove(&uuid);
+    }
+
+    // Only if it's still that entry's
+    pub fn release(&self, name: &str, uuid: uuid::Uuid) {
+        self.names.remove_if(name, |_name, owner| *owner == uuid);
+    }
+
+    // Every name of the entry, also when its upload failed
+    pub fn remove(&self, uuid: uuid::Uuid, details: &super::UploadDetails) {
+        for name in details.short_id.iter().chain(details.alias.iter()) {
+            self.release(name, uuid);
+        }
+        self.pending.remove(&uuid);
+    }
+}
+
+// What an alias can look like, so it's usable in urls and can't be mistaken for something else
+pub fn is_valid_alias(alias: &str) -> bool {
+    (MIN_ALIAS_LENGTH..=MAX_ALIAS_LENGTH).contains(&alias.len())
+        && alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        // Could be someone's short id
+        && alias.len() != SHORT_ID_LENGTH
+        // Could be a uuid (without hyphens, those are already refused)
+        && uuid::Uuid::try_parse(alias).is_err()
+        && !RESERVED.contains(&alias.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_id_map() {
+        use {
+            super::{is_valid_alias, IdMap, SHORT_ID_LENGTH},
+            uuid::Uuid,
+        };
+
+        let cache = crate::cache::CacheEntryMap::default();
+        let ids = IdMap::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let short_id = ids.new_short_id(first, &cache);
+        assert_eq!(short_id.len(), SHORT_ID_LENGTH);
+        assert!(short_id.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        // Still uploading, taken but not resolving
+        assert!(ids.claim("my-file", first, &cache));
+        assert!(ids.resolve("my-file", &cache).is_none());
+        assert!(!ids.claim("my-file", second, &cache));
+
+        // Stored and then evicted, so the name is free again
+        ids.stored(first);
+        assert!(ids.resolve("my-file", &cache).is_none());
+        assert!(ids.claim("my-file", second, &cache));
+
+        ids.release("my-file", first);
+        assert_eq!(ids.names.get("my-file").map(|owner| *owner), Some(second));
+
+        // Failed upload
+        let details = crate::cache::UploadDetails {
+            alias: Some(String::from("my-file")),
+            ..Default::default()
+        };
+        ids.remove(second, &details);
+        assert!(ids.claim("my-file", first, &cache));
+
+        assert!(is_valid_alias("release-notes_v2"));
+        assert!(!is_valid_alias("no"));
+        assert!(!is_valid_alias("with space"));
+        assert!(!is_valid_alias("Health"));
+        assert!(!is_valid_alias(&"a".repeat(SHORT_ID_LENGTH)));
+        assert!(!is_valid_alias(&Uuid::new_v4().simple().to_string()));
+    }
+}
@@ -0,0 +1,53 @@
+// Replaces the data file of an entry stored in chunked mode
+// Named after the entry's uuid with .manifest at the end
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    chunks: Vec<ChunkRef>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChunkRef {
+    hash: String,
+    // Before compression
+    size: u64,
+}
+
+impl Manifest {
+    pub fn push(&mut self, hash: String, size: u64) {
+        self.chunks.push(ChunkRef { hash, size });
+    }
+
+    pub fn chunks(&self) -> &[ChunkRef] {
+        &self.chunks
+    }
+
+    pub fn into_chunks(self) -> Vec<ChunkRef> {
+        self.chunks
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<Self, crate::error::CacheError> {
+        use {crate::error::CacheError, rocket::serde::json::serde_json};
+
+        let file = std::fs::File::open(path).map_err(|e| CacheError::FileOpen {
+            file: path.display().to_string(),
+            why: e,
+        })?;
+
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| {
+            CacheError::Deserialization {
+                file: path.display().to_string(),
+                why: e,
+            }
+        })
+    }
+}
+
+impl ChunkRef {
+    pub fn hash(&self) -> &String {
+        &self.hash
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
@@ -1,19 +1,83 @@
 // Structure of a .meta file
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+//
+// Metas are read through Metadata::from_reader, which brings older versions up to date first (see MIGRATIONS).
+// Adding an optional field only needs a #[serde(default)], anything else (renaming, moving or changing a field) needs a
+// new version and its migration
+//
+// Version history:
+//  1: Every meta written before versions existed, fields added over time are all optional
+//  2: Upload details (time, address, user agent, content type) and last access
+
+pub const VERSION: u32 = 2;
+
+// Migrations[n] brings a meta from version n + 1 to n + 2
+const MIGRATIONS: &[fn(
+    &mut rocket::serde::json::serde_json::Map<String, rocket::serde::json::Value>,
+)] = &[v1_to_v2];
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Metadata {
+    version: u32,
     name: String,
     extension: String,
     size: super::Size,
+    // Data file for whole entries, manifest for chunked ones
     data_file_name: String,
+    // Missing in metas written before the chunked mode existed
+    #[serde(default)]
+    storage: super::StorageMode,
+    // Id of the zstd dictionary the data file was compressed with, see dictionaries.rs
+    #[serde(default)]
+    dictionary: Option<String>,
+    // Opaque flag, the content was encrypted by the uploader's browser (see front/src/scene/upload.rs)
+    #[serde(default)]
+    client_encrypted: bool,
+    // Argon2 hash of the password required to download, see password.rs
+    #[serde(default)]
+    password_hash: Option<String>,
+    // Never evicted, see eviction.rs
+    #[serde(default)]
+    pinned: bool,
+    // Already went through the recompression job, see recompression.rs
+    #[serde(default)]
+    recompressed: bool,
+    #[serde(flatten)]
+    details: super::UploadDetails,
+    // Seconds since the epoch, only written once in a while, see CacheEntry::load
+    #[serde(default)]
+    last_access: Option<u64>,
+    // Seconds since the epoch, last change of the name or details (see routes/patch.rs)
+    #[serde(default)]
+    edited: Option<u64>,
+    // Number of edits, in the ETag as two edits can happen in the same second
+    #[serde(default)]
+    revision: u64,
 }
 
 impl Metadata {
-    pub fn new(name: String, extension: String, size: super::Size, data_file_name: String) -> Self {
+    pub fn new(
+        upload_info: &super::UploadInfo,
+        size: super::Size,
+        data_file_name: String,
+        storage: super::StorageMode,
+        dictionary: Option<String>,
+    ) -> Self {
         Self {
-            name,
-            extension,
+            version: VERSION,
+            name: upload_info.name().clone(),
+            extension: upload_info.extension().clone(),
             size,
             data_file_name,
+            storage,
+            dictionary,
+            client_encrypted: upload_info.client_encrypted(),
+            password_hash: upload_info.password_hash().map(str::to_string),
+            pinned: upload_info.pinned(),
+            recompressed: false,
+            details: upload_info.details().clone(),
+            last_access: None,
+            edited: None,
+            revision: 0,
         }
     }
 
@@ -27,8 +91,125 @@ impl Metadata {
     pub fn size(&self) -> &super::Size {
         &self.size
     }
+    pub fn set_size(&mut self, size: super::Size) {
+        self.size = size;
+    }
 
     pub fn data_file_name(&self) -> &String {
         &self.data_file_name
     }
+    pub fn set_data_file_name(&mut self, data_file_name: String) {
+        self.data_file_name = data_file_name;
+    }
+
+    pub fn storage(&self) -> super::StorageMode {
+        self.storage
+    }
+
+    pub fn dictionary(&self) -> Option<&str> {
+        self.dictionary.as_deref()
+    }
+
+    pub fn client_encrypted(&self) -> bool {
+        self.client_encrypted
+    }
+
+    pub fn password_hash(&self) -> Option<&str> {
+        self.password_hash.as_deref()
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
+    pub fn recompressed(&self) -> bool {
+        self.recompressed
+    }
+    pub fn set_recompressed(&mut self, recompressed: bool) {
+        self.recompressed = recompressed;
+    }
+
+    pub fn details(&self) -> &super::UploadDetails {
+        &self.details
+    }
+
+    pub fn last_access(&self) -> Option<u64> {
+        self.last_access
+    }
+    pub fn set_last_access(&mut self, last_access: u64) {
+        self.last_access = Some(last_access);
+    }
+
+    pub fn edited(&self) -> Option<u64> {
+        self.edited
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    // What an edit can change, the password and encryption stay
+    pub fn edit(&mut self, upload_info: &super::UploadInfo, edited: u64) {
+        self.name = upload_info.name().clone();
+        self.extension = upload_info.extension().clone();
+        self.pinned = upload_info.pinned();
+        self.details = upload_info.details().clone();
+        self.edited = Some(edited);
+        self.revision += 1;
+    }
+
+    // Reads a meta of any version, migrated to the current one
+    pub fn from_reader(
+        reader: impl std::io::Read,
+    ) -> Result<Self, rocket::serde::json::serde_json::Error> {
+        use {rocket::serde::json::serde_json, serde::de::Error as _};
+
+        let mut value = serde_json::from_reader::<_, serde_json::Value>(reader)?;
+
+        let Some(fields) = value.as_object_mut() else {
+            return Err(serde_json::Error::custom("a meta should be an object"));
+        };
+
+        let version = match fields.get("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| serde_json::Error::custom("invalid version"))?
+                as u32,
+            None => 1,
+        };
+
+        if version == 0 || version > VERSION {
+            return Err(serde_json::Error::custom(format!(
+                "unsupported version {version}, this build reads up to {VERSION}"
+            )));
+        }
+
+        for migration in &MIGRATIONS[version as usize - 1..] {
+            migration(fields);
+        }
+        fields.insert(String::from("version"), VERSION.into());
+
+        serde_json::from_value(value)
+    }
+}
+
+// Only optional fields were added, nothing to move
+fn v1_to_v2(_: &mut rocket::serde::json::serde_json::Map<String, rocket::serde::json::Value>) {}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_metadata_migration() {
+        // Written before versions existed, with the upload time but none of the other details
+        let v1 = r#"{"name":"notes","extension":"txt","size":{"original":10,"compressed":19},"data_file_name":"abc","upload_time":1700000000}"#;
+
+        let metadata = super::Metadata::from_reader(v1.as_bytes()).unwrap();
+        assert_eq!(metadata.version, super::VERSION);
+        assert_eq!(metadata.details().upload_time, Some(1700000000));
+        assert_eq!(metadata.details().user_agent, None);
+        assert_eq!(metadata.last_access(), None);
+
+        let newer = r#"{"version":999,"name":"notes"}"#;
+        assert!(super::Metadata::from_reader(newer.as_bytes()).is_err());
+    }
 }
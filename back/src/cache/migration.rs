@@ -0,0 +1,186 @@
+// Data files used to be named after the hash of their compressed bytes,
+// they are now named after the hash of the original bytes (see stream_to_file)
+//
+// Each data file is renamed through a hard link, so that a crash at any point leaves
+// either the old or the new name valid for every meta, and running the migration again finishes the job
+
+/// Returns the amount of renamed data files
+pub fn migrate(config: &crate::config::StorageConfig) -> Result<usize, crate::error::CacheError> {
+    use {crate::error::CacheError, std::collections::HashMap};
+
+    let mut duplicate_map = super::DuplicateMap::init_from_cache_dir(config);
+
+    if !duplicate_map.is_outdated() {
+        return Ok(0);
+    }
+
+    let scan = super::recovery::scan(config)?;
+
+    let mut metas_by_data_file = HashMap::<&String, Vec<&uuid::Uuid>>::new();
+    for (uuid, meta) in scan.metas.iter() {
+        metas_by_data_file
+            .entry(meta.data_file_name())
+            .or_default()
+            .push(uuid);
+    }
+
+    let mut migrated = 0;
+
+    for old_name in scan.data_files.iter() {
+        let old_path = super::fs::data_path(config, old_name);
+
+        // Dictionaries came after this migration, so none of these files use one
+        let new_name = match super::fsck::check_data_file(config, &old_path, None) {
+            Ok((hash, _)) => hash,
+            Err(why) => {
+                // Leave it as is, fsck will report it
+                error!("Could not migrate '{}' due to: {why}", old_path.display());
+                continue;
+            }
+        };
+
+        if &new_name == old_name {
+            continue;
+        }
+
+        let new_path = super::fs::data_path(config, &new_name);
+
+        // If it exists, that's the same content stored with different compression settings
+        if !new_path.exists() {
+            std::fs::hard_link(&old_path, &new_path).map_err(|e| CacheError::FileRename {
+                file: old_path.display().to_string(),
+                why: e,
+            })?;
+        }
+
+        for uuid in metas_by_data_file.get(old_name).into_iter().flatten() {
+            let meta_path = super::fs::meta_path(config, uuid);
+            let file = std::fs::File::open(&meta_path).map_err(|e| CacheError::FileOpen {
+                file: meta_path.display().to_string(),
+                why: e,
+            })?;
+            let mut metadata = super::Metadata::from_reader(std::io::BufReader::new(file))
+                .map_err(|e| CacheError::Deserialization {
+                    file: meta_path.display().to_string(),
+                    why: e,
+                })?;
+
+            metadata.set_data_file_name(new_name.clone());
+            super::fs::write_meta(config, uuid, &metadata)?;
+        }
+
+        std::fs::remove_file(&old_path).map_err(|e| CacheError::FileRemove {
+            file: old_path.display().to_string(),
+            why: e,
+        })?;
+
+        migrated += 1;
+    }
+
+    // Rebuild the keys from the now updated metas
+    let rebuilt = super::recovery::scan(config)?
+        .metas
+        .into_iter()
+        .filter(|(_, meta)| meta.storage() == super::StorageMode::Whole)
+        .map(|(uuid, meta)| (meta.data_file_name().clone(), uuid));
+
+    duplicate_map.set_up_to_date();
+    duplicate_map.reconcile(rebuilt)?;
+    duplicate_map.write_to_file()?;
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::cache::fs,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_migration() {
+        use sha2::{Digest as _, Sha256};
+
+        let client = Client::tracked(
+            crate::build_test_rocket_from(
+                rocket::Config::figment().merge(("hot_cache.capacity", 0)),
+            )
+            .await,
+        )
+        .await
+        .expect("valid rocket instance");
+
+        let mut uuids = Vec::new();
+        for name in ["notes.txt", "copy.txt"] {
+            let response = client
+                .put(format!("/{name}"))
+                .body("Some notes")
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            let uuid = response.into_string().await.unwrap();
+            uuids.push(uuid::Uuid::parse_str(&uuid).unwrap());
+        }
+
+        let config = client
+            .rocket()
+            .state::<crate::config::StorageConfig>()
+            .unwrap();
+
+        // Back to how it was stored before: named after the compressed bytes, in a bare map
+        let new_name = fs::read_meta(config, &uuids[0])
+            .unwrap()
+            .data_file_name()
+            .clone();
+        let compressed = std::fs::read(fs::data_path(config, &new_name)).unwrap();
+        let old_name = format!("{:x}", Sha256::digest(&compressed));
+        std::fs::rename(
+            fs::data_path(config, &new_name),
+            fs::data_path(config, &old_name),
+        )
+        .unwrap();
+        for uuid in uuids.iter() {
+            let mut metadata = fs::read_meta(config, uuid).unwrap();
+            metadata.set_data_file_name(old_name.clone());
+            fs::write_meta(config, uuid, &metadata).unwrap();
+        }
+        std::fs::write(
+            fs::duplicates_path(config),
+            format!(r#"{{"{old_name}":["{}","{}"]}}"#, uuids[0], uuids[1]),
+        )
+        .unwrap();
+        assert!(crate::cache::DuplicateMap::init_from_cache_dir(config).is_outdated());
+
+        assert_eq!(super::migrate(config).unwrap(), 1);
+
+        assert!(fs::data_path(config, &new_name).exists());
+        assert!(!fs::data_path(config, &old_name).exists());
+
+        let duplicate_map = crate::cache::DuplicateMap::init_from_cache_dir(config);
+        assert!(!duplicate_map.is_outdated());
+        assert!(duplicate_map.get(&old_name).is_none());
+        for uuid in uuids.iter() {
+            assert_eq!(
+                fs::read_meta(config, uuid).unwrap().data_file_name(),
+                &new_name
+            );
+            assert!(duplicate_map.get(&new_name).unwrap().contains(uuid));
+
+            let response = client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_string().await.unwrap(), "Some notes");
+        }
+
+        // Nothing left to do
+        assert_eq!(super::migrate(config).unwrap(), 0);
+    }
+}
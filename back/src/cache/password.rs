@@ -0,0 +1,37 @@
+// Password protected entries store an Argon2 hash of the password in their meta file (PHC string format,
+// so the salt and parameters are stored with it)
+//
+// Hashing is slow on purpose, so these should be called with spawn_blocking from async code
+
+pub fn hash(password: &str) -> Result<String, crate::error::CacheError> {
+    use {
+        crate::error::CacheError,
+        argon2::{
+            password_hash::{PasswordHasher as _, SaltString},
+            Argon2,
+        },
+    };
+
+    let salt = SaltString::generate(rand::rngs::OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| CacheError::PasswordHash { why: e.to_string() })
+}
+
+pub fn verify(hash: &str, password: &str) -> bool {
+    use argon2::{
+        password_hash::{PasswordHash, PasswordVerifier as _},
+        Argon2,
+    };
+
+    let Ok(hash) = PasswordHash::new(hash) else {
+        error!("Could not parse a stored password hash");
+        return false;
+    };
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
@@ -0,0 +1,500 @@
+// Uploads are compressed fast (storage.compression_level), which is a waste for files nobody downloads anymore
+//
+// This job goes through the whole data files that every holder left cold (no download for recompression.cold_after,
+// see CacheEntry::last_access) and recompresses them with recompression.compression_level and long distance matching,
+// keeping the dictionary they were made with. Chunked entries are left alone, their chunks are shared
+//
+// The new file is made next to the old one, then swapped in with a rename while holding every holder's file lock.
+// Holders being downloaded are skipped and retried on the next run, so downloads never wait on the job.
+// Every holder's meta then gets the new compressed size and is flagged, so it's only done once
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RecompressionReport {
+    pub recompressed: usize,
+    // Not smaller at the higher level, kept as is
+    pub unchanged: usize,
+    // Downloaded while it was recompressed
+    pub busy: usize,
+    pub errors: usize,
+    // Sizes of the recompressed files
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl RecompressionReport {
+    pub fn reclaimed(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+impl std::fmt::Display for RecompressionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use rocket::data::ByteUnit;
+
+        write!(
+            f,
+            "recompressed {} files ({} -> {}, reclaimed {}), {} were not smaller, {} were busy ({} errors)",
+            self.recompressed,
+            ByteUnit::Byte(self.bytes_before),
+            ByteUnit::Byte(self.bytes_after),
+            ByteUnit::Byte(self.reclaimed()),
+            self.unchanged,
+            self.busy,
+            self.errors,
+        )
+    }
+}
+
+// Runs the job every recompression.interval, on the blocking pool
+pub async fn run_periodically(
+    config: crate::config::StorageConfig,
+    recompression: crate::config::RecompressionConfig,
+    cache: std::sync::Arc<super::CacheEntryMap>,
+    duplicate_map: std::sync::Arc<rocket::tokio::sync::Mutex<super::DuplicateMap>>,
+) {
+    use std::time::{Duration, Instant};
+
+    if recompression.interval == 0 {
+        return;
+    }
+
+    loop {
+        rocket::tokio::time::sleep(Duration::from_secs(recompression.interval)).await;
+
+        // Not held during the run, uploads need it
+        let holders = duplicate_map
+            .lock()
+            .await
+            .iter()
+            .map(|(hash, uuids)| (hash.clone(), uuids.to_vec()))
+            .collect();
+
+        let config = config.clone();
+        let cache = std::sync::Arc::clone(&cache);
+        let start = Instant::now();
+
+        match rocket::tokio::task::spawn_blocking(move || {
+            recompress(&config, &recompression, &cache, holders)
+        })
+        .await
+        {
+            Ok(report) => info!(
+                "Recompression: {report} in {}",
+                time::format(start.elapsed(), 1)
+            ),
+            Err(e) => error!("Recompression job failed due to: {e}"),
+        }
+    }
+}
+
+// Blocking, runs until every cold data file went through
+// Holders are the uuids using each data file, from the duplicate map
+pub fn recompress(
+    config: &crate::config::StorageConfig,
+    recompression: &crate::config::RecompressionConfig,
+    cache: &super::CacheEntryMap,
+    holders: Vec<(String, Vec<uuid::Uuid>)>,
+) -> RecompressionReport {
+    use super::StorageMode;
+
+    let mut report = RecompressionReport::default();
+
+    let cold_before = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .saturating_sub(std::time::Duration::from_secs(recompression.cold_after))
+        .as_millis() as u64;
+
+    for (hash, uuids) in holders {
+        let cold = uuids.iter().all(|uuid| {
+            cache
+                .get(uuid)
+                .is_some_and(|entry| entry.last_access() <= cold_before)
+        });
+
+        if uuids.is_empty() || !cold {
+            continue;
+        }
+
+        let Some(metadata) = cache.get(&uuids[0]).map(|entry| entry.load_meta(config)) else {
+            // Deleted in the meantime
+            continue;
+        };
+
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                error!(
+                    "[{}] Could not check for recompression due to: {e}",
+                    uuids[0]
+                );
+                report.errors += 1;
+                continue;
+            }
+        };
+
+        if metadata.storage() != StorageMode::Whole
+            || metadata.recompressed()
+            || *metadata.data_file_name() != hash
+        {
+            continue;
+        }
+
+        if let Err(e) = recompress_data_file(
+            config,
+            recompression,
+            cache,
+            &hash,
+            &uuids,
+            metadata.dictionary(),
+            &mut report,
+        ) {
+            error!("Failed to recompress {hash} due to: {e}");
+            report.errors += 1;
+        }
+    }
+
+    report
+}
+
+fn recompress_data_file(
+    config: &crate::config::StorageConfig,
+    recompression: &crate::config::RecompressionConfig,
+    cache: &super::CacheEntryMap,
+    hash: &str,
+    uuids: &[uuid::Uuid],
+    dictionary: Option<&str>,
+    report: &mut RecompressionReport,
+) -> Result<(), crate::error::CacheError> {
+    use {crate::error::CacheError, rocket::data::ByteUnit};
+
+    let data_path = super::fs::data_path(config, hash);
+    let temp_path = super::fs::temp_recompressed_path(config, hash);
+
+    let remove_temp = || {
+        if let Err(e) = std::fs::remove_file(&temp_path) {
+            error!("Failed to remove '{}' due to: {e}", temp_path.display());
+        }
+    };
+
+    let (before, after) =
+        match write_recompressed(config, recompression, &data_path, &temp_path, dictionary) {
+            Ok(sizes) => sizes,
+            Err(e) => {
+                remove_temp();
+                return Err(e);
+            }
+        };
+
+    // Every holder, or none
+    let locks = uuids
+        .iter()
+        .map(|uuid| cache.get(uuid).and_then(|entry| entry.try_lock_files()))
+        .collect::<Option<Vec<_>>>();
+
+    let Some(locks) = locks else {
+        // Being downloaded, or deleted in the meantime
+        remove_temp();
+        report.busy += 1;
+        return Ok(());
+    };
+
+    // The last holder could have been deleted before we got the locks
+    if !data_path.exists() {
+        remove_temp();
+        return Ok(());
+    }
+
+    let smaller = after < before;
+
+    if smaller {
+        if let Err(e) = std::fs::rename(&temp_path, &data_path) {
+            remove_temp();
+            return Err(CacheError::FileRename {
+                file: temp_path.display().to_string(),
+                why: e,
+            });
+        }
+    } else {
+        remove_temp();
+    }
+
+    // The data file is already swapped, so keep going with the other metas on errors
+    let mut errors = Vec::new();
+    for uuid in uuids {
+        // Being deleted, the meta will be removed anyway
+        let Some(metadata) = cache.get(uuid).map(|entry| entry.load_meta(config)) else {
+            continue;
+        };
+
+        let updated = metadata.and_then(|mut metadata| {
+            if smaller {
+                metadata.set_size(super::Size::new(metadata.size().original(), after));
+            }
+            metadata.set_recompressed(true);
+            super::fs::write_meta(config, uuid, &metadata).map(|_| *metadata.size())
+        });
+
+        match updated {
+            Ok(size) => {
+                if let Some(mut entry) = cache.get_mut(uuid) {
+                    entry.set_size(size)
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    drop(locks);
+
+    if smaller {
+        debug!(
+            "Recompressed {hash} ({} -> {})",
+            ByteUnit::Byte(before),
+            ByteUnit::Byte(after)
+        );
+        report.recompressed += 1;
+        report.bytes_before += before;
+        report.bytes_after += after;
+    } else {
+        report.unchanged += 1;
+    }
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(CacheError::Multiple(errors)),
+    }
+}
+
+// Writes the recompressed version of the given data file, returns the old and new file sizes
+fn write_recompressed(
+    config: &crate::config::StorageConfig,
+    recompression: &crate::config::RecompressionConfig,
+    data_path: &std::path::Path,
+    temp_path: &std::path::Path,
+    dictionary: Option<&str>,
+) -> Result<(u64, u64), crate::error::CacheError> {
+    use {crate::error::CacheError, zstd::stream::Encoder};
+
+    let file = std::fs::File::open(data_path).map_err(|e| CacheError::FileOpen {
+        file: data_path.display().to_string(),
+        why: e,
+    })?;
+    let before = file
+        .metadata()
+        .map_err(|e| CacheError::FileRead {
+            file: data_path.display().to_string(),
+            why: e,
+        })?
+        .len();
+
+    let file = super::encryption::reader(
+        Throttled::new(file, recompression),
+        config.master_key.as_ref(),
+    )
+    .map_err(|e| CacheError::FileRead {
+        file: data_path.display().to_string(),
+        why: e,
+    })?;
+    let mut decoder = super::dictionaries::decoder(config, file, dictionary)?;
+
+    let dictionary_data = dictionary
+        .map(|id| super::dictionaries::load(config, id))
+        .transpose()?;
+
+    let temp_file = std::fs::File::create(temp_path).map_err(|e| CacheError::FileCreate {
+        file: temp_path.display().to_string(),
+        why: e,
+    })?;
+
+    let temp_file = (|| {
+        let temp_file = super::encryption::Writer::new(temp_file, config.master_key.as_ref())?;
+
+        let mut encoder = match dictionary_data.as_deref() {
+            Some(dictionary) => {
+                Encoder::with_dictionary(temp_file, recompression.compression_level, dictionary)
+            }
+            None => Encoder::new(temp_file, recompression.compression_level),
+        }?;
+
+        encoder.long_distance_matching(recompression.long_distance_matching)?;
+        if recompression.workers != 0 {
+            encoder.multithread(recompression.workers)?;
+        }
+
+        std::io::copy(&mut decoder, &mut encoder)?;
+
+        let temp_file = encoder
+            .finish()
+            .and_then(super::encryption::Writer::finish)?;
+        temp_file.sync_all()?;
+
+        Ok(temp_file)
+    })()
+    .map_err(|e| CacheError::Compression { why: e })?;
+
+    let after = temp_file
+        .metadata()
+        .map_err(|e| CacheError::FileRead {
+            file: temp_path.display().to_string(),
+            why: e,
+        })?
+        .len();
+
+    Ok((before, after))
+}
+
+// Sleeps between reads to stay under recompression.bytes_per_second and recompression.cpu_percent
+// Compression happens between two reads, so the time spent out of read() is (roughly) the time spent working
+struct Throttled<R> {
+    inner: R,
+    bytes_per_second: u64,
+    cpu_share: f64,
+    start: std::time::Instant,
+    slept: std::time::Duration,
+    total_read: u64,
+}
+
+impl<R> Throttled<R> {
+    fn new(inner: R, recompression: &crate::config::RecompressionConfig) -> Self {
+        Self {
+            inner,
+            bytes_per_second: recompression.bytes_per_second.as_u64(),
+            cpu_share: recompression.cpu_percent as f64 / 100.,
+            start: std::time::Instant::now(),
+            slept: std::time::Duration::ZERO,
+            total_read: 0,
+        }
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::time::Duration;
+
+        let read = self.inner.read(buf)?;
+        self.total_read += read as u64;
+
+        let elapsed = self.start.elapsed();
+
+        // How long it should have taken so far
+        let mut target = elapsed.saturating_sub(self.slept).div_f64(self.cpu_share);
+        if self.bytes_per_second != 0 {
+            target = target.max(Duration::from_secs_f64(
+                self.total_read as f64 / self.bytes_per_second as f64,
+            ));
+        }
+
+        if let Some(wait) = target.checked_sub(elapsed) {
+            std::thread::sleep(wait);
+            self.slept += wait;
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::cache::fs,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_recompression() {
+        let client = Client::tracked(
+            crate::build_test_rocket_from(
+                rocket::Config::figment()
+                    .merge(("storage.compression_level", 1))
+                    .merge(("hot_cache.capacity", 0)),
+            )
+            .await,
+        )
+        .await
+        .expect("valid rocket instance");
+
+        // Compresses a lot better at a higher level
+        let words = ["storage", "server", "cache", "entry", "upload", "download"];
+        let content = (0..50_000)
+            .map(|_| words[rand::random::<usize>() % words.len()])
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        let response = client
+            .put("/words.txt")
+            .body(&content)
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = uuid::Uuid::parse_str(&response.into_string().await.unwrap()).unwrap();
+
+        let rocket = client.rocket();
+        let config = rocket.state::<crate::config::StorageConfig>().unwrap();
+        let cache = rocket
+            .state::<std::sync::Arc<crate::cache::CacheEntryMap>>()
+            .unwrap();
+        let holders = || async {
+            rocket
+                .state::<std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::DuplicateMap>>>()
+                .unwrap()
+                .lock()
+                .await
+                .iter()
+                .map(|(hash, uuids)| (hash.clone(), uuids.to_vec()))
+                .collect::<Vec<_>>()
+        };
+
+        // Everything is cold right away, without throttling
+        let recompression = crate::config::RecompressionConfig {
+            cold_after: 0,
+            bytes_per_second: rocket::data::ByteUnit::Byte(0),
+            cpu_percent: 100,
+            ..Default::default()
+        };
+
+        let before = cache.get(&uuid).unwrap().size();
+        let data_path = fs::data_path(
+            config,
+            fs::read_meta(config, &uuid).unwrap().data_file_name(),
+        );
+        assert_eq!(
+            std::fs::metadata(&data_path).unwrap().len(),
+            before.compressed()
+        );
+
+        let report = super::recompress(config, &recompression, cache, holders().await);
+        assert_eq!((report.recompressed, report.errors), (1, 0));
+
+        // Swapped in place, with the new size in memory and in the meta
+        let after = cache.get(&uuid).unwrap().size();
+        assert_eq!(after.original(), before.original());
+        assert!(after.compressed() < before.compressed());
+        assert_eq!(report.bytes_after, after.compressed());
+        assert_eq!(
+            std::fs::metadata(&data_path).unwrap().len(),
+            after.compressed()
+        );
+
+        let meta = fs::read_meta(config, &uuid).unwrap();
+        assert_eq!(meta.size().compressed(), after.compressed());
+        assert!(meta.recompressed());
+
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), content);
+
+        // Only done once
+        let report = super::recompress(config, &recompression, cache, holders().await);
+        assert_eq!((report.recompressed, report.unchanged), (0, 0));
+    }
+}
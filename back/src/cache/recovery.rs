@@ -0,0 +1,511 @@
+// An upload killed mid-stream leaves its .temp_data (or .temp_chunk) files and an empty .meta file behind,
+// and nothing ever removes a data file (or chunk) whose last meta is gone.
+// This pass runs at boot, before the cache list is loaded, and brings the cache directory back to a coherent state
+
+/// What's in the cache directory, sorted by file type
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub temp_files: Vec<std::path::PathBuf>,
+    pub empty_metas: Vec<std::path::PathBuf>,
+    // Path and reason
+    pub unparsable_metas: Vec<(std::path::PathBuf, String)>,
+    pub metas: Vec<(uuid::Uuid, super::Metadata)>,
+    pub data_files: std::collections::HashSet<String>,
+    pub manifests: std::collections::HashSet<String>,
+    // In the chunks directory
+    pub chunks: std::collections::HashSet<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RecoveryReport {
+    pub removed_temp_files: usize,
+    pub removed_empty_metas: usize,
+    pub quarantined_metas: usize,
+    pub missing_data: usize,
+    pub removed_orphan_data: usize,
+    pub duplicate_map_fixes: usize,
+    pub removed_orphan_chunks: usize,
+    pub chunk_map_fixes: usize,
+    pub errors: usize,
+}
+
+impl RecoveryReport {
+    pub fn is_clean(&self) -> bool {
+        self.removed_temp_files == 0
+            && self.removed_empty_metas == 0
+            && self.quarantined_metas == 0
+            && self.missing_data == 0
+            && self.removed_orphan_data == 0
+            && self.duplicate_map_fixes == 0
+            && self.removed_orphan_chunks == 0
+            && self.chunk_map_fixes == 0
+            && self.errors == 0
+    }
+}
+
+impl std::fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_clean() {
+            return write!(f, "nothing to fix");
+        }
+
+        write!(
+            f,
+            "removed {} temp files, removed {} empty metas, quarantined {} unparsable metas, quarantined {} metas with missing data, removed {} orphan data files, fixed {} duplicate map entries, removed {} orphan chunks, fixed {} chunk map entries ({} errors)",
+            self.removed_temp_files,
+            self.removed_empty_metas,
+            self.quarantined_metas,
+            self.missing_data,
+            self.removed_orphan_data,
+            self.duplicate_map_fixes,
+            self.removed_orphan_chunks,
+            self.chunk_map_fixes,
+            self.errors,
+        )
+    }
+}
+
+pub fn scan(config: &crate::config::StorageConfig) -> Result<Scan, crate::error::CacheError> {
+    use {std::str::FromStr as _, uuid::Uuid};
+
+    let mut scan = Scan::default();
+
+    for entry in super::fs::read_cache_dir(config)?.flatten() {
+        let path = entry.path();
+
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("temp_data" | "temp_meta" | "temp_json") => scan.temp_files.push(path),
+            Some("meta") if metadata.len() == 0 => scan.empty_metas.push(path),
+            Some("meta") => {
+                let Some(uuid) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|s| Uuid::from_str(s).ok())
+                else {
+                    scan.unparsable_metas
+                        .push((path, String::from("file name is not an uuid")));
+                    continue;
+                };
+
+                let parsed = std::fs::File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        super::Metadata::from_reader(std::io::BufReader::new(file))
+                            .map_err(|e| e.to_string())
+                    });
+
+                match parsed {
+                    Ok(meta) => scan.metas.push((uuid, meta)),
+                    Err(why) => scan.unparsable_metas.push((path, why)),
+                }
+            }
+            Some("manifest") => {
+                scan.manifests.insert(file_name.to_string());
+            }
+            None if super::fs::is_data_file_name(file_name) => {
+                scan.data_files.insert(file_name.to_string());
+            }
+            _ => (),
+        }
+    }
+
+    // Only exists once something was stored in chunked mode
+    let Ok(chunks_dir) = std::fs::read_dir(super::fs::chunks_dir(config)) else {
+        return Ok(scan);
+    };
+
+    for entry in chunks_dir.flatten() {
+        let path = entry.path();
+
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("temp_chunk") => scan.temp_files.push(path),
+            None if super::fs::is_data_file_name(file_name) => {
+                scan.chunks.insert(file_name.to_string());
+            }
+            _ => (),
+        }
+    }
+
+    Ok(scan)
+}
+
+/// Cleans up what interrupted uploads left and makes the duplicate and chunk maps match the files
+pub fn recover(
+    config: &crate::config::StorageConfig,
+) -> Result<RecoveryReport, crate::error::CacheError> {
+    let mut scan = scan(config)?;
+    let mut report = RecoveryReport::default();
+
+    remove_all(
+        &scan.temp_files,
+        &mut report.removed_temp_files,
+        &mut report.errors,
+    );
+    remove_all(
+        &scan.empty_metas,
+        &mut report.removed_empty_metas,
+        &mut report.errors,
+    );
+
+    for (path, why) in std::mem::take(&mut scan.unparsable_metas) {
+        warn!(
+            "Quarantining '{}' as it could not be parsed: {why}",
+            path.display()
+        );
+        match super::fs::quarantine(config, &path) {
+            Ok(_) => report.quarantined_metas += 1,
+            Err(e) => {
+                error!("{e}");
+                report.errors += 1;
+            }
+        }
+    }
+
+    let mut valid_metas = Vec::with_capacity(scan.metas.len());
+    for (uuid, meta) in std::mem::take(&mut scan.metas) {
+        let Err(why) = check_data(config, &scan, &meta) else {
+            valid_metas.push((uuid, meta));
+            continue;
+        };
+
+        warn!("[{uuid}] Quarantining meta as {why}");
+        match super::fs::quarantine(config, &super::fs::meta_path(config, &uuid)) {
+            Ok(_) => report.missing_data += 1,
+            Err(e) => {
+                error!("{e}");
+                report.errors += 1;
+            }
+        }
+    }
+
+    remove_all(
+        &unreferenced_data(config, &scan, &valid_metas),
+        &mut report.removed_orphan_data,
+        &mut report.errors,
+    );
+
+    report.duplicate_map_fixes = reconcile_duplicate_map(config, &valid_metas)?;
+
+    clean_up_chunks(config, &scan, &valid_metas, &mut report)?;
+
+    Ok(report)
+}
+
+/// Only removes what's safe to remove: temp files, empty metas and data files (or chunks) no meta points to
+pub fn collect_garbage(
+    config: &crate::config::StorageConfig,
+) -> Result<RecoveryReport, crate::error::CacheError> {
+    let scan = scan(config)?;
+    let mut report = RecoveryReport::default();
+
+    remove_all(
+        &scan.temp_files,
+        &mut report.removed_temp_files,
+        &mut report.errors,
+    );
+    remove_all(
+        &scan.empty_metas,
+        &mut report.removed_empty_metas,
+        &mut report.errors,
+    );
+    remove_all(
+        &unreferenced_data(config, &scan, &scan.metas),
+        &mut report.removed_orphan_data,
+        &mut report.errors,
+    );
+
+    // Removing a chunk means fixing the chunk map, or a later upload would reference it
+    clean_up_chunks(config, &scan, &scan.metas, &mut report)?;
+
+    Ok(report)
+}
+
+/// Rebuilds the duplicate and chunk maps from the meta files, without removing anything
+/// Returns the amount of fixed entries
+pub fn rebuild_index(
+    config: &crate::config::StorageConfig,
+) -> Result<usize, crate::error::CacheError> {
+    let scan = scan(config)?;
+
+    let valid_metas = scan
+        .metas
+        .iter()
+        .filter(|(_, meta)| check_data(config, &scan, meta).is_ok())
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let (references, _) = chunk_references(config, &valid_metas);
+
+    Ok(reconcile_duplicate_map(config, &valid_metas)?
+        + reconcile_chunk_map(config, &scan, &references)?)
+}
+
+/// Data files and manifests that no meta points to
+pub fn unreferenced_data(
+    config: &crate::config::StorageConfig,
+    scan: &Scan,
+    metas: &[(uuid::Uuid, super::Metadata)],
+) -> Vec<std::path::PathBuf> {
+    use std::collections::HashSet;
+
+    let referenced = metas
+        .iter()
+        .map(|(_, meta)| meta.data_file_name().as_str())
+        .collect::<HashSet<&str>>();
+
+    scan.data_files
+        .iter()
+        .chain(scan.manifests.iter())
+        .filter(|name| !referenced.contains(name.as_str()))
+        .map(|name| super::fs::data_path(config, name))
+        .collect()
+}
+
+/// Chunks that no manifest lists
+pub fn unreferenced_chunks(
+    config: &crate::config::StorageConfig,
+    scan: &Scan,
+    references: &std::collections::HashMap<String, u32>,
+) -> Vec<std::path::PathBuf> {
+    scan.chunks
+        .iter()
+        .filter(|hash| !references.contains_key(*hash))
+        .map(|hash| super::fs::chunk_path(config, hash))
+        .collect()
+}
+
+/// Counts the references to each chunk, one per occurence in the manifests of the given metas
+/// Also returns the amount of manifests that could not be read
+pub fn chunk_references(
+    config: &crate::config::StorageConfig,
+    metas: &[(uuid::Uuid, super::Metadata)],
+) -> (std::collections::HashMap<String, u32>, usize) {
+    let mut references = std::collections::HashMap::<String, u32>::new();
+    let mut errors = 0;
+
+    for (uuid, meta) in metas {
+        if meta.storage() != super::StorageMode::Chunked {
+            continue;
+        }
+
+        match super::Manifest::from_file(&super::fs::data_path(config, meta.data_file_name())) {
+            Ok(manifest) => {
+                for chunk in manifest.chunks() {
+                    *references.entry(chunk.hash().clone()).or_default() += 1;
+                }
+            }
+            Err(e) => {
+                error!("[{uuid}] Could not read manifest due to: {e}");
+                errors += 1;
+            }
+        }
+    }
+
+    (references, errors)
+}
+
+// Makes sure the data a meta points to is there, for chunked entries, that's the manifest and every chunk it lists
+fn check_data(
+    config: &crate::config::StorageConfig,
+    scan: &Scan,
+    meta: &super::Metadata,
+) -> Result<(), String> {
+    let name = meta.data_file_name();
+
+    if meta.storage() == super::StorageMode::Whole {
+        if !scan.data_files.contains(name) {
+            return Err(format!("its data file '{name}' does not exist"));
+        }
+        if let Some(id) = meta.dictionary() {
+            if !super::fs::dictionary_path(config, id).exists() {
+                return Err(format!("its dictionary '{id}' does not exist"));
+            }
+        }
+        return Ok(());
+    }
+
+    if !scan.manifests.contains(name) {
+        return Err(format!("its manifest '{name}' does not exist"));
+    }
+
+    let manifest = super::Manifest::from_file(&super::fs::data_path(config, name))
+        .map_err(|e| format!("its manifest could not be read: {e}"))?;
+
+    if let Some(missing) = manifest
+        .chunks()
+        .iter()
+        .find(|chunk| !scan.chunks.contains(chunk.hash()))
+    {
+        return Err(format!("its chunk '{}' does not exist", missing.hash()));
+    }
+
+    Ok(())
+}
+
+fn clean_up_chunks(
+    config: &crate::config::StorageConfig,
+    scan: &Scan,
+    metas: &[(uuid::Uuid, super::Metadata)],
+    report: &mut RecoveryReport,
+) -> Result<(), crate::error::CacheError> {
+    let (references, errors) = chunk_references(config, metas);
+
+    if errors != 0 {
+        // Can't tell which chunks these manifests need, better keep everything
+        report.errors += errors;
+        return Ok(());
+    }
+
+    remove_all(
+        &unreferenced_chunks(config, scan, &references),
+        &mut report.removed_orphan_chunks,
+        &mut report.errors,
+    );
+
+    report.chunk_map_fixes = reconcile_chunk_map(config, scan, &references)?;
+
+    Ok(())
+}
+
+fn reconcile_chunk_map(
+    config: &crate::config::StorageConfig,
+    scan: &Scan,
+    references: &std::collections::HashMap<String, u32>,
+) -> Result<usize, crate::error::CacheError> {
+    let expected = references
+        .iter()
+        .filter(|(hash, _)| scan.chunks.contains(*hash))
+        .map(|(hash, refs)| {
+            let compressed = std::fs::metadata(super::fs::chunk_path(config, hash))
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            (
+                hash.clone(),
+                super::chunks::ChunkInfo::new(*refs, compressed),
+            )
+        })
+        .collect();
+
+    super::ChunkMap::init_from_cache_dir(config).reconcile(expected)
+}
+
+fn reconcile_duplicate_map(
+    config: &crate::config::StorageConfig,
+    metas: &[(uuid::Uuid, super::Metadata)],
+) -> Result<usize, crate::error::CacheError> {
+    let mut duplicate_map = super::DuplicateMap::init_from_cache_dir(config);
+
+    // Chunked entries are deduplicated per chunk, see chunks.rs
+    duplicate_map.reconcile(
+        metas
+            .iter()
+            .filter(|(_, meta)| meta.storage() == super::StorageMode::Whole)
+            .map(|(uuid, meta)| (meta.data_file_name().clone(), *uuid)),
+    )
+}
+
+fn remove_all(paths: &[std::path::PathBuf], removed: &mut usize, errors: &mut usize) {
+    for path in paths {
+        match std::fs::remove_file(path) {
+            Ok(()) => *removed += 1,
+            Err(e) => {
+                error!("Could not remove '{}' due to: {e}", path.display());
+                *errors += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::cache::fs,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_recovery() {
+        let client = Client::tracked(crate::build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/notes.txt")
+            .body("Some notes")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+        let uuid = uuid::Uuid::parse_str(&uuid).unwrap();
+
+        let config = client
+            .rocket()
+            .state::<crate::config::StorageConfig>()
+            .unwrap();
+
+        // What a crash mid upload or mid meta rewrite leaves behind
+        let temp_meta = fs::temp_meta_path(config, &uuid);
+        std::fs::write(&temp_meta, "{\"name\":").unwrap();
+        let killed = uuid::Uuid::new_v4();
+        let temp_data = fs::temp_data_path(config, &killed);
+        std::fs::write(&temp_data, "half an upload").unwrap();
+        let empty_meta = fs::meta_path(config, &killed);
+        std::fs::write(&empty_meta, "").unwrap();
+        let orphan_data = fs::data_path(config, &"ab".repeat(32));
+        std::fs::write(&orphan_data, "nobody points here").unwrap();
+        let unparsable = fs::meta_path(config, &uuid::Uuid::new_v4());
+        std::fs::write(&unparsable, "not json").unwrap();
+
+        let report = super::recover(config).unwrap();
+        assert_eq!(report.removed_temp_files, 2);
+        assert_eq!(report.removed_empty_metas, 1);
+        assert_eq!(report.removed_orphan_data, 1);
+        assert_eq!(report.quarantined_metas, 1);
+        assert_eq!(report.missing_data, 0);
+        assert_eq!(report.errors, 0);
+
+        for path in [
+            &temp_meta,
+            &temp_data,
+            &empty_meta,
+            &orphan_data,
+            &unparsable,
+        ] {
+            assert!(!path.exists(), "{}", path.display());
+        }
+        assert!(fs::quarantine_dir(config)
+            .join(unparsable.file_name().unwrap())
+            .exists());
+
+        // The stored entry is left alone
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "Some notes");
+
+        assert!(super::recover(config).unwrap().is_clean());
+    }
+}
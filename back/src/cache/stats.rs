@@ -0,0 +1,70 @@
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Stats {
+    pub entries: usize,
+    pub data_files: usize,
+    pub chunks: usize,
+    // Sum of all uploads, before compression
+    pub original_bytes: u64,
+    // What the uploads would take compressed, without deduplication
+    pub compressed_bytes: u64,
+    // What's really on disk
+    pub stored_bytes: u64,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use rocket::data::ByteUnit;
+
+        write!(
+            f,
+            "Entries: {}\nData files: {}\nChunks: {}\nOriginal size: {}\nCompressed size: {}\nStored size: {}\nSaved by deduplication: {}",
+            self.entries,
+            self.data_files,
+            self.chunks,
+            ByteUnit::Byte(self.original_bytes),
+            ByteUnit::Byte(self.compressed_bytes),
+            ByteUnit::Byte(self.stored_bytes),
+            ByteUnit::Byte(self.compressed_bytes.saturating_sub(self.stored_bytes)),
+        )
+    }
+}
+
+pub fn stats(config: &crate::config::StorageConfig) -> Result<Stats, crate::error::CacheError> {
+    use crate::error::CacheError;
+
+    let scan = super::recovery::scan(config)?;
+
+    let mut stats = Stats {
+        entries: scan.metas.len(),
+        data_files: scan.data_files.len(),
+        chunks: scan.chunks.len(),
+        ..Default::default()
+    };
+
+    for (_, meta) in scan.metas.iter() {
+        stats.original_bytes += meta.size().original();
+        stats.compressed_bytes += meta.size().compressed();
+    }
+
+    let paths = scan
+        .data_files
+        .iter()
+        .chain(scan.manifests.iter())
+        .map(|name| super::fs::data_path(config, name))
+        .chain(
+            scan.chunks
+                .iter()
+                .map(|hash| super::fs::chunk_path(config, hash)),
+        );
+
+    for path in paths {
+        stats.stored_bytes += std::fs::metadata(&path)
+            .map_err(|e| CacheError::FileRead {
+                file: path.display().to_string(),
+                why: e,
+            })?
+            .len();
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,10 @@
+// How an upload's content is stored on disk
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    // One compressed data file per distinct content, see duplicates.rs
+    #[default]
+    Whole,
+    // Content-defined chunks, deduplicated across uploads, and a manifest listing them in order
+    Chunked,
+}
@@ -3,11 +3,56 @@
 pub struct UploadInfo {
     name: String,
     extension: String,
+    // The front end can encrypt files before uploading them, the key never reaches us
+    // so all we can do is remember it for the download page
+    client_encrypted: bool,
+    // Only tell if there is one, the hash itself stays on the server
+    #[serde(rename = "password_protected", serialize_with = "is_some")]
+    password_hash: Option<String>,
+    // Never evicted, see eviction.rs
+    pinned: bool,
+    #[serde(flatten)]
+    details: UploadDetails,
+}
+
+// Where an upload came from, stored in its meta since version 2 (see metadata.rs) and shown by /info
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct UploadDetails {
+    // Seconds since the epoch
+    pub upload_time: Option<u64>,
+    pub uploader_address: Option<String>,
+    pub user_agent: Option<String>,
+    // As sent by the uploader, or guessed from the extension
+    pub content_type: Option<String>,
+    // As sent by the uploader, when it had to be sanitised (see filename.rs)
+    pub original_name: Option<String>,
+    // Text sent to /paste, served inline and shown by the front end's viewer (see routes/paste.rs)
+    #[serde(default)]
+    pub paste: bool,
+    // Given with the paste, for the syntax highlighting
+    pub language: Option<String>,
+    // Other names it can be downloaded with, see ids.rs. Missing on entries uploaded before they existed
+    pub short_id: Option<String>,
+    pub alias: Option<String>,
 }
 
 impl UploadInfo {
-    pub fn new(name: String, extension: String) -> Self {
-        Self { name, extension }
+    pub fn new(
+        name: String,
+        extension: String,
+        client_encrypted: bool,
+        password_hash: Option<String>,
+        pinned: bool,
+        details: UploadDetails,
+    ) -> Self {
+        Self {
+            name,
+            extension,
+            client_encrypted,
+            password_hash,
+            pinned,
+            details,
+        }
     }
 
     pub fn name(&self) -> &String {
@@ -17,4 +62,33 @@ impl UploadInfo {
     pub fn extension(&self) -> &String {
         &self.extension
     }
+
+    // Name and extension back together
+    pub fn file_name(&self) -> String {
+        if self.extension.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.name, self.extension)
+        }
+    }
+
+    pub fn client_encrypted(&self) -> bool {
+        self.client_encrypted
+    }
+
+    pub fn password_hash(&self) -> Option<&str> {
+        self.password_hash.as_deref()
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
+    pub fn details(&self) -> &UploadDetails {
+        &self.details
+    }
+}
+
+fn is_some<S: serde::Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(value.is_some())
 }
@@ -42,14 +42,46 @@ pub fn upload_400(_req: &rocket::Request<'_>) -> crate::response::Response {
 }
 
 #[rocket::catch(413)]
-pub fn upload_413(_req: &rocket::Request<'_>) -> crate::response::Response {
+pub fn upload_413(req: &rocket::Request<'_>) -> crate::response::Response {
     use rocket::http::{ContentType, Status};
 
+    let limit = req
+        .rocket()
+        .state::<crate::config::StorageConfig>()
+        .map(|config| config.file_limit)
+        .unwrap_or_default();
+
     crate::response::ResponseBuilder::default()
         .with_status(Status::PayloadTooLarge)
-        .with_content(format!("Data too large, {} max", unsafe {
-            crate::FILE_REQ_SIZE_LIMIT
-        }))
+        .with_content(format!("Data too large, {limit} max"))
+        .with_content_type(ContentType::Text)
+        .build()
+}
+
+#[rocket::catch(507)]
+pub fn upload_507(_req: &rocket::Request<'_>) -> crate::response::Response {
+    use rocket::http::{ContentType, Status};
+
+    crate::response::ResponseBuilder::default()
+        .with_status(Status::InsufficientStorage)
+        .with_content("Not enough space left to store this file")
+        .with_content_type(ContentType::Text)
+        .build()
+}
+
+#[rocket::catch(429)]
+pub fn root_429(req: &rocket::Request<'_>) -> crate::response::Response {
+    use {
+        crate::rate_limit::RetryAfter,
+        rocket::http::{ContentType, Status},
+    };
+
+    let RetryAfter(retry_after) = req.local_cache(|| RetryAfter(std::time::Duration::ZERO));
+
+    crate::response::ResponseBuilder::default()
+        .with_status(Status::TooManyRequests)
+        .with_header("Retry-After", &(retry_after.as_secs() + 1).to_string())
+        .with_content("Too many requests, try again later")
         .with_content_type(ContentType::Text)
         .build()
 }
@@ -0,0 +1,240 @@
+// Offline maintenance commands
+//
+// They work directly on the cache directory, without starting rocket.
+// Do NOT run them while the server is up, as they would see in-flight uploads as garbage
+
+const USAGE: &str = "Usage: server [COMMAND] [ARGS] [OPTIONS]
+
+Without command, starts the server
+
+Commands:
+    fsck            Check every meta, data file, chunk and the duplicate and chunk maps
+    gc              Remove temp files, empty metas and unreferenced data files
+    stats           Display the cache directory's usage
+    rebuild-index   Rebuild the duplicate and chunk maps from the meta files
+    train-dictionary <EXTENSION>
+                    Train a new zstd dictionary from the small stored uploads with that extension,
+                    used by the next uploads with that extension
+    rotate-key <OLD_KEY_FILE>
+                    Re-wrap the file keys made with the old master key using the configured one
+    recompress      Recompress the cold data files now, like the server's background job does
+    help            Display this message
+
+Options:
+    --repair        (fsck) Fix the issues found
+    --max-size <N>  (train-dictionary) Max dictionary size in bytes, defaults to 110KiB
+    --json          Output a machine-readable report";
+
+#[derive(Debug)]
+pub enum Command {
+    Fsck {
+        repair: bool,
+    },
+    Gc,
+    Stats,
+    RebuildIndex,
+    TrainDictionary {
+        extension: String,
+        max_size: Option<usize>,
+    },
+    RotateKey {
+        old_key_file: std::path::PathBuf,
+    },
+    Recompress,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    command: Command,
+    json: bool,
+}
+
+/// Returns None if no command was given, meaning that the server should start
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let Some(command) = args.next() else {
+        return Ok(None);
+    };
+
+    let mut repair = false;
+    let mut json = false;
+    let mut max_size = None;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--repair" => repair = true,
+            "--json" => json = true,
+            "--max-size" => {
+                max_size = Some(
+                    args.next()
+                        .and_then(|size| size.parse::<usize>().ok())
+                        .ok_or_else(|| String::from("--max-size expects a size in bytes"))?,
+                )
+            }
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => return Err(format!("Unknown option: {arg}")),
+        }
+    }
+
+    let command = match command.as_str() {
+        "fsck" => Command::Fsck { repair },
+        "gc" => Command::Gc,
+        "stats" => Command::Stats,
+        "rebuild-index" => Command::RebuildIndex,
+        "train-dictionary" => {
+            if positional.len() != 1 {
+                return Err(String::from("train-dictionary expects a single extension"));
+            }
+            Command::TrainDictionary {
+                extension: positional.remove(0),
+                max_size,
+            }
+        }
+        "rotate-key" => {
+            if positional.len() != 1 {
+                return Err(String::from("rotate-key expects the old master key file"));
+            }
+            Command::RotateKey {
+                old_key_file: positional.remove(0).into(),
+            }
+        }
+        "recompress" => Command::Recompress,
+        "help" | "--help" | "-h" => Command::Help,
+        _ => return Err(format!("Unknown command: {command}")),
+    };
+
+    if repair && !matches!(command, Command::Fsck { .. }) {
+        return Err(String::from("--repair is only available for fsck"));
+    }
+
+    if max_size.is_some() && !matches!(command, Command::TrainDictionary { .. }) {
+        return Err(String::from(
+            "--max-size is only available for train-dictionary",
+        ));
+    }
+
+    if !positional.is_empty()
+        && !matches!(
+            command,
+            Command::TrainDictionary { .. } | Command::RotateKey { .. }
+        )
+    {
+        return Err(format!("Unexpected argument: {}", positional[0]));
+    }
+
+    Ok(Some(Args { command, json }))
+}
+
+/// Runs the given command, returns the process exit code
+pub fn run(args: Args) -> i32 {
+    use crate::cache;
+
+    let Args { command, json } = args;
+
+    // Same config as the server, with its master key as encrypted files can't be checked without it
+    let config = match crate::config::StorageConfig::from_figment(&rocket::Config::figment()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    let config = &config;
+
+    let output = match command {
+        Command::Help => {
+            println!("{USAGE}");
+            return 0;
+        }
+        Command::Fsck { repair } => cache::fsck(config).and_then(|report| {
+            let clean = report.is_clean();
+
+            if !repair || clean {
+                return Ok((render(&report, json), clean));
+            }
+
+            let repaired = cache::repair(config, &report)?;
+            // Some issues are still there
+            let clean = repaired.errors == 0;
+
+            if json {
+                Ok((
+                    rocket::serde::json::json!({ "fsck": report, "repair": repaired }).to_string(),
+                    clean,
+                ))
+            } else {
+                Ok((format!("{report}\nRepair: {repaired}"), clean))
+            }
+        }),
+        Command::Gc => cache::collect_garbage(config).map(|report| (render(&report, json), true)),
+        Command::Stats => cache::stats(config).map(|stats| (render(&stats, json), true)),
+        Command::RebuildIndex => cache::rebuild_index(config).map(|fixes| {
+            let output = if json {
+                rocket::serde::json::json!({ "index_fixes": fixes }).to_string()
+            } else {
+                format!("Fixed {fixes} index entries")
+            };
+            (output, true)
+        }),
+        Command::TrainDictionary {
+            extension,
+            max_size,
+        } => cache::train_dictionary(config, &extension, max_size)
+            .map(|report| (render(&report, json), true)),
+        Command::RotateKey { old_key_file } => cache::MasterKey::from_file(&old_key_file)
+            .and_then(|old_key| cache::rotate_master_key(config, &old_key))
+            .map(|report| {
+                let clean = report.errors == 0;
+                (render(&report, json), clean)
+            }),
+        Command::Recompress => crate::config::RecompressionConfig::from_figment(
+            &rocket::Config::figment(),
+        )
+        .and_then(|recompression| {
+            let cache = cache::init_cache_list_from_cache_dir(config).ok_or_else(|| {
+                crate::error::CacheError::CacheDirRead {
+                    dir: config.cache_dir.display().to_string(),
+                    why: std::io::Error::other("could not load the cache entries"),
+                }
+            })?;
+
+            let holders = cache::DuplicateMap::init_from_cache_dir(config)
+                .iter()
+                .map(|(hash, uuids)| (hash.clone(), uuids.to_vec()))
+                .collect();
+
+            let report = cache::recompress(config, &recompression, &cache, holders);
+            let clean = report.errors == 0;
+            Ok((render(&report, json), clean))
+        }),
+    };
+
+    match output {
+        Ok((output, success)) => {
+            println!("{output}");
+            if success {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+fn render<T: serde::Serialize + std::fmt::Display>(value: &T, json: bool) -> String {
+    if !json {
+        return value.to_string();
+    }
+
+    rocket::serde::json::serde_json::to_string(value)
+        .unwrap_or_else(|e| format!("{{\"error\": \"{e}\"}}"))
+}
+
+pub fn usage() -> &'static str {
+    USAGE
+}
@@ -0,0 +1,341 @@
+// Typed sections of Rocket.toml
+//
+// Managed by rocket, so every instance has its own, tests use that to run isolated instances side by side
+
+// [default.storage]
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    // Where everything is stored, see cache.rs for the layout
+    pub cache_dir: std::path::PathBuf,
+    pub log_dir: std::path::PathBuf,
+    // zstd level used for new data files and chunks, 1..=22
+    pub compression_level: i32,
+    // How much of an upload is read at once
+    pub buffer_size: rocket::data::ByteUnit,
+    // zstd threads compressing each upload, 0 compresses on the upload's own (blocking) thread
+    pub compression_workers: u32,
+    // Max size of an upload, defaults to limits.file (which the front end reads too)
+    pub file_limit: rocket::data::ByteUnit,
+    // Free space kept on the cache volume, uploads are refused under it (see disk.rs)
+    pub reserved_space: rocket::data::ByteUnit,
+    // Under it, the server goes read-only
+    pub critical_space: rocket::data::ByteUnit,
+    // How new uploads are stored, entries keep the mode they were stored with
+    pub storage_mode: crate::cache::StorageMode,
+    // From the encryption section, None stores new files in clear (see cache/encryption.rs)
+    // Part of the config so instances in the same process don't share it
+    #[serde(skip)]
+    pub master_key: Option<crate::cache::MasterKey>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        use rocket::data::ToByteUnit as _;
+
+        Self {
+            cache_dir: std::path::PathBuf::from("./cache"),
+            log_dir: std::path::PathBuf::from("./log"),
+            compression_level: zstd::DEFAULT_COMPRESSION_LEVEL, // 3
+            buffer_size: 500.kilobytes(),
+            compression_workers: 2,
+            file_limit: 1.gibibytes(),
+            reserved_space: 1.gibibytes(),
+            critical_space: 100.mebibytes(),
+            storage_mode: crate::cache::StorageMode::Whole,
+            master_key: None,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Self, crate::error::CacheError> {
+        use {
+            crate::error::CacheError,
+            rocket::{data::ByteUnit, figment::providers::Serialized},
+        };
+
+        let mut figment = figment.clone();
+
+        // join only sets it if the storage section doesn't
+        if let Ok(limit) = figment.extract_inner::<ByteUnit>("limits.file") {
+            figment = figment.join(Serialized::default("storage.file_limit", limit.as_u64()));
+        }
+
+        let mut config = if figment.contains("storage") {
+            figment
+                .extract_inner::<Self>("storage")
+                .map_err(|e| CacheError::Config {
+                    section: "storage",
+                    why: e.to_string(),
+                })?
+        } else {
+            Self::default()
+        };
+
+        if !(1..=22).contains(&config.compression_level) {
+            return Err(CacheError::Config {
+                section: "storage",
+                why: format!(
+                    "compression_level should be between 1 and 22, got {}",
+                    config.compression_level
+                ),
+            });
+        }
+
+        if config.buffer_size == 0 {
+            return Err(CacheError::Config {
+                section: "storage",
+                why: String::from("buffer_size can't be 0"),
+            });
+        }
+
+        if config.critical_space > config.reserved_space {
+            return Err(CacheError::Config {
+                section: "storage",
+                why: String::from("critical_space can't be over reserved_space"),
+            });
+        }
+
+        config.master_key = crate::cache::MasterKey::from_figment(&figment)?;
+
+        Ok(config)
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.as_u64() as usize
+    }
+}
+
+// [default.rate_limit], budgets are per client, 0 means unlimited
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub upload: Budget,
+    pub download: Budget,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    pub requests_per_minute: u32,
+    // Uploads or downloads running at the same time
+    pub concurrent_streams: u32,
+    // Shared by every stream of the client
+    pub bytes_per_second: rocket::data::ByteUnit,
+}
+
+impl RateLimitConfig {
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Self, crate::error::CacheError> {
+        if !figment.contains("rate_limit") {
+            return Ok(Self::default());
+        }
+
+        figment
+            .extract_inner::<Self>("rate_limit")
+            .map_err(|e| crate::error::CacheError::Config {
+                section: "rate_limit",
+                why: e.to_string(),
+            })
+    }
+}
+
+// [default.timeouts], see timeout.rs
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    pub upload: StreamTimeouts,
+    pub download: StreamTimeouts,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default)]
+pub struct StreamTimeouts {
+    // Seconds without receiving (or sending) anything, 0 to disable
+    pub idle: u64,
+    // Average throughput, checked once the stream is older than grace_period (seconds), 0 to disable
+    // Keep it under the rate_limit bandwidth, throttled streams count as slow too
+    pub min_bytes_per_second: rocket::data::ByteUnit,
+    pub grace_period: u64,
+}
+
+impl Default for StreamTimeouts {
+    fn default() -> Self {
+        use rocket::data::ToByteUnit as _;
+
+        Self {
+            idle: 30,
+            min_bytes_per_second: 1.kibibytes(),
+            grace_period: 10,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Self, crate::error::CacheError> {
+        if !figment.contains("timeouts") {
+            return Ok(Self::default());
+        }
+
+        figment
+            .extract_inner::<Self>("timeouts")
+            .map_err(|e| crate::error::CacheError::Config {
+                section: "timeouts",
+                why: e.to_string(),
+            })
+    }
+}
+
+// [default.eviction], see cache/eviction.rs
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct EvictionConfig {
+    // Max total compressed size of the stored entries, 0 to never evict anything
+    pub capacity: rocket::data::ByteUnit,
+    pub policy: EvictionPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    // Least recently downloaded first
+    #[default]
+    Lru,
+    // Least downloaded first, then least recently
+    Lfu,
+}
+
+impl EvictionConfig {
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Self, crate::error::CacheError> {
+        if !figment.contains("eviction") {
+            return Ok(Self::default());
+        }
+
+        figment
+            .extract_inner::<Self>("eviction")
+            .map_err(|e| crate::error::CacheError::Config {
+                section: "eviction",
+                why: e.to_string(),
+            })
+    }
+}
+
+// [default.hot_cache], see hot_cache.rs
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default)]
+pub struct HotCacheConfig {
+    // Max total size of the kept (decompressed) files, 0 disables it
+    pub capacity: rocket::data::ByteUnit,
+    // Bigger files are always read from disk
+    pub max_entry_size: rocket::data::ByteUnit,
+}
+
+impl Default for HotCacheConfig {
+    fn default() -> Self {
+        use rocket::data::ToByteUnit as _;
+
+        Self {
+            capacity: 64.mebibytes(),
+            max_entry_size: 256.kibibytes(),
+        }
+    }
+}
+
+impl HotCacheConfig {
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Self, crate::error::CacheError> {
+        if !figment.contains("hot_cache") {
+            return Ok(Self::default());
+        }
+
+        figment
+            .extract_inner::<Self>("hot_cache")
+            .map_err(|e| crate::error::CacheError::Config {
+                section: "hot_cache",
+                why: e.to_string(),
+            })
+    }
+}
+
+// [default.recompression], see cache/recompression.rs
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default)]
+pub struct RecompressionConfig {
+    // Seconds between two runs, 0 disables the job
+    pub interval: u64,
+    // Seconds without any download (or since the upload) before an entry is recompressed
+    pub cold_after: u64,
+    // zstd level and long distance matching used to recompress, the zstd threads it uses on top of its own
+    pub compression_level: i32,
+    pub long_distance_matching: bool,
+    pub workers: u32,
+    // Throttles, read speed of the stored files (0 for unlimited) and share of the time the job spends working
+    pub bytes_per_second: rocket::data::ByteUnit,
+    pub cpu_percent: u32,
+}
+
+impl Default for RecompressionConfig {
+    fn default() -> Self {
+        use rocket::data::ToByteUnit as _;
+
+        Self {
+            interval: 60 * 60,             // An hour
+            cold_after: 30 * 24 * 60 * 60, // A month
+            compression_level: 19,
+            long_distance_matching: true,
+            workers: 0,
+            bytes_per_second: 10.mebibytes(),
+            cpu_percent: 25,
+        }
+    }
+}
+
+impl RecompressionConfig {
+    pub fn from_figment(
+        figment: &rocket::figment::Figment,
+    ) -> Result<Self, crate::error::CacheError> {
+        use crate::error::CacheError;
+
+        if !figment.contains("recompression") {
+            return Ok(Self::default());
+        }
+
+        let config = figment
+            .extract_inner::<Self>("recompression")
+            .map_err(|e| CacheError::Config {
+                section: "recompression",
+                why: e.to_string(),
+            })?;
+
+        if !(1..=22).contains(&config.compression_level) {
+            return Err(CacheError::Config {
+                section: "recompression",
+                why: format!(
+                    "compression_level should be between 1 and 22, got {}",
+                    config.compression_level
+                ),
+            });
+        }
+
+        if !(1..=100).contains(&config.cpu_percent) {
+            return Err(CacheError::Config {
+                section: "recompression",
+                why: format!(
+                    "cpu_percent should be between 1 and 100, got {}",
+                    config.cpu_percent
+                ),
+            });
+        }
+
+        Ok(config)
+    }
+}
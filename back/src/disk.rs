@@ -0,0 +1,103 @@
+// Free space of the cache volume, checked every CHECK_INTERVAL (see build_rocket) and before every upload
+//
+// Under storage.reserved_space, uploads are refused with a 507
+// Under storage.critical_space, the server goes read-only: deletes are refused too, as a half written
+// duplicate or chunk map would be worse than a few more files. It goes back to normal by itself once space is freed
+
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskState {
+    Ok,
+    Low,
+    ReadOnly,
+}
+
+pub struct DiskMonitor {
+    cache_dir: std::path::PathBuf,
+    reserved_space: u64,
+    critical_space: u64,
+    // u64::MAX until the first check
+    available_space: std::sync::atomic::AtomicU64,
+}
+
+impl DiskMonitor {
+    pub fn new(config: &crate::config::StorageConfig) -> Self {
+        let monitor = Self {
+            cache_dir: config.cache_dir.clone(),
+            reserved_space: config.reserved_space.as_u64(),
+            critical_space: config.critical_space.as_u64(),
+            available_space: std::sync::atomic::AtomicU64::new(u64::MAX),
+        };
+        monitor.check();
+        monitor
+    }
+
+    // Reads the free space again
+    pub fn check(&self) -> DiskState {
+        use {rocket::data::ByteUnit, std::sync::atomic::Ordering};
+
+        let available = match fs4::available_space(&self.cache_dir) {
+            Ok(available) => available,
+            Err(e) => {
+                // Keep the last reading, better than guessing
+                error!("Could not check the available disk space due to: {e}");
+                return self.state();
+            }
+        };
+
+        let old_state = self.state();
+        self.available_space.store(available, Ordering::Release);
+        let state = self.state();
+
+        if state != old_state {
+            let available = ByteUnit::Byte(available);
+            match state {
+                DiskState::Ok => info!("Disk space is back to normal ({available} available)"),
+                DiskState::Low => warn!(
+                    "Only {available} left on disk, refusing uploads until {} are available",
+                    ByteUnit::Byte(self.reserved_space)
+                ),
+                DiskState::ReadOnly => error!(
+                    "Only {available} left on disk, the server is read-only until {} are available",
+                    ByteUnit::Byte(self.critical_space)
+                ),
+            }
+        }
+
+        state
+    }
+
+    pub fn available_space(&self) -> u64 {
+        self.available_space
+            .load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    pub fn reserved_space(&self) -> u64 {
+        self.reserved_space
+    }
+
+    pub fn critical_space(&self) -> u64 {
+        self.critical_space
+    }
+
+    pub fn state(&self) -> DiskState {
+        let available = self.available_space();
+
+        if available < self.critical_space {
+            DiskState::ReadOnly
+        } else if available < self.reserved_space {
+            DiskState::Low
+        } else {
+            DiskState::Ok
+        }
+    }
+
+    // Whether storing that many more bytes keeps the reserve untouched
+    pub fn can_store(&self, size: u64) -> bool {
+        self.available_space()
+            .checked_sub(size)
+            .is_some_and(|left| left >= self.reserved_space)
+    }
+}
@@ -3,6 +3,9 @@ pub enum CacheError {
     #[error("Could not read directory '{dir}' due to: {why}")]
     CacheDirRead { dir: String, why: std::io::Error },
 
+    #[error("Could not create directory '{dir}' due to: {why}")]
+    DirCreate { dir: String, why: std::io::Error },
+
     #[error("Could not create file '{file}' due to: {why}")]
     FileCreate { file: String, why: std::io::Error },
 
@@ -21,12 +24,33 @@ pub enum CacheError {
     #[error("Could not rename to file '{file}' due to: {why}")]
     FileRename { file: String, why: std::io::Error },
 
-    #[error("Given file was too large, max size is: {}", unsafe{crate::FILE_REQ_SIZE_LIMIT})]
-    FileSizeExceeded,
+    #[error("Given file was too large, max size is: {limit}")]
+    FileSizeExceeded { limit: rocket::data::ByteUnit },
+
+    #[error("Not enough evictable entries to stay under the {capacity} capacity")]
+    CapacityExceeded { capacity: rocket::data::ByteUnit },
+
+    #[error("The upload timed out: {why}")]
+    Timeout { why: std::io::Error },
 
     #[error("Could not compress the given data due to {why}")]
     Compression { why: std::io::Error },
 
+    #[error("Invalid master key: {why}")]
+    MasterKey { why: String },
+
+    #[error("Invalid {section} config: {why}")]
+    Config { section: &'static str, why: String },
+
+    #[error("Could not hash the password due to: {why}")]
+    PasswordHash { why: String },
+
+    #[error("Could not train a dictionary for '{extension}' due to: {why}")]
+    DictionaryTraining {
+        extension: String,
+        why: std::io::Error,
+    },
+
     // #[error("Could not decompress the given data")]
     // Decompression,
 
@@ -35,7 +59,6 @@ pub enum CacheError {
 
     // #[error("Cache with uuid: {uuid} isn't ready yet")]
     // NotReady { uuid: uuid::Uuid },
-
     #[error("Serialization error while {context} due to {why}")]
     Serialization {
         context: String, // Extremely short description of what was atempted to do
@@ -59,6 +82,24 @@ pub enum CacheError {
 
     #[error("Duplicate map issue: {0}")]
     DuplicateMapLogic(String),
+
+    #[error("The blocking task stopped before opening '{file}'")]
+    BlockingTask { file: String },
+}
+
+impl CacheError {
+    // A full disk can show up while writing any of the files of an upload
+    pub fn is_disk_full(&self) -> bool {
+        match self {
+            Self::DirCreate { why, .. }
+            | Self::FileCreate { why, .. }
+            | Self::FileWrite { why, .. }
+            | Self::FileRename { why, .. }
+            | Self::Compression { why } => why.kind() == std::io::ErrorKind::StorageFull,
+            Self::Multiple(errors) => errors.iter().any(Self::is_disk_full),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -0,0 +1,193 @@
+// Uploaded file names, any UTF-8 is accepted but they end up in 'Content-Disposition' headers (and in the
+// downloader's file system), so they're sanitised: path separators and control characters are replaced, repeated
+// spaces, underscores and dots are collapsed, and they're truncated to MAX_LENGTH bytes, keeping the extension.
+// Names are never refused, the uploader gets the final one back (and the original is kept, see cache::UploadDetails)
+//
+// Headers can only hold ASCII, so downloads get both an ASCII approximation (filename=, with accents transliterated)
+// for old clients and the real name, percent-encoded (filename*=, RFC 5987 / RFC 6266)
+
+// Most file systems don't allow longer names
+pub const MAX_LENGTH: usize = 255;
+
+// Longer extensions are cut with the rest of the name
+const MAX_EXTENSION_LENGTH: usize = 32;
+
+// When nothing is left
+const DEFAULT_NAME: &str = "file";
+
+// RFC 5987's attr-char, everything else is percent-encoded
+const ATTR_CHAR: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+pub fn sanitise(name: &str) -> String {
+    let mut sanitised = String::with_capacity(name.len());
+
+    for c in name.chars() {
+        let c = match c {
+            '/' | '\\' => '_',
+            c if c.is_whitespace() => ' ',
+            c if c.is_control() => '_',
+            c => c,
+        };
+
+        if matches!(c, ' ' | '_' | '.') && sanitised.ends_with(c) {
+            continue;
+        }
+
+        sanitised.push(c);
+    }
+
+    // Some systems drop trailing dots and spaces, and '.' or '..' would be a surprise once saved
+    let sanitised = sanitised.trim_matches([' ', '_']).trim_end_matches('.');
+    if sanitised.is_empty() {
+        return String::from(DEFAULT_NAME);
+    }
+
+    if sanitised.len() <= MAX_LENGTH {
+        return sanitised.to_string();
+    }
+
+    let (stem, extension) = match sanitised.rfind('.') {
+        Some(dot) if dot != 0 && sanitised.len() - dot <= MAX_EXTENSION_LENGTH + 1 => {
+            sanitised.split_at(dot)
+        }
+        _ => (sanitised, ""),
+    };
+
+    let mut end = MAX_LENGTH - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!(
+        "{}{extension}",
+        stem[..end].trim_end_matches([' ', '_', '.'])
+    )
+}
+
+// Inline ones are shown by the browser instead of saved
+pub fn content_disposition(name: &str, inline: bool) -> String {
+    let disposition = if inline { "inline" } else { "attachment" };
+
+    let fallback = name
+        .chars()
+        .map(|c| match c {
+            // Quotes and backslashes would end or escape the quoted string, some clients decode '%'
+            '"' | '\\' | '%' => String::from("_"),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            c => transliterate(c).unwrap_or("_").to_string(),
+        })
+        .collect::<String>();
+
+    if fallback == name {
+        return format!("{disposition}; filename=\"{name}\"");
+    }
+
+    format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        encode(name)
+    )
+}
+
+// For headers
+pub fn encode(name: &str) -> String {
+    percent_encoding::utf8_percent_encode(name, ATTR_CHAR).to_string()
+}
+
+// Closest ASCII of the most common latin letters
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'æ' => "ae",
+        'Æ' => "AE",
+        'ç' | 'ć' | 'č' => "c",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'ď' | 'đ' => "d",
+        'Ď' | 'Đ' => "D",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'ğ' => "g",
+        'Ğ' => "G",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' | 'Į' | 'İ' => "I",
+        'ł' | 'ľ' => "l",
+        'Ł' | 'Ľ' => "L",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+        'œ' => "oe",
+        'Œ' => "OE",
+        'ř' => "r",
+        'Ř' => "R",
+        'ś' | 'š' | 'ş' | 'ș' => "s",
+        'Ś' | 'Š' | 'Ş' | 'Ș' => "S",
+        'ß' => "ss",
+        'ť' | 'ţ' | 'ț' => "t",
+        'Ť' | 'Ţ' | 'Ț' => "T",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ý' | 'ÿ' => "y",
+        'Ý' | 'Ÿ' => "Y",
+        'ź' | 'ż' | 'ž' => "z",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_sanitise() {
+        use super::sanitise;
+
+        assert_eq!(sanitise("Rapport final été.pdf"), "Rapport final été.pdf");
+        assert_eq!(sanitise("../../etc/passwd\n"), "._._etc_passwd");
+        assert_eq!(sanitise("a  \t b__c...txt"), "a b_c.txt");
+        assert_eq!(sanitise(".."), "file");
+        assert_eq!(sanitise("/"), "file");
+
+        // Cut on a char boundary, before the extension
+        let long = sanitise(&format!("a{}.tar.gz", "é".repeat(200)));
+        assert_eq!(long.len(), 254);
+        assert!(long.ends_with("é.gz"));
+
+        // Too long to be one
+        let long = sanitise(&format!("a.{}", "b".repeat(300)));
+        assert_eq!(long.len(), 255);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        use super::content_disposition;
+
+        assert_eq!(
+            content_disposition("notes.txt", false),
+            "attachment; filename=\"notes.txt\""
+        );
+        assert_eq!(
+            content_disposition("Rapport final été.pdf", false),
+            "attachment; filename=\"Rapport final ete.pdf\"; filename*=UTF-8''Rapport%20final%20%C3%A9t%C3%A9.pdf"
+        );
+        assert_eq!(
+            content_disposition("say \"hi\".txt", false),
+            "attachment; filename=\"say _hi_.txt\"; filename*=UTF-8''say%20%22hi%22.txt"
+        );
+        assert_eq!(
+            content_disposition("paste.txt", true),
+            "inline; filename=\"paste.txt\""
+        );
+    }
+}
@@ -0,0 +1,172 @@
+// Decompressed content of small, often downloaded entries (badges, config blobs, ..), kept in memory so they're
+// served without opening and decompressing their data file each time
+//
+// Least recently used entries are dropped once the total goes over hot_cache.capacity, entries bigger than
+// hot_cache.max_entry_size are never kept. Deleted and evicted entries are removed from it
+
+pub struct HotCache {
+    capacity: u64,
+    max_entry_size: u64,
+    inner: std::sync::Mutex<Inner>,
+    hits: std::sync::atomic::AtomicU64,
+    misses: std::sync::atomic::AtomicU64,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Content and last use
+    entries: std::collections::HashMap<uuid::Uuid, (std::sync::Arc<[u8]>, u64)>,
+    // Last use -> uuid, least recently used first
+    order: std::collections::BTreeMap<u64, uuid::Uuid>,
+    // Bumped on each use, so last uses are unique
+    tick: u64,
+    size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct HotCacheStats {
+    pub entries: usize,
+    // Bytes
+    pub size: u64,
+    pub capacity: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl HotCache {
+    pub fn new(config: &crate::config::HotCacheConfig) -> Self {
+        Self {
+            capacity: config.capacity.as_u64(),
+            max_entry_size: config.max_entry_size.as_u64(),
+            inner: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+        }
+    }
+
+    // If an entry of that (decompressed) size can be kept
+    pub fn accepts(&self, size: u64) -> bool {
+        self.capacity != 0 && size <= self.max_entry_size && size <= self.capacity
+    }
+
+    // Only call it for entries it accepts, the others would count as misses
+    pub fn get(&self, uuid: &uuid::Uuid) -> Option<std::sync::Arc<[u8]>> {
+        use std::sync::atomic::Ordering;
+
+        let data = self.inner.lock().unwrap().get(uuid);
+
+        match data {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        data
+    }
+
+    pub fn insert(&self, uuid: uuid::Uuid, data: std::sync::Arc<[u8]>) {
+        if !self.accepts(data.len() as u64) {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.remove(&uuid);
+
+        while inner.size + data.len() as u64 > self.capacity {
+            let Some((_last_use, oldest)) = inner.order.pop_first() else {
+                break;
+            };
+            if let Some((data, _last_use)) = inner.entries.remove(&oldest) {
+                inner.size -= data.len() as u64;
+            }
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+
+        inner.size += data.len() as u64;
+        inner.order.insert(tick, uuid);
+        inner.entries.insert(uuid, (data, tick));
+    }
+
+    pub fn remove(&self, uuid: &uuid::Uuid) {
+        self.inner.lock().unwrap().remove(uuid);
+    }
+
+    pub fn stats(&self) -> HotCacheStats {
+        use std::sync::atomic::Ordering;
+
+        let inner = self.inner.lock().unwrap();
+
+        HotCacheStats {
+            entries: inner.entries.len(),
+            size: inner.size,
+            capacity: self.capacity,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Inner {
+    fn get(&mut self, uuid: &uuid::Uuid) -> Option<std::sync::Arc<[u8]>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let (data, last_use) = self.entries.get_mut(uuid)?;
+
+        self.order.remove(last_use);
+        self.order.insert(tick, *uuid);
+        *last_use = tick;
+
+        Some(std::sync::Arc::clone(data))
+    }
+
+    fn remove(&mut self, uuid: &uuid::Uuid) {
+        if let Some((data, last_use)) = self.entries.remove(uuid) {
+            self.order.remove(&last_use);
+            self.size -= data.len() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::HotCache, rocket::data::ToByteUnit as _, std::sync::Arc, uuid::Uuid};
+
+    #[test]
+    fn test_hot_cache_lru() {
+        let hot_cache = HotCache::new(&crate::config::HotCacheConfig {
+            capacity: 30.bytes(),
+            max_entry_size: 10.bytes(),
+        });
+
+        let uuids = (0..4).map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+
+        for uuid in &uuids[..3] {
+            hot_cache.insert(*uuid, Arc::from(vec![0; 10]));
+        }
+
+        // Makes the second one the least recently used
+        assert!(hot_cache.get(&uuids[0]).is_some());
+
+        hot_cache.insert(uuids[3], Arc::from(vec![0; 10]));
+
+        assert!(hot_cache.get(&uuids[1]).is_none());
+        assert!(hot_cache.get(&uuids[2]).is_some());
+        assert!(hot_cache.get(&uuids[3]).is_some());
+
+        // Too big
+        hot_cache.insert(uuids[1], Arc::from(vec![0; 11]));
+        assert!(hot_cache.get(&uuids[1]).is_none());
+
+        hot_cache.remove(&uuids[0]);
+        assert!(hot_cache.get(&uuids[0]).is_none());
+
+        let stats = hot_cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.size, 20);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 3);
+    }
+}
@@ -8,38 +8,178 @@ extern crate log;
 #[macro_use(lazy_static)]
 extern crate lazy_static;
 
+mod blocking;
+mod buffers;
 mod cache;
 mod catchers;
+mod cli;
+mod config;
+mod disk;
 mod error;
+mod filename;
+mod hot_cache;
+mod rate_limit;
 mod response;
 mod routes;
+mod timeout;
 
-// SAFETY:
-//     This static is ONLY EVER mutated at the program's init, before the webserer is even running
-static mut FILE_REQ_SIZE_LIMIT: rocket::data::ByteUnit = rocket::data::ByteUnit::Byte(0);
-
-// Needed for tests
 pub async fn build_rocket() -> rocket::Rocket<rocket::Ignite> {
-    let Some(cache) = cache::init_cache_list_from_cache_dir() else {
+    build_rocket_from(rocket::Config::figment()).await
+}
+
+// Each instance only touches its own storage.cache_dir
+pub async fn build_rocket_from(
+    figment: rocket::figment::Figment,
+) -> rocket::Rocket<rocket::Ignite> {
+    build_unignited(figment).ignite().await.unwrap()
+}
+
+// Tests add their own state before igniting it, see build_test_rocket_from
+fn build_unignited(figment: rocket::figment::Figment) -> rocket::Rocket<rocket::Build> {
+    let config = match config::StorageConfig::from_figment(&figment) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    };
+
+    if let Err(e) = std::fs::create_dir_all(&config.cache_dir) {
+        error!(
+            "Could not create the cache directory '{}' due to: {e}",
+            config.cache_dir.display()
+        );
+        std::process::exit(1)
+    }
+
+    match cache::migrate(&config) {
+        Ok(0) => (),
+        Ok(migrated) => info!("Migrated {migrated} data files to content hashes"),
+        Err(e) => error!("Cache migration failed due to: {e}"),
+    }
+
+    match cache::recover(&config) {
+        Ok(report) => info!("Cache recovery: {report}"),
+        Err(e) => error!("Cache recovery failed due to: {e}"),
+    }
+
+    let Some(cache) = cache::init_cache_list_from_cache_dir(&config) else {
         error!("Failled to load cache");
         std::process::exit(1)
     };
 
-    let duplicate_map = cache::DuplicateMap::init_from_cache_dir();
+    let duplicate_map = cache::DuplicateMap::init_from_cache_dir(&config);
+    let chunk_map = cache::ChunkMap::init_from_cache_dir(&config);
+
+    let rate_limit = match config::RateLimitConfig::from_figment(&figment) {
+        Ok(rate_limit) => rate_limit,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    };
+
+    let timeouts = match config::TimeoutConfig::from_figment(&figment) {
+        Ok(timeouts) => timeouts,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    };
+
+    let eviction = match config::EvictionConfig::from_figment(&figment) {
+        Ok(eviction) => eviction,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    };
+
+    let recompression = match config::RecompressionConfig::from_figment(&figment) {
+        Ok(recompression) => recompression,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    };
+
+    let hot_cache = match config::HotCacheConfig::from_figment(&figment) {
+        Ok(hot_cache) => hot_cache::HotCache::new(&hot_cache),
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+    };
 
-    let rocket = rocket::build()
-        .manage(cache)
+    let disk_monitor = std::sync::Arc::new(disk::DiskMonitor::new(&config));
+
+    let rocket = rocket::custom(figment);
+
+    if config.master_key.is_none() {
+        warn!("No master key configured, new files will be stored unencrypted");
+    }
+
+    let ids = cache::IdMap::init_from_cache(&cache);
+
+    // Shared with the recompression job
+    let cache = std::sync::Arc::new(cache);
+    let duplicate_map = std::sync::Arc::new(rocket::tokio::sync::Mutex::new(duplicate_map));
+
+    // For the recompression job
+    let storage_config = config.clone();
+
+    rocket
+        .manage(std::sync::Arc::clone(&cache))
+        .manage(std::sync::Arc::clone(&duplicate_map))
         .manage(std::sync::Arc::new(rocket::tokio::sync::Mutex::new(
-            duplicate_map,
+            chunk_map,
         )))
+        .manage(config)
+        .manage(rate_limit::RateLimiter::new(rate_limit))
+        .manage(timeouts)
+        .manage(eviction)
+        .manage(hot_cache)
+        .manage(ids)
+        .manage(std::sync::Arc::clone(&disk_monitor))
+        .manage(routes::FailedAttempts::default())
+        .attach(rocket::fairing::AdHoc::on_liftoff(
+            "Disk monitor",
+            move |_| {
+                Box::pin(async move {
+                    rocket::tokio::spawn(async move {
+                        loop {
+                            rocket::tokio::time::sleep(disk::CHECK_INTERVAL).await;
+                            disk_monitor.check();
+                        }
+                    });
+                })
+            },
+        ))
+        .attach(rocket::fairing::AdHoc::on_liftoff(
+            "Recompression",
+            move |_| {
+                Box::pin(async move {
+                    rocket::tokio::spawn(cache::run_recompression(
+                        storage_config,
+                        recompression,
+                        cache,
+                        duplicate_map,
+                    ));
+                })
+            },
+        ))
         .register(
             "/",
-            rocket::catchers![catchers::root_403, catchers::root_404],
-        )
-        .register(
-            "/upload",
-            rocket::catchers![catchers::upload_400, catchers::upload_413],
+            rocket::catchers![
+                catchers::root_403,
+                catchers::root_404,
+                catchers::root_429,
+                // Uploads are at /<filename>
+                catchers::upload_413,
+                catchers::upload_507
+            ],
         )
+        .register("/upload", rocket::catchers![catchers::upload_400])
         .mount(
             "/",
             rocket::routes![
@@ -49,6 +189,8 @@ pub async fn build_rocket() -> rocket::Rocket<rocket::Ignite> {
                 routes::home,
                 routes::upload,
                 routes::contact,
+                routes::download,
+                routes::paste,
                 routes::_404,
                 routes::front_js,
                 routes::front_bg_wasm,
@@ -57,31 +199,70 @@ pub async fn build_rocket() -> rocket::Rocket<rocket::Ignite> {
                 routes::static_css,
                 routes::favicon_ico,
                 routes::api_upload,
+                routes::api_paste,
                 routes::api_download,
                 routes::api_download_filename,
+                routes::api_download_head,
+                routes::api_download_filename_head,
                 routes::api_delete,
-                routes::info // routes::api_download_head,
+                routes::api_patch,
+                routes::info,
+                routes::health
             ],
         )
-        .ignite()
-        .await
-        .unwrap();
+}
 
-    // Safety:
-    //  This will only be writen once and at the reads are not yet loaded because the sever is not yet launched
-    unsafe {
-        let Some(limit) = rocket.config().limits.get("file") else {
-            panic!("Failed to retrieve the file limit from the config");
-        };
-        FILE_REQ_SIZE_LIMIT = limit;
-    }
+#[cfg(test)]
+pub async fn build_test_rocket() -> rocket::Rocket<rocket::Ignite> {
+    build_test_rocket_from(rocket::Config::figment()).await
+}
 
-    rocket
+// Every test gets its own cache directory, so they can run in parallel
+// It's managed by the rocket, and removed with it once the test's client is dropped
+#[cfg(test)]
+pub async fn build_test_rocket_from(
+    figment: rocket::figment::Figment,
+) -> rocket::Rocket<rocket::Ignite> {
+    let cache_dir = tempfile::Builder::new()
+        .prefix("storage_server_test_")
+        .tempdir()
+        .unwrap();
+
+    // Tests shouldn't depend on how full the machine's disk is
+    build_unignited(
+        figment
+            .merge(("storage.cache_dir", cache_dir.path()))
+            .merge(("storage.reserved_space", 0))
+            .merge(("storage.critical_space", 0)),
+    )
+    .manage(cache_dir)
+    .ignite()
+    .await
+    .unwrap()
 }
 
 #[rocket::main]
 async fn main() {
     use log::LevelFilter;
+
+    // Maintenance commands don't start the server, and their output shouldn't be mixed with logs
+    match cli::parse(std::env::args().skip(1)) {
+        Ok(None) => (),
+        Ok(Some(args)) => std::process::exit(cli::run(args)),
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::usage());
+            std::process::exit(2)
+        }
+    }
+
+    let log_dir = match config::StorageConfig::from_figment(&rocket::Config::figment()) {
+        Ok(config) => config.log_dir,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1)
+        }
+    };
+
     let filters = [
         ("rocket", LevelFilter::Warn),
         ("rocket::server.rs", LevelFilter::Off), // on 0.5.1, it only has infos about querying a 404 and catcher panicking
@@ -93,13 +274,13 @@ async fn main() {
             .filters(&filters),
         logger::Config::default()
             .output(logger::Output::new_timed_file(
-                "./log/.log",
+                &log_dir.join(".log").display().to_string(),
                 std::time::Duration::from_secs(86400), // A day
             ))
             .filters(&filters),
         logger::Config::default()
             .output(logger::Output::new_timed_file(
-                "./log/rocket.log",
+                &log_dir.join("rocket.log").display().to_string(),
                 std::time::Duration::from_secs(86400), // A day
             ))
             .level(LevelFilter::Off)
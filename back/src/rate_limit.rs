@@ -0,0 +1,332 @@
+// Per client budgets for uploads and downloads, so a single client can't take every worker
+//
+// Each direction has its own request rate (per minute), concurrent streams and bandwidth budget, see config::RateLimitConfig
+// The request guards (UploadSlot / DownloadSlot) check the first two, over-limit requests get a 429 with 'Retry-After'
+// (see catchers::root_429), the slot is then held by the stream (response::Throttled) which uses the bandwidth budget
+
+// How long to tell a client to wait when it has too many streams running, we can't know when one will end
+const STREAM_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(5);
+const WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Idle clients are only cleaned up past that, no need to go through the map on every request
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    Addr(String),
+    // API keys will go here
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+// Stored in the request's local cache when a guard refuses it, for the catcher to read
+pub struct RetryAfter(pub std::time::Duration);
+
+pub struct RateLimiter {
+    config: crate::config::RateLimitConfig,
+    clients: dashmap::DashMap<ClientKey, std::sync::Arc<ClientState>>,
+}
+
+struct ClientState {
+    upload: DirectionState,
+    download: DirectionState,
+}
+
+struct DirectionState {
+    // Requests in the current window, and when it started
+    window: std::sync::Mutex<Option<(u32, std::time::Instant)>>,
+    streams: std::sync::atomic::AtomicU32,
+    bandwidth: Option<Bandwidth>,
+}
+
+impl DirectionState {
+    fn new(budget: &crate::config::Budget) -> Self {
+        Self {
+            window: Default::default(),
+            streams: Default::default(),
+            bandwidth: (budget.bytes_per_second != 0)
+                .then(|| Bandwidth::new(budget.bytes_per_second.as_u64())),
+        }
+    }
+}
+
+impl ClientState {
+    fn new(config: &crate::config::RateLimitConfig) -> Self {
+        Self {
+            upload: DirectionState::new(&config.upload),
+            download: DirectionState::new(&config.download),
+        }
+    }
+
+    fn direction(&self, direction: Direction) -> &DirectionState {
+        match direction {
+            Direction::Upload => &self.upload,
+            Direction::Download => &self.download,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        use std::sync::atomic::Ordering;
+
+        [&self.upload, &self.download].iter().all(|state| {
+            state.streams.load(Ordering::Acquire) == 0
+                && state
+                    .window
+                    .lock()
+                    .unwrap()
+                    .is_none_or(|(_, start)| start.elapsed() >= WINDOW)
+        })
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: crate::config::RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Default::default(),
+        }
+    }
+
+    // Takes a request and a stream from the client's budget, or returns how long it should wait
+    pub fn acquire(
+        &self,
+        key: ClientKey,
+        direction: Direction,
+    ) -> Result<Slot, std::time::Duration> {
+        use std::{sync::atomic::Ordering, time::Instant};
+
+        let budget = match direction {
+            Direction::Upload => self.config.upload,
+            Direction::Download => self.config.download,
+        };
+
+        if self.clients.len() > MAX_TRACKED_CLIENTS {
+            self.clients.retain(|_, client| !client.is_idle());
+        }
+
+        let client = self
+            .clients
+            .entry(key)
+            .or_insert_with(|| std::sync::Arc::new(ClientState::new(&self.config)))
+            .clone();
+        let state = client.direction(direction);
+
+        if budget.requests_per_minute != 0 {
+            let mut window = state.window.lock().unwrap();
+
+            match *window {
+                Some((count, start)) if start.elapsed() < WINDOW => {
+                    if count >= budget.requests_per_minute {
+                        return Err(WINDOW - start.elapsed());
+                    }
+                    *window = Some((count + 1, start));
+                }
+                _ => *window = Some((1, Instant::now())),
+            }
+        }
+
+        // Always counted, idle clients are the ones without streams
+        let streams = state.streams.fetch_add(1, Ordering::AcqRel);
+        if budget.concurrent_streams != 0 && streams >= budget.concurrent_streams {
+            state.streams.fetch_sub(1, Ordering::AcqRel);
+            return Err(STREAM_RETRY_AFTER);
+        }
+
+        Ok(Slot { client, direction })
+    }
+}
+
+// A running upload or download, gives its stream back to the client's budget when dropped
+pub struct Slot {
+    client: std::sync::Arc<ClientState>,
+    direction: Direction,
+}
+
+impl Slot {
+    pub fn bandwidth(&self) -> Option<&Bandwidth> {
+        self.client.direction(self.direction).bandwidth.as_ref()
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.client
+            .direction(self.direction)
+            .streams
+            .fetch_sub(1, std::sync::atomic::Ordering::AcqRel);
+    }
+}
+
+// Token bucket, holding up to a second worth of bytes
+pub struct Bandwidth {
+    bytes_per_second: f64,
+    // Available bytes (negative when streams sharing it took more at once), and when it was last refilled
+    state: std::sync::Mutex<(f64, std::time::Instant)>,
+}
+
+impl Bandwidth {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second as f64,
+            state: std::sync::Mutex::new((bytes_per_second as f64, std::time::Instant::now())),
+        }
+    }
+
+    // How many bytes can be sent right now, or how long until some can
+    pub fn available(&self) -> Result<usize, std::time::Duration> {
+        use std::time::{Duration, Instant};
+
+        // Waiting for a few KB at least, reading byte by byte would be a waste
+        let min = self.bytes_per_second.min(8. * 1024.);
+
+        let mut state = self.state.lock().unwrap();
+        let (available, last_refill) = &mut *state;
+
+        *available = (*available + last_refill.elapsed().as_secs_f64() * self.bytes_per_second)
+            .min(self.bytes_per_second);
+        *last_refill = Instant::now();
+
+        if *available < min {
+            return Err(Duration::from_secs_f64(
+                (min - *available) / self.bytes_per_second,
+            ));
+        }
+
+        Ok(*available as usize)
+    }
+
+    pub fn consume(&self, bytes: usize) {
+        self.state.lock().unwrap().0 -= bytes as f64;
+    }
+}
+
+async fn acquire(
+    req: &rocket::Request<'_>,
+    direction: Direction,
+) -> rocket::request::Outcome<Slot, ()> {
+    use rocket::{http::Status, outcome::Outcome};
+
+    let Outcome::Success(addr) = req.guard::<rocket_client_addr::ClientAddr>().await else {
+        return Outcome::Forward(Status::BadRequest);
+    };
+
+    let Some(limiter) = req.rocket().state::<RateLimiter>() else {
+        return Outcome::Error((Status::InternalServerError, ()));
+    };
+
+    let key = ClientKey::Addr(
+        addr.get_ipv4_string()
+            .unwrap_or_else(|| addr.get_ipv6_string()),
+    );
+
+    match limiter.acquire(key, direction) {
+        Ok(slot) => Outcome::Success(slot),
+        Err(retry_after) => {
+            warn!("[{addr}] Over its {direction:?} budget, retry after {retry_after:?}");
+            req.local_cache(|| RetryAfter(retry_after));
+            Outcome::Error((Status::TooManyRequests, ()))
+        }
+    }
+}
+
+pub struct UploadSlot(Slot);
+
+impl UploadSlot {
+    pub fn into_inner(self) -> Slot {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for UploadSlot {
+    type Error = ();
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        acquire(req, Direction::Upload).await.map(UploadSlot)
+    }
+}
+
+pub struct DownloadSlot(Slot);
+
+impl DownloadSlot {
+    pub fn into_inner(self) -> Slot {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for DownloadSlot {
+    type Error = ();
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        acquire(req, Direction::Download).await.map(DownloadSlot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ClientKey, Direction, RateLimiter},
+        crate::config::{Budget, RateLimitConfig},
+    };
+
+    fn limiter() -> RateLimiter {
+        use rocket::data::ByteUnit;
+
+        RateLimiter::new(RateLimitConfig {
+            upload: Budget {
+                requests_per_minute: 3,
+                concurrent_streams: 1,
+                bytes_per_second: ByteUnit::Byte(0),
+            },
+            download: Budget::default(),
+        })
+    }
+
+    fn key(addr: &str) -> ClientKey {
+        ClientKey::Addr(addr.to_string())
+    }
+
+    #[test]
+    fn test_concurrent_streams() {
+        let limiter = limiter();
+
+        let slot = limiter.acquire(key("1.1.1.1"), Direction::Upload).unwrap();
+        assert!(limiter.acquire(key("1.1.1.1"), Direction::Upload).is_err());
+
+        // Other clients and directions have their own budget
+        assert!(limiter.acquire(key("2.2.2.2"), Direction::Upload).is_ok());
+        assert!(limiter.acquire(key("1.1.1.1"), Direction::Download).is_ok());
+
+        drop(slot);
+        assert!(limiter.acquire(key("1.1.1.1"), Direction::Upload).is_ok());
+    }
+
+    #[test]
+    fn test_request_rate() {
+        let limiter = limiter();
+
+        for _ in 0..3 {
+            assert!(limiter.acquire(key("1.1.1.1"), Direction::Upload).is_ok());
+        }
+
+        let retry_after = limiter
+            .acquire(key("1.1.1.1"), Direction::Upload)
+            .err()
+            .unwrap();
+        assert!(retry_after <= super::WINDOW);
+
+        // Unlimited
+        for _ in 0..100 {
+            assert!(limiter.acquire(key("1.1.1.1"), Direction::Download).is_ok());
+        }
+    }
+}
@@ -3,6 +3,10 @@ pub enum ResponseContent {
     Stream(Box<dyn std::io::Read + Send>),
     AsyncStream(Box<dyn tokio::io::AsyncRead + Send + Unpin>),
     AsyncBufStream(Box<dyn tokio::io::AsyncBufRead + Send + Unpin>),
+    // Streamed like AsyncStream, with its size known ahead and sent as the 'Content-Length'
+    SizedStream(u64, Box<dyn tokio::io::AsyncRead + Send + Unpin>),
+    // No body, but rocket still sends that size as the 'Content-Length', for HEAD responses
+    SizeOnly(u64),
 }
 
 impl From<Vec<u8>> for ResponseContent {
@@ -50,6 +54,8 @@ pub struct Response {
     headers: std::collections::HashMap<String, String>,
     content: ResponseContent,
     content_type: rocket::http::ContentType,
+    // Held until the body is sent, streamed bodies are throttled with its bandwidth budget
+    slot: Option<crate::rate_limit::Slot>,
 }
 
 impl Response {
@@ -69,10 +75,6 @@ impl Response {
     pub fn status(&self) -> &rocket::http::Status {
         &self.status
     }
-
-    pub fn headers(&self) -> &std::collections::HashMap<String, String> {
-        &self.headers
-    }
 }
 
 impl<'r> rocket::response::Responder<'r, 'static> for Response {
@@ -97,14 +99,26 @@ impl<'r> rocket::response::Responder<'r, 'static> for Response {
                 resp.sized_body(vec.len(), Cursor::new(vec));
             }
             ResponseContent::Stream(reader) => {
-                use tokio_util::compat::FuturesAsyncReadCompatExt as _;
-                resp.streamed_body(futures::io::AllowStdIo::new(reader).compat());
+                // Off the runtime, see blocking.rs
+                resp.streamed_body(Throttled::new(
+                    crate::blocking::BlockingReader::new(reader),
+                    self.slot,
+                ));
             }
             ResponseContent::AsyncStream(async_read) => {
-                resp.streamed_body(async_read);
+                resp.streamed_body(Throttled::new(async_read, self.slot));
             }
             ResponseContent::AsyncBufStream(async_buf_read) => {
-                resp.streamed_body(async_buf_read);
+                resp.streamed_body(Throttled::new(async_buf_read, self.slot));
+            }
+            ResponseContent::SizedStream(size, async_read) => {
+                resp.sized_body(
+                    size as usize,
+                    Unseekable(Throttled::new(async_read, self.slot)),
+                );
+            }
+            ResponseContent::SizeOnly(size) => {
+                resp.sized_body(size as usize, Cursor::new(Vec::new()));
             }
         }
 
@@ -142,6 +156,11 @@ impl ResponseBuilder {
         self
     }
 
+    pub fn with_slot(mut self, slot: crate::rate_limit::Slot) -> Self {
+        self.inner.slot = Some(slot);
+        self
+    }
+
     pub fn build(self) -> Response {
         self.inner
     }
@@ -160,7 +179,95 @@ impl Default for ResponseBuilder {
                 headers: HashMap::new(),
                 content: ResponseContent::Sized(Vec::new()),
                 content_type: ContentType::Any,
+                slot: None,
             },
         }
     }
 }
+
+// Rocket only seeks sized bodies to find their size, which SizedStream already gives
+struct Unseekable<R>(R);
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Unseekable<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<R: Unpin> tokio::io::AsyncSeek for Unseekable<R> {
+    fn start_seek(self: std::pin::Pin<&mut Self>, _: std::io::SeekFrom) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<u64>> {
+        std::task::Poll::Ready(Err(std::io::ErrorKind::Unsupported.into()))
+    }
+}
+
+// Reads at most what the slot's bandwidth budget allows, and keeps the slot until dropped
+pub struct Throttled<R> {
+    inner: R,
+    slot: Option<crate::rate_limit::Slot>,
+    sleep: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<R> Throttled<R> {
+    pub fn new(inner: R, slot: Option<crate::rate_limit::Slot>) -> Self {
+        Self {
+            inner,
+            slot,
+            sleep: None,
+        }
+    }
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Throttled<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use {
+            std::{future::Future as _, pin::Pin, task::Poll},
+            tokio::io::ReadBuf,
+        };
+
+        let this = &mut *self;
+
+        let Some(bandwidth) = this.slot.as_ref().and_then(|slot| slot.bandwidth()) else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+
+        loop {
+            if let Some(sleep) = this.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.sleep = None;
+            }
+
+            match bandwidth.available() {
+                Ok(available) => {
+                    let mut limited =
+                        ReadBuf::new(buf.initialize_unfilled_to(available.min(buf.remaining())));
+
+                    let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+
+                    let read = limited.filled().len();
+                    buf.advance(read);
+                    bandwidth.consume(read);
+
+                    return result;
+                }
+                Err(wait) => this.sleep = Some(Box::pin(tokio::time::sleep(wait))),
+            }
+        }
+    }
+}
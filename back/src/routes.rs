@@ -2,8 +2,14 @@
 mod delete_route;
 #[path = "routes/download.rs"]
 mod download_route;
+#[path = "routes/health.rs"]
+mod health_route;
 #[path = "routes/info.rs"]
 mod info_route;
+#[path = "routes/paste.rs"]
+mod paste_route;
+#[path = "routes/patch.rs"]
+mod patch_route;
 #[path = "routes/upload.rs"] // Naming conflict in main when registering route
 mod upload_route;
 
@@ -12,8 +18,14 @@ pub use delete_route::*;
 #[allow(unused_imports)] // Used by main.rs
 pub use download_route::*;
 #[allow(unused_imports)] // Used by main.rs
+pub use health_route::*;
+#[allow(unused_imports)] // Used by main.rs
 pub use info_route::*;
 #[allow(unused_imports)] // Used by main.rs
+pub use paste_route::*;
+#[allow(unused_imports)] // Used by main.rs
+pub use patch_route::*;
+#[allow(unused_imports)] // Used by main.rs
 pub use upload_route::*;
 
 // Here are routes that are managed by the front end router, so just serve the page and let it do its things
@@ -31,6 +43,8 @@ macro_rules! front_route {
 front_route!(home, "/home");
 front_route!(upload, "/upload");
 front_route!(contact, "/contact");
+front_route!(download, "/download");
+front_route!(paste, "/p/<_>");
 front_route!(_404, "/404");
 
 #[rocket::get("/")]
@@ -98,6 +112,8 @@ static_dir_server!(
     &[
         "contact.css",
         "upload.css",
+        "download.css",
+        "paste.css",
         "notification.css",
         "home.css",
         "light_switch.css",
@@ -5,12 +5,18 @@
 ///     It returns Ok if the deletion was sucessfull, the error otherwise
 ///
 #[rocket::delete("/<uuidw>")]
+#[allow(clippy::too_many_arguments)] // Request guards
 pub async fn api_delete(
     uuidw: Option<super::UuidWrapper>,
-    cache: &rocket::State<crate::cache::CacheEntryMap>,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
     duplicate_map: &rocket::State<
         std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::DuplicateMap>>,
     >,
+    chunk_map: &rocket::State<std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::ChunkMap>>>,
+    config: &rocket::State<crate::config::StorageConfig>,
+    disk_monitor: &rocket::State<std::sync::Arc<crate::disk::DiskMonitor>>,
+    hot_cache: &rocket::State<crate::hot_cache::HotCache>,
 
     // See route::api_download's comment
     addr: rocket_client_addr::ClientAddr,
@@ -22,17 +28,25 @@ pub async fn api_delete(
     use rocket::http::{ContentType, Status};
 
     // wrong route
-    let Some(uuidw) = uuidw else {
+    let Some(uuid) = uuidw.and_then(|uuidw| uuidw.resolve(ids, cache)) else {
         let addr_string = addr
             .get_ipv4_string()
             .unwrap_or_else(|| addr.get_ipv6_string());
         return crate::catchers::inner_404(addr_string, method, uri, c_type).await;
     };
 
-    let uuid = *uuidw;
-
     info!("[{addr}] DELETE request of {uuid}");
 
+    // Removing an entry rewrites the duplicate and chunk maps, see disk.rs
+    if disk_monitor.state() == crate::disk::DiskState::ReadOnly {
+        warn!("[{addr}] Refusing to delete {uuid}, the server is read-only");
+        return Response::builder()
+            .with_status(Status::ServiceUnavailable)
+            .with_content_type(ContentType::Text)
+            .with_content("The server is read-only, try again later")
+            .build();
+    }
+
     let Some((_uuid, entry)) = cache.remove(&uuid) else {
         error!("Could not find entry for {uuid}");
         return Response::builder()
@@ -40,7 +54,14 @@ pub async fn api_delete(
             .build();
     };
 
-    if let Err(e) = entry.delete(std::sync::Arc::clone(duplicate_map)).await {
+    if let Err(e) = entry
+        .delete(
+            config,
+            std::sync::Arc::clone(duplicate_map),
+            std::sync::Arc::clone(chunk_map),
+        )
+        .await
+    {
         error!("Failed to delete {uuid} due to: {e}");
 
         cache.insert(entry.uuid(), entry);
@@ -52,6 +73,9 @@ pub async fn api_delete(
             .build();
     };
 
+    hot_cache.remove(&uuid);
+    ids.remove(uuid, entry.upload_info().details());
+
     debug!("Successfully deleted {uuid}");
 
     Response::builder()
@@ -64,7 +88,7 @@ pub async fn api_delete(
 #[cfg(test)]
 mod tests {
     use {
-        crate::build_rocket,
+        crate::build_test_rocket,
         rocket::{http::Status, local::asynchronous::Client},
         std::str::FromStr,
     };
@@ -73,7 +97,7 @@ mod tests {
     async fn test_delete() {
         use rocket::http::Header;
         let base_filename = "test.file";
-        let client = Client::tracked(build_rocket().await)
+        let client = Client::tracked(build_test_rocket().await)
             .await
             .expect("valid rocket instance");
 
@@ -4,33 +4,169 @@ lazy_static! {
         r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-4[0-9a-fA-F]{3}-[89abAB][0-9a-fA-F]{3}-[0-9a-fA-F]{12}$"
     )
     .unwrap();
+    // Short ids and aliases, see cache/ids.rs (up to ids::MAX_ALIAS_LENGTH)
+    pub static ref NAME_VALIDATION_REGEX: regex::Regex =
+        regex::Regex::new(r"^[0-9a-zA-Z_-]{1,64}$").unwrap();
 }
 
-pub struct UuidWrapper(uuid::Uuid);
+// An entry in a url, by its uuid or one of its other names (short id or alias)
+pub enum UuidWrapper {
+    Uuid(uuid::Uuid),
+    Name(String),
+}
 
 // uuid::Uuid does not implement rocket::request::FromParam, so I made a simple wrapper.
-// Maybe shouldn't be there though
+// Names are only checked here, resolving them needs the id map (see UuidWrapper::resolve)
 impl<'p> rocket::request::FromParam<'p> for UuidWrapper {
     type Error = crate::error::UuidParseError;
 
     fn from_param(param: &'p str) -> Result<Self, Self::Error> {
         use {crate::error::UuidParseError, std::str::FromStr, uuid::Uuid};
 
-        if !UUID_VALIDATION_REGEX.is_match(param) {
-            return Err(UuidParseError::Regex);
+        if UUID_VALIDATION_REGEX.is_match(param) {
+            return Ok(UuidWrapper::Uuid(
+                Uuid::from_str(param).map_err(|_e| UuidParseError::Convert)?,
+            ));
+        }
+
+        if NAME_VALIDATION_REGEX.is_match(param) {
+            return Ok(UuidWrapper::Name(param.to_string()));
+        }
+
+        Err(UuidParseError::Regex)
+    }
+}
+
+impl UuidWrapper {
+    // The uuid it stands for, None for names no entry has
+    // Uuids are returned as is, the routes tell the client when they don't exist
+    pub fn resolve(
+        &self,
+        ids: &crate::cache::IdMap,
+        cache: &crate::cache::CacheEntryMap,
+    ) -> Option<uuid::Uuid> {
+        match self {
+            UuidWrapper::Uuid(uuid) => Some(*uuid),
+            UuidWrapper::Name(name) => ids.resolve(name, cache),
+        }
+    }
+}
+
+// Password sent with a request, either in the 'X-Password' header or with basic auth (the user name is ignored)
+// Basic auth is what makes browsers show a password prompt
+pub struct Password(Option<String>);
+
+impl Password {
+    pub fn into_inner(self) -> Option<String> {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Password {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        use base64::Engine as _;
+
+        let headers = req.headers();
+
+        let password = headers
+            .get_one("X-Password")
+            .map(str::to_string)
+            .or_else(|| {
+                let encoded = headers.get_one("Authorization")?.strip_prefix("Basic ")?;
+                let decoded = base64::engine::general_purpose::STANDARD
+                    .decode(encoded.trim())
+                    .ok()?;
+                let decoded = String::from_utf8(decoded).ok()?;
+                let (_user, password) = decoded.split_once(':')?;
+                Some(password.to_string())
+            })
+            .filter(|password| !password.is_empty());
+
+        rocket::request::Outcome::Success(Password(password))
+    }
+}
+
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const FAILED_ATTEMPTS_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Failed password attempts of each entry, so passwords can't be brute forced
+#[derive(Default)]
+pub struct FailedAttempts(dashmap::DashMap<uuid::Uuid, (u32, std::time::Instant)>);
+
+impl FailedAttempts {
+    // Counted as failed before the password is checked, or parallel attempts would all get through before the first
+    // one fails. Refused with how long until the entry can be tried again once it's locked
+    fn attempt(&self, uuid: uuid::Uuid) -> Result<(), std::time::Duration> {
+        use std::time::Instant;
+
+        let mut attempts = self.0.entry(uuid).or_insert((0, Instant::now()));
+
+        if attempts.1.elapsed() >= FAILED_ATTEMPTS_WINDOW {
+            *attempts = (0, Instant::now());
         }
 
-        Ok(UuidWrapper(
-            Uuid::from_str(param).map_err(|_e| UuidParseError::Convert)?,
-        ))
+        if attempts.0 >= MAX_FAILED_ATTEMPTS {
+            return Err(FAILED_ATTEMPTS_WINDOW.saturating_sub(attempts.1.elapsed()));
+        }
+
+        attempts.0 += 1;
+        Ok(())
+    }
+
+    // The right password was given
+    fn clear(&self, uuid: &uuid::Uuid) {
+        self.0.remove(uuid);
+    }
+}
+
+// 'If-None-Match' and 'If-Modified-Since' of a request, so clients that already have the file don't download it again
+pub struct Conditional {
+    if_none_match: Option<String>,
+    if_modified_since: Option<std::time::SystemTime>,
+}
+
+impl Conditional {
+    // If the client's copy is the current one
+    fn is_fresh(&self, etag: &str, modified: u64) -> bool {
+        use std::time::UNIX_EPOCH;
+
+        // If-Modified-Since is ignored when both are sent (RFC 9110 13.1.3)
+        if let Some(if_none_match) = &self.if_none_match {
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+        }
+
+        self.if_modified_since.is_some_and(|since| {
+            since
+                .duration_since(UNIX_EPOCH)
+                .is_ok_and(|since| since.as_secs() >= modified)
+        })
     }
 }
 
-impl std::ops::Deref for UuidWrapper {
-    type Target = uuid::Uuid;
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Conditional {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let headers = req.headers();
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+        rocket::request::Outcome::Success(Conditional {
+            if_none_match: headers.get_one("If-None-Match").map(str::to_string),
+            // Invalid dates are ignored, like the RFC says
+            if_modified_since: headers
+                .get_one("If-Modified-Since")
+                .and_then(|date| httpdate::parse_http_date(date).ok()),
+        })
     }
 }
 
@@ -43,9 +179,18 @@ impl std::ops::Deref for UuidWrapper {
 ///         As for the filename, it sets the 'Content-Disposition' header for the browser to interpret
 ///
 #[rocket::get("/<uuidw>")]
+#[allow(clippy::too_many_arguments)] // Request guards
 pub async fn api_download(
     uuidw: Option<UuidWrapper>,
-    cache: &rocket::State<crate::cache::CacheEntryMap>,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
+    config: &rocket::State<crate::config::StorageConfig>,
+    password: Password,
+    failed_attempts: &rocket::State<FailedAttempts>,
+    conditional: Conditional,
+    slot: crate::rate_limit::DownloadSlot,
+    timeouts: &rocket::State<crate::config::TimeoutConfig>,
+    hot_cache: &rocket::State<crate::hot_cache::HotCache>,
 
     // About the optional uuidw and the ugly ton of params:
     //  The routing system in rocket works a bit weirdly, since you can only have 1
@@ -64,35 +209,78 @@ pub async fn api_download(
     c_type: Option<&rocket::http::ContentType>,
 ) -> crate::response::Response {
     use {
-        crate::{error::CacheError, response::ResponseBuilder},
+        crate::{
+            error::CacheError,
+            response::{ResponseBuilder, ResponseContent},
+        },
         rocket::http::{ContentType, Status},
         std::time::Instant,
     };
 
     let start_timer = Instant::now();
 
-    let Some(uuidw) = uuidw else {
+    // Unknown names are most likely mistyped pages
+    let Some(uuid) = uuidw.and_then(|uuidw| uuidw.resolve(ids, cache)) else {
         let addr_string = addr
             .get_ipv4_string()
             .unwrap_or_else(|| addr.get_ipv6_string());
         return crate::catchers::inner_404(addr_string, method, uri, c_type).await;
     };
 
-    let uuid = *uuidw;
-
     info!("[{addr}] DOWNLOAD request of {uuid}");
 
-    let Some(cache_entry) = cache.get(&uuid) else {
-        error!("[{uuid}] The given uuid doesn't correspnd to any cache entry");
-        return ResponseBuilder::default()
-            .with_status(Status::NotFound)
-            .with_content("The given id doesn't correspond to any cache entry")
-            .with_content_type(ContentType::Text)
-            .build();
+    let cache_entry = match authorize(uuid, cache, password, failed_attempts).await {
+        Ok(cache_entry) => cache_entry,
+        Err(resp) => return resp,
+    };
+
+    let (etag, last_modified) = validators(&cache_entry);
+
+    if conditional.is_fresh(&etag, cache_entry.modified()) {
+        debug!("[{uuid}] Not modified");
+        return not_modified(&etag, &last_modified);
+    }
+
+    let size = cache_entry.size().original();
+    let hot = hot_cache.accepts(size);
+    let meta = cache_entry.upload_info().clone();
+    let handle = cache_entry.handle();
+    drop(cache_entry);
+
+    // Even from the hot cache, the body is watched and throttled with the slot's budget (see response::Throttled)
+    // Sent with the same 'Content-Length' as HEAD
+    let respond = move |data_stream: Box<dyn rocket::tokio::io::AsyncRead + Send + Unpin>| {
+        let data_stream: Box<dyn rocket::tokio::io::AsyncRead + Send + Unpin> =
+            Box::new(crate::timeout::Watchdog::new(
+                data_stream,
+                uuid,
+                crate::rate_limit::Direction::Download,
+                timeouts.download,
+            ));
+
+        info!(
+            "[{uuid}] Responded in {}",
+            time::format(start_timer.elapsed(), 2)
+        );
+
+        found(&meta, &etag, &last_modified)
+            .with_content(ResponseContent::SizedStream(size, data_stream))
+            .with_slot(slot.into_inner())
+            .build()
     };
 
-    let (meta, data_stream) = match cache_entry.load().await {
-        Ok(meta_data) => meta_data,
+    if hot {
+        if let Some(data) = hot_cache.get(&uuid) {
+            handle.touch(config);
+
+            debug!("[{uuid}] Served from the hot cache");
+
+            return respond(Box::new(std::io::Cursor::new(data)));
+        }
+    }
+
+    let mut data_stream = match handle.load(config).await {
+        Ok(data_stream) => data_stream,
         // Err(CacheError::NotReady { uuid }) => {
         //     error!("[{uuid}] The requested cache is not ready yet");
         //     return ResponseBuilder::default()
@@ -127,31 +315,176 @@ pub async fn api_download(
         }
     };
 
-    info!(
-        "[{uuid}] Responded in {}",
-        time::format(start_timer.elapsed(), 2)
-    );
+    // Small enough to be kept, read it whole and send it as is
+    if hot {
+        use rocket::tokio::io::AsyncReadExt as _;
+
+        let mut data = Vec::new();
+
+        let data = match data_stream.read_to_end(&mut data).await {
+            Ok(_) => std::sync::Arc::<[u8]>::from(data),
+            Err(e) => {
+                error!("[{uuid}] Failed to read the content due to: {e}");
+                return ResponseBuilder::default()
+                    .with_status(Status::InternalServerError)
+                    .with_content("Could not acces given id's content")
+                    .with_content_type(ContentType::Text)
+                    .build();
+            }
+        };
+
+        hot_cache.insert(uuid, std::sync::Arc::clone(&data));
+
+        return respond(Box::new(std::io::Cursor::new(data)));
+    }
+
+    // Decompression runs off the runtime, see blocking.rs
+    respond(Box::new(data_stream))
+}
+
+// Shared by GET and HEAD, the entry if it exists and its password (if it has one) was given
+// The entry isn't held across awaits, its shard of the map would stay locked
+async fn authorize<'a>(
+    uuid: uuid::Uuid,
+    cache: &'a crate::cache::CacheEntryMap,
+    password: Password,
+    failed_attempts: &FailedAttempts,
+) -> Result<
+    dashmap::mapref::one::Ref<'a, uuid::Uuid, crate::cache::CacheEntry>,
+    crate::response::Response,
+> {
+    use {
+        crate::response::ResponseBuilder,
+        rocket::http::{ContentType, Status},
+    };
+
+    let Some(cache_entry) = cache.get(&uuid) else {
+        error!("[{uuid}] The given uuid doesn't correspnd to any cache entry");
+        return Err(ResponseBuilder::default()
+            .with_status(Status::NotFound)
+            .with_content("The given id doesn't correspond to any cache entry")
+            .with_content_type(ContentType::Text)
+            .build());
+    };
+
+    // Not held while the password is checked
+    let password_hash = cache_entry
+        .upload_info()
+        .password_hash()
+        .map(str::to_string);
+    drop(cache_entry);
+
+    if let Some(password_hash) = password_hash {
+        let Some(password) = password.into_inner() else {
+            debug!("[{uuid}] No password given");
+            return Err(ResponseBuilder::default()
+                .with_status(Status::Unauthorized)
+                .with_header(
+                    "WWW-Authenticate",
+                    "Basic realm=\"Password protected file\", charset=\"UTF-8\"",
+                )
+                .with_content("This file is password protected")
+                .with_content_type(ContentType::Text)
+                .build());
+        };
+
+        if let Err(retry_after) = failed_attempts.attempt(uuid) {
+            warn!("[{uuid}] Too many failed password attempts, refusing for now");
+            return Err(ResponseBuilder::default()
+                .with_status(Status::TooManyRequests)
+                .with_header("Retry-After", &(retry_after.as_secs() + 1).to_string())
+                .with_content("Too many failed attempts, try again later")
+                .with_content_type(ContentType::Text)
+                .build());
+        }
+
+        // Argon2 is slow on purpose, don't block the runtime with it
+        let valid = rocket::tokio::task::spawn_blocking(move || {
+            crate::cache::verify_password(&password_hash, &password)
+        })
+        .await
+        .unwrap_or(false);
+
+        if !valid {
+            warn!("[{uuid}] Wrong password");
+            return Err(ResponseBuilder::default()
+                .with_status(Status::Unauthorized)
+                .with_header(
+                    "WWW-Authenticate",
+                    "Basic realm=\"Password protected file\", charset=\"UTF-8\"",
+                )
+                .with_content("Wrong password")
+                .with_content_type(ContentType::Text)
+                .build());
+        }
+
+        failed_attempts.clear(&uuid);
+    }
+
+    // Deleted in the meantime
+    cache.get(&uuid).ok_or_else(|| {
+        ResponseBuilder::default()
+            .with_status(Status::NotFound)
+            .with_content("The given id doesn't correspond to any cache entry")
+            .with_content_type(ContentType::Text)
+            .build()
+    })
+}
+
+// The entry's 'ETag' and 'Last-Modified', also used by edits (see patch.rs)
+// Its content never changes once uploaded, so the modification time and size are enough (nginx does the same with
+// files), plus the revision as the name can be edited twice in the same second
+pub fn validators(cache_entry: &crate::cache::CacheEntry) -> (String, String) {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    (
+        format!(
+            "\"{:x}-{:x}-{:x}\"",
+            cache_entry.modified(),
+            cache_entry.size().original(),
+            cache_entry.revision()
+        ),
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(cache_entry.modified())),
+    )
+}
+
+fn not_modified(etag: &str, last_modified: &str) -> crate::response::Response {
+    use {crate::response::ResponseBuilder, rocket::http::Status};
+
+    ResponseBuilder::default()
+        .with_status(Status::NotModified)
+        .with_header("ETag", etag)
+        .with_header("Last-Modified", last_modified)
+        .build()
+}
+
+// Headers of a successful GET or HEAD, without the content
+fn found(
+    meta: &crate::cache::UploadInfo,
+    etag: &str,
+    last_modified: &str,
+) -> crate::response::ResponseBuilder {
+    use {
+        crate::response::ResponseBuilder,
+        rocket::http::{ContentType, Status},
+    };
+
+    // Pastes are served raw, as text the browser shows (see paste.rs)
+    let paste = meta.details().paste;
 
     ResponseBuilder::default()
         .with_status(Status::Ok)
-        .with_content(data_stream)
-        .with_content_type(ContentType::Binary)
+        .with_content_type(if paste {
+            ContentType::Text
+        } else {
+            ContentType::Binary
+        })
         .with_header(
             "Content-Disposition",
-            // This would add a '.' at the end of a file name if it had no extension.
-            // ie: An uploaded file named 'Hellow' would be sent back as 'Hellow.'
-            // Caught by unit tests, but damn what a dumb mistake
-            &if meta.extension().is_empty() {
-                format!("attachment; filename=\"{}\"", meta.name(),)
-            } else {
-                format!(
-                    "attachment; filename=\"{}.{}\"",
-                    meta.name(),
-                    meta.extension()
-                )
-            },
+            &crate::filename::content_disposition(&meta.file_name(), paste),
         )
-        .build()
+        .with_header("ETag", etag)
+        .with_header("Last-Modified", last_modified)
 }
 
 ///
@@ -164,10 +497,19 @@ pub async fn api_download(
 ///     This route is a proxy and mostly for curl users to be able to use '-O' (download with auto file name)
 ///
 #[rocket::get("/<uuidw>/<filename>")]
+#[allow(clippy::too_many_arguments)] // Request guards
 pub async fn api_download_filename(
     uuidw: Option<UuidWrapper>,
     filename: &str,
-    cache: &rocket::State<crate::cache::CacheEntryMap>,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
+    config: &rocket::State<crate::config::StorageConfig>,
+    password: Password,
+    failed_attempts: &rocket::State<FailedAttempts>,
+    conditional: Conditional,
+    slot: crate::rate_limit::DownloadSlot,
+    timeouts: &rocket::State<crate::config::TimeoutConfig>,
+    hot_cache: &rocket::State<crate::hot_cache::HotCache>,
     client_addr: rocket_client_addr::ClientAddr,
 
     // Ewww
@@ -175,43 +517,157 @@ pub async fn api_download_filename(
     uri: &rocket::http::uri::Origin<'_>,
     c_type: Option<&rocket::http::ContentType>,
 ) -> crate::response::Response {
-    use {
-        crate::response::ResponseBuilder,
-        rocket::http::{ContentType, Status},
+    let Some(uuid) = uuidw.and_then(|uuidw| uuidw.resolve(ids, cache)) else {
+        let addr_string = client_addr
+            .get_ipv4_string()
+            .unwrap_or_else(|| client_addr.get_ipv6_string());
+        return crate::catchers::inner_404(addr_string, method, uri, c_type).await;
+    };
+
+    let resp = api_download(
+        Some(UuidWrapper::Uuid(uuid)),
+        cache,
+        ids,
+        config,
+        password,
+        failed_attempts,
+        conditional,
+        slot,
+        timeouts,
+        hot_cache,
+        client_addr,
+        method,
+        uri,
+        c_type,
+    )
+    .await;
+
+    check_filename(resp, filename, cache, uuid)
+}
+
+///
+/// HEAD version of api_download, same headers (with the 'Content-Length' of the decompressed file) without the content
+///
+///     The file isn't opened, so it doesn't count as a download
+///
+#[rocket::head("/<uuidw>")]
+#[allow(clippy::too_many_arguments)] // Request guards
+pub async fn api_download_head(
+    uuidw: Option<UuidWrapper>,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
+    password: Password,
+    failed_attempts: &rocket::State<FailedAttempts>,
+    conditional: Conditional,
+
+    // See api_download
+    addr: rocket_client_addr::ClientAddr,
+    method: rocket::http::Method,
+    uri: &rocket::http::uri::Origin<'_>,
+    c_type: Option<&rocket::http::ContentType>,
+) -> crate::response::Response {
+    use crate::response::ResponseContent;
+
+    let Some(uuid) = uuidw.and_then(|uuidw| uuidw.resolve(ids, cache)) else {
+        let addr_string = addr
+            .get_ipv4_string()
+            .unwrap_or_else(|| addr.get_ipv6_string());
+        return crate::catchers::inner_404(addr_string, method, uri, c_type).await;
     };
 
-    let Some(uuidw) = uuidw else {
+    info!("[{addr}] HEAD request of {uuid}");
+
+    let cache_entry = match authorize(uuid, cache, password, failed_attempts).await {
+        Ok(cache_entry) => cache_entry,
+        Err(resp) => return resp,
+    };
+
+    let (etag, last_modified) = validators(&cache_entry);
+
+    if conditional.is_fresh(&etag, cache_entry.modified()) {
+        return not_modified(&etag, &last_modified);
+    }
+
+    found(cache_entry.upload_info(), &etag, &last_modified)
+        .with_content(ResponseContent::SizeOnly(cache_entry.size().original()))
+        .build()
+}
+
+#[rocket::head("/<uuidw>/<filename>")]
+#[allow(clippy::too_many_arguments)] // Request guards
+pub async fn api_download_filename_head(
+    uuidw: Option<UuidWrapper>,
+    filename: &str,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
+    password: Password,
+    failed_attempts: &rocket::State<FailedAttempts>,
+    conditional: Conditional,
+    client_addr: rocket_client_addr::ClientAddr,
+    method: rocket::http::Method,
+    uri: &rocket::http::uri::Origin<'_>,
+    c_type: Option<&rocket::http::ContentType>,
+) -> crate::response::Response {
+    let Some(uuid) = uuidw.and_then(|uuidw| uuidw.resolve(ids, cache)) else {
         let addr_string = client_addr
             .get_ipv4_string()
             .unwrap_or_else(|| client_addr.get_ipv6_string());
         return crate::catchers::inner_404(addr_string, method, uri, c_type).await;
     };
 
-    let resp = api_download(Some(uuidw), cache, client_addr, method, uri, c_type).await;
+    let resp = api_download_head(
+        Some(UuidWrapper::Uuid(uuid)),
+        cache,
+        ids,
+        password,
+        failed_attempts,
+        conditional,
+        client_addr,
+        method,
+        uri,
+        c_type,
+    )
+    .await;
+
+    check_filename(resp, filename, cache, uuid)
+}
+
+// Makes sure the file name in a download url is the entry's one
+// The file name in the url was percent-decoded by rocket, compare it to the entry's
+fn check_filename(
+    resp: crate::response::Response,
+    filename: &str,
+    cache: &crate::cache::CacheEntryMap,
+    uuid: uuid::Uuid,
+) -> crate::response::Response {
+    use {
+        crate::response::ResponseBuilder,
+        rocket::http::{ContentType, Status},
+    };
 
     if resp.status() != &Status::Ok {
         // If the internal call returned an error, there is no point doing the filename verification
         return resp;
     }
 
-    let Some(content_disposition_header) = resp.headers().get("Content-Disposition") else {
-        error!("Could not find the 'Content-Disposition' header of the api_download internal call");
+    let Some(stored_filename) = cache
+        .get(&uuid)
+        .map(|entry| entry.upload_info().file_name())
+    else {
+        // Deleted in the meantime
         return ResponseBuilder::default()
-            .with_status(Status::InternalServerError)
+            .with_status(Status::NotFound)
+            .with_content("The given id doesn't correspond to any cache entry")
+            .with_content_type(ContentType::Text)
             .build();
     };
 
-    // This won't truncate the real file name as quotes are not allowed in filename / extensions (see upload.rs::FILENAME_VALIDATION_REGEX)
-    let header_filename = content_disposition_header
-        .replace("attachment; filename=\"", "")
-        .replace('"', "");
-
-    if header_filename != filename {
-        error!("The user supplied filename: '{filename}' but the one stored in metadata is '{header_filename}'");
+    if stored_filename != filename {
+        error!("The user supplied filename: '{filename}' but the one stored in metadata is '{stored_filename}'");
         return ResponseBuilder::default()
             .with_status(Status::BadRequest)
             .with_content(format!(
-                "Incorrect file name, did you meant '{header_filename}'?"
+                "Incorrect file name, did you meant '{stored_filename}'?"
             ))
             .with_content_type(ContentType::Text)
             .build();
@@ -220,30 +676,10 @@ pub async fn api_download_filename(
     resp
 }
 
-// Not sure why it's there, it seems to return only the file name
-// #[rocket::head("/<id>")]
-// pub async fn api_download_head(
-//     id: &str,
-//     cache: &rocket::State<rocket::tokio::sync::RwLock<crate::cache::Cache>>,
-// ) -> String {
-//     use {std::str::FromStr, uuid::Uuid};
-
-//     info!("Request of HEAD {id}");
-//     let uuid = Uuid::from_str(id).unwrap();
-
-//     let upload_info = cache.read().await.get_entry(uuid).await.unwrap();
-
-//     format!(
-//         "{}.{}",
-//         upload_info.upload_info().name(),
-//         upload_info.upload_info().extension()
-//     )
-// }
-
 #[cfg(test)]
 mod tests {
     use {
-        crate::build_rocket,
+        crate::build_test_rocket,
         rocket::{http::Status, local::asynchronous::Client},
         std::str::FromStr,
     };
@@ -253,7 +689,7 @@ mod tests {
         use rocket::http::Header;
         let base_filename = "Test";
 
-        let client = Client::tracked(build_rocket().await)
+        let client = Client::tracked(build_test_rocket().await)
             .await
             .expect("valid rocket instance");
 
@@ -297,7 +733,7 @@ mod tests {
         use rocket::http::Header;
         let base_filename = "test.file";
 
-        let client = Client::tracked(build_rocket().await)
+        let client = Client::tracked(build_test_rocket().await)
             .await
             .expect("valid rocket instance");
 
@@ -335,4 +771,479 @@ mod tests {
             format!("attachment; filename=\"{base_filename}\"")
         );
     }
+
+    #[rocket::async_test]
+    async fn test_download_password() {
+        use rocket::http::Header;
+
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/secret.txt")
+            .body("This is a protected file")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .header(Header::new("x-password", "hunter2"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = uuid::Uuid::from_str(&response.into_string().await.unwrap()).unwrap();
+
+        let get = |password: Option<Header<'static>>| {
+            let mut request = client
+                .get(format!("/{uuid}/secret.txt"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"));
+            if let Some(password) = password {
+                request = request.header(password);
+            }
+            request.dispatch()
+        };
+
+        let response = get(None).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.headers().get_one("WWW-Authenticate").is_some());
+
+        assert_eq!(
+            get(Some(Header::new("x-password", "hunter2")))
+                .await
+                .status(),
+            Status::Ok
+        );
+        // ':hunter2' in base64
+        assert_eq!(
+            get(Some(Header::new("Authorization", "Basic Omh1bnRlcjI=")))
+                .await
+                .status(),
+            Status::Ok
+        );
+
+        for _ in 0..5 {
+            assert_eq!(
+                get(Some(Header::new("x-password", "wrong"))).await.status(),
+                Status::Unauthorized
+            );
+        }
+
+        // Even the right one is refused once locked
+        let response = get(Some(Header::new("x-password", "hunter2"))).await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert!(response.headers().get_one("Retry-After").is_some());
+    }
+
+    #[rocket::async_test]
+    async fn test_download_password_parallel() {
+        use rocket::http::Header;
+
+        // Only the failed attempts should refuse them
+        let figment =
+            rocket::Config::figment().merge(("rate_limit.download.concurrent_streams", 0));
+        let client = Client::tracked(crate::build_test_rocket_from(figment).await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/secret.txt")
+            .body("This is a protected file")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .header(Header::new("x-password", "hunter2"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+
+        let responses = futures::future::join_all((0..8).map(|_| {
+            client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .header(Header::new("x-password", "wrong"))
+                .dispatch()
+        }))
+        .await;
+
+        let statuses = responses
+            .iter()
+            .map(|response| response.status())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|status| **status == Status::Unauthorized)
+                .count(),
+            5
+        );
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|status| **status == Status::TooManyRequests)
+                .count(),
+            3
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_download_hot_cache() {
+        use rocket::http::Header;
+
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/badge.svg")
+            .body("<svg></svg>")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response
+            .into_string()
+            .await
+            .unwrap()
+            .replace("Success: ", "");
+
+        // Read from disk, then from memory
+        for _ in 0..2 {
+            let response = client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.body().preset_size(), Some(11));
+            assert_eq!(
+                response.headers().get_one("Content-Disposition").unwrap(),
+                "attachment; filename=\"badge.svg\""
+            );
+            assert_eq!(response.into_string().await.unwrap(), "<svg></svg>");
+        }
+
+        let health = client
+            .get("/health")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(health.contains("\"hits\":1,\"misses\":1"));
+
+        let response = client
+            .delete(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_download_size() {
+        use rocket::http::Header;
+
+        // Read from disk every time
+        let figment = rocket::Config::figment().merge(("hot_cache.capacity", 0));
+        let client = Client::tracked(crate::build_test_rocket_from(figment).await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/notes.txt")
+            .body("Some notes")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+
+        let head = client
+            .head(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        let get = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(head.body().preset_size(), Some(10));
+        assert_eq!(get.body().preset_size(), Some(10));
+        assert_eq!(get.into_string().await.unwrap(), "Some notes");
+    }
+
+    #[rocket::async_test]
+    async fn test_download_hot_cache_slot() {
+        use rocket::http::Header;
+
+        let figment =
+            rocket::Config::figment().merge(("rate_limit.download.concurrent_streams", 1));
+        let client = Client::tracked(crate::build_test_rocket_from(figment).await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/badge.svg")
+            .body("<svg></svg>")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+
+        let get = || {
+            client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+        };
+
+        // Puts it in the hot cache
+        assert_eq!(get().await.into_string().await.unwrap(), "<svg></svg>");
+
+        // Served from memory, its slot is still held until the body is read
+        let response = get().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(get().await.status(), Status::TooManyRequests);
+        assert_eq!(response.into_string().await.unwrap(), "<svg></svg>");
+
+        assert_eq!(get().await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_download_head_conditional() {
+        use rocket::http::Header;
+
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/notes.txt")
+            .body("Some notes")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response
+            .into_string()
+            .await
+            .unwrap()
+            .replace("Success: ", "");
+
+        let response = client
+            .head(format!("/{uuid}/notes.txt"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // Turned into the 'Content-Length' by hyper, which the local client skips
+        assert_eq!(response.body().preset_size(), Some(10));
+
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_one("Content-Disposition"),
+            Some("attachment; filename=\"notes.txt\"")
+        );
+        let etag = headers.get_one("ETag").unwrap().to_string();
+        let last_modified = headers.get_one("Last-Modified").unwrap().to_string();
+
+        for (name, value) in [
+            ("If-None-Match", etag.clone()),
+            ("If-Modified-Since", last_modified),
+        ] {
+            let response = client
+                .get(format!("/{uuid}"))
+                .header(Header::new(name, value))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::NotModified);
+            assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+        }
+
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("If-None-Match", "\"something-else\""))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "Some notes");
+    }
+
+    #[rocket::async_test]
+    async fn test_download_unicode_filename() {
+        use rocket::http::Header;
+
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/Rapport%20final%20%C3%A9t%C3%A9.pdf")
+            .body("Not really a pdf")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response
+            .into_string()
+            .await
+            .unwrap()
+            .replace("Success: ", "");
+
+        let response = client
+            .get(format!("/{uuid}/Rapport%20final%20%C3%A9t%C3%A9.pdf"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Content-Disposition").unwrap(),
+            "attachment; filename=\"Rapport final ete.pdf\"; filename*=UTF-8''Rapport%20final%20%C3%A9t%C3%A9.pdf"
+        );
+
+        let response = client
+            .get(format!("/{uuid}/Rapport%20final%20ete.pdf"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_download_names() {
+        use rocket::http::Header;
+
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/notes.txt")
+            .body("Some notes")
+            .header(Header::new("X-Alias", "meeting-notes"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let short_id = response
+            .headers()
+            .get_one("X-Short-Id")
+            .unwrap()
+            .to_string();
+        assert_eq!(short_id.len(), crate::cache::SHORT_ID_LENGTH);
+        let uuid = response.into_string().await.unwrap();
+
+        for name in [short_id.as_str(), "meeting-notes"] {
+            let response = client
+                .get(format!("/{name}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok, "{name}");
+            assert_eq!(response.into_string().await.unwrap(), "Some notes");
+
+            let response = client
+                .get(format!("/{name}/notes.txt"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok, "{name}");
+        }
+
+        let info = client
+            .get(format!("/info/{short_id}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(info.contains(&uuid));
+
+        // Taken
+        let response = client
+            .put("/other.txt")
+            .body("Other notes")
+            .header(Header::new("X-Alias", "meeting-notes"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client
+            .delete("/meeting-notes")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+
+        // Gone with the entry
+        let response = client
+            .get(format!("/{short_id}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_download_encrypted() {
+        use rocket::http::Header;
+
+        // Each instance has its own key, or none
+        for key in [Some("07".repeat(32)), None] {
+            let figment = match &key {
+                Some(key) => rocket::Config::figment().merge(("encryption.key", key)),
+                None => rocket::Config::figment(),
+            };
+            let client = Client::tracked(crate::build_test_rocket_from(figment).await)
+                .await
+                .expect("valid rocket instance");
+
+            let response = client
+                .put("/secret.txt")
+                .body("Some secret notes")
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            let uuid = response.into_string().await.unwrap();
+
+            let config = client
+                .rocket()
+                .state::<crate::config::StorageConfig>()
+                .unwrap();
+            let encrypted = std::fs::read_dir(&config.cache_dir)
+                .unwrap()
+                .filter_map(|entry| std::fs::read(entry.unwrap().path()).ok())
+                .any(|content| content.starts_with(b"SSE1"));
+            assert_eq!(encrypted, key.is_some());
+
+            let response = client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_string().await.unwrap(), "Some secret notes");
+        }
+    }
 }
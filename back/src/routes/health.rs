@@ -0,0 +1,85 @@
+#[derive(serde::Serialize)]
+struct Health {
+    status: crate::disk::DiskState,
+    read_only: bool,
+    // Bytes
+    available_space: u64,
+    reserved_space: u64,
+    critical_space: u64,
+    entries: usize,
+    hot_cache: crate::hot_cache::HotCacheStats,
+}
+
+// For monitoring, 503 when the server is read-only
+#[rocket::get("/health")]
+pub async fn health(
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    disk_monitor: &rocket::State<std::sync::Arc<crate::disk::DiskMonitor>>,
+    hot_cache: &rocket::State<crate::hot_cache::HotCache>,
+) -> crate::response::Response {
+    use {
+        crate::{disk::DiskState, response::Response},
+        rocket::http::{ContentType, Status},
+    };
+
+    let state = disk_monitor.check();
+
+    let health = Health {
+        status: state,
+        read_only: state == DiskState::ReadOnly,
+        available_space: disk_monitor.available_space(),
+        reserved_space: disk_monitor.reserved_space(),
+        critical_space: disk_monitor.critical_space(),
+        entries: cache.len(),
+        hot_cache: hot_cache.stats(),
+    };
+
+    let json = match rocket::serde::json::serde_json::to_string(&health) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to serialize the health report due to: {e}");
+            return Response::builder()
+                .with_status(Status::InternalServerError)
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+    };
+
+    Response::builder()
+        .with_status(if health.read_only {
+            Status::ServiceUnavailable
+        } else {
+            Status::Ok
+        })
+        .with_content(json)
+        .with_content_type(ContentType::JSON)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::build_test_rocket,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_health() {
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+        let response = client
+            .get("/health")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().await.unwrap();
+        assert!(body.contains("\"status\":\"ok\""));
+        assert!(body.contains("\"read_only\":false"));
+    }
+}
@@ -1,21 +1,25 @@
 #[rocket::get("/info/<uuidw>")]
 pub async fn info(
     uuidw: super::download_route::UuidWrapper,
-    cache: &rocket::State<crate::cache::CacheEntryMap>,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
 ) -> crate::response::Response {
     use crate::response::Response;
     use rocket::http::{ContentType, Status};
 
-    let uuid = *uuidw;
-
-    let Some(entry) = cache.get(&uuid) else {
+    let Some(entry) = uuidw.resolve(ids, cache).and_then(|uuid| cache.get(&uuid)) else {
         return Response::builder()
             .with_status(Status::NotFound)
-            .with_content(format!("Invalid id: {uuid}"))
+            .with_content("Invalid id")
             .with_content_type(ContentType::Text)
             .build();
     };
 
+    let uuid = entry.uuid();
+
+    // For edits, see patch.rs
+    let (etag, _last_modified) = super::validators(&entry);
+
     let json = match rocket::serde::json::serde_json::to_string(&*entry) {
         Ok(s) => s,
         Err(e) => {
@@ -28,6 +32,7 @@ pub async fn info(
     };
 
     Response::builder()
+        .with_header("ETag", &etag)
         .with_content(json)
         .with_content_type(ContentType::JSON)
         .build()
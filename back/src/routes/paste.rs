@@ -0,0 +1,269 @@
+// Pastes, text sent from the front end's textarea (or with curl) to be read in the browser
+//
+// They're stored like any other upload, as 'paste.txt' with the language hint in their details (see
+// cache::UploadDetails). The download routes serve them raw, as inline text, and /p/<uuid> is the front end's viewer
+// (see front/src/scene/paste.rs)
+
+// Used when Rocket.toml has no limits.paste, pastes are read whole to make sure they're text
+const DEFAULT_LIMIT: rocket::data::ByteUnit = rocket::data::ByteUnit::Mebibyte(1);
+
+// Hints are language names or extensions ('rust', 'c++', 'py', ..)
+const MAX_LANGUAGE_LENGTH: usize = 32;
+
+#[rocket::post("/paste?<lang>", data = "<raw_data>")]
+#[allow(clippy::too_many_arguments)] // Request guards
+pub async fn api_paste(
+    lang: Option<&str>,
+    _size: super::UploadSize,
+    raw_data: rocket::data::Data<'_>,
+    limits: &rocket::data::Limits,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    duplicate_map: &rocket::State<
+        std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::DuplicateMap>>,
+    >,
+    chunk_map: &rocket::State<std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::ChunkMap>>>,
+    config: &rocket::State<crate::config::StorageConfig>,
+    pinned: super::Pinned,
+    password: super::Password,
+    _slot: crate::rate_limit::UploadSlot,
+    disk_monitor: &rocket::State<std::sync::Arc<crate::disk::DiskMonitor>>,
+    eviction: &rocket::State<crate::config::EvictionConfig>,
+    hot_cache: &rocket::State<crate::hot_cache::HotCache>,
+    user_agent: super::UserAgent,
+    ids: &rocket::State<crate::cache::IdMap>,
+    alias: super::Alias,
+    addr: rocket_client_addr::ClientAddr,
+) -> crate::response::Response {
+    use {
+        crate::response::Response,
+        rocket::http::{ContentType, Status},
+        std::time::Instant,
+        uuid::Uuid,
+    };
+
+    let start_timer = Instant::now();
+
+    let uuid = loop {
+        let uuid = Uuid::new_v4();
+        if cache.get(&uuid).is_none() {
+            break uuid;
+        }
+    };
+
+    debug!("Received new paste request from {addr}\nUsing id: {uuid}\nLanguage: {lang:?}");
+
+    let language = match lang.map(str::trim).filter(|lang| !lang.is_empty()) {
+        Some(lang) if is_valid_language(lang) => Some(lang.to_lowercase()),
+        Some(lang) => {
+            warn!("[{uuid}] Invalid language hint: {lang}");
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content("Invalid language, use its name or extension (rust, py, c++, ..)")
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+        None => None,
+    };
+
+    let limit = limits.get("paste").unwrap_or(DEFAULT_LIMIT);
+
+    let text = match raw_data.open(limit).into_string().await {
+        Ok(text) if !text.is_complete() => {
+            warn!("[{uuid}] Refusing a paste over the {limit} limit");
+            return Response::builder()
+                .with_status(Status::PayloadTooLarge)
+                .with_content(format!("Data too large, {limit} max for pastes"))
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+        Ok(text) => text.into_inner(),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            debug!("[{uuid}] The paste is not UTF-8");
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content(
+                    "Pastes have to be UTF-8 text, upload other files with PUT /<filename>",
+                )
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+        Err(e) => {
+            error!("[{uuid}] Failed to read the paste due to: {e}");
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content("Could not read the paste")
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+    };
+
+    if text.is_empty() {
+        return Response::builder()
+            .with_status(Status::BadRequest)
+            .with_content("Empty paste")
+            .with_content_type(ContentType::Text)
+            .build();
+    }
+
+    let password_hash = match super::hash_password(uuid, password).await {
+        Ok(password_hash) => password_hash,
+        Err(resp) => return resp,
+    };
+
+    let (short_id, alias) = match super::claim_names(uuid, alias, ids, cache) {
+        Ok(names) => names,
+        Err(resp) => return resp,
+    };
+
+    let details = crate::cache::UploadDetails {
+        upload_time: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .ok(),
+        uploader_address: Some(addr.to_string()),
+        user_agent: user_agent.0,
+        content_type: Some(ContentType::Text.to_string()),
+        original_name: None,
+        paste: true,
+        language,
+        short_id: Some(short_id.clone()),
+        alias,
+    };
+
+    let upload_info = crate::cache::UploadInfo::new(
+        String::from("paste"),
+        String::from("txt"),
+        false,
+        password_hash,
+        pinned.0,
+        details,
+    );
+
+    if let Err(resp) = super::store(
+        uuid,
+        upload_info,
+        std::io::Cursor::new(text.into_bytes()),
+        cache,
+        duplicate_map,
+        chunk_map,
+        config,
+        disk_monitor,
+        eviction,
+        hot_cache,
+        ids,
+    )
+    .await
+    {
+        return resp;
+    }
+
+    info!(
+        "[{uuid}] Responded in {}",
+        time::format(start_timer.elapsed(), 2)
+    );
+
+    Response::builder()
+        .with_status(Status::Created)
+        .with_header("X-Short-Id", &short_id)
+        .with_content(uuid.hyphenated().to_string())
+        .with_content_type(ContentType::Text)
+        .build()
+}
+
+// Also used by edits, see patch.rs
+pub fn is_valid_language(lang: &str) -> bool {
+    lang.len() <= MAX_LANGUAGE_LENGTH
+        && lang
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '#' | '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::build_test_rocket,
+        rocket::{
+            http::{ContentType, Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_paste() {
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let text = "fn main() {\n    println!(\"héllo\");\n}\n";
+
+        let response = client
+            .post("/paste?lang=Rust")
+            .body(text)
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+
+        // Raw
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::Text));
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("inline; filename=\"paste.txt\"")
+        );
+        assert_eq!(response.into_string().await.unwrap(), text);
+
+        let info = client
+            .get(format!("/info/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(info.contains("\"paste\":true"));
+        assert!(info.contains("\"language\":\"rust\""));
+    }
+
+    #[rocket::async_test]
+    async fn test_paste_refused() {
+        let client = Client::tracked(
+            crate::build_test_rocket_from(rocket::Config::figment().merge(("limits.paste", 16)))
+                .await,
+        )
+        .await
+        .expect("valid rocket instance");
+
+        for (url, body, status) in [
+            ("/paste", vec![0xff, 0xfe], Status::BadRequest),
+            ("/paste", Vec::new(), Status::BadRequest),
+            (
+                "/paste?lang=no%20spaces",
+                b"text".to_vec(),
+                Status::BadRequest,
+            ),
+            (
+                "/paste",
+                b"way over sixteen bytes".to_vec(),
+                Status::PayloadTooLarge,
+            ),
+        ] {
+            let response = client
+                .post(url)
+                .body(body)
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+
+            assert_eq!(response.status(), status, "{url}");
+        }
+    }
+}
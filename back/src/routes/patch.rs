@@ -0,0 +1,413 @@
+// Edits of an entry once uploaded (name, extension and details), so a typo doesn't mean uploading it again
+//
+// Authorised like deletions, with the uuid, its short id or an alias. The body is a JSON object with the fields
+// to change (see Edit), the meta is rewritten first (through a temp file, see cache::fs::write_meta), off the runtime
+// and without the map's guard, which is only held to update the entry in memory after
+//
+// Concurrent edits are caught with 'If-Match': when sent, it has to be the entry's current ETag (the one of the
+// download routes and /info), which changes with every edit
+
+// Used when Rocket.toml has no limits.edit
+const DEFAULT_LIMIT: rocket::data::ByteUnit = rocket::data::ByteUnit::Kibibyte(16);
+
+// Every field is optional, unknown ones are refused so typos don't go unnoticed
+#[derive(Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Edit {
+    // Sanitised like uploaded names, see filename.rs
+    name: Option<String>,
+    extension: Option<String>,
+    // Empty to remove them
+    content_type: Option<String>,
+    language: Option<String>,
+    pinned: Option<bool>,
+}
+
+// 'If-Match' of an edit, the ETags the client expects the entry to have
+pub struct IfMatch(Option<String>);
+
+impl IfMatch {
+    // Without it, edits apply to whatever the entry is now
+    fn matches(&self, etag: &str) -> bool {
+        self.0.as_ref().is_none_or(|if_match| {
+            if_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == etag)
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for IfMatch {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(IfMatch(
+            req.headers().get_one("If-Match").map(str::to_string),
+        ))
+    }
+}
+
+#[rocket::patch("/<uuidw>", data = "<raw_data>")]
+#[allow(clippy::too_many_arguments)] // Request guards
+pub async fn api_patch(
+    uuidw: Option<super::UuidWrapper>,
+    raw_data: rocket::data::Data<'_>,
+    limits: &rocket::data::Limits,
+    if_match: IfMatch,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
+    ids: &rocket::State<crate::cache::IdMap>,
+    config: &rocket::State<crate::config::StorageConfig>,
+    disk_monitor: &rocket::State<std::sync::Arc<crate::disk::DiskMonitor>>,
+
+    // See route::api_download's comment
+    addr: rocket_client_addr::ClientAddr,
+    method: rocket::http::Method,
+    uri: &rocket::http::uri::Origin<'_>,
+    c_type: Option<&rocket::http::ContentType>,
+) -> crate::response::Response {
+    use {
+        crate::response::Response,
+        rocket::http::{ContentType, Status},
+    };
+
+    // wrong route
+    let Some(uuid) = uuidw.and_then(|uuidw| uuidw.resolve(ids, cache)) else {
+        let addr_string = addr
+            .get_ipv4_string()
+            .unwrap_or_else(|| addr.get_ipv6_string());
+        return crate::catchers::inner_404(addr_string, method, uri, c_type).await;
+    };
+
+    info!("[{addr}] PATCH request of {uuid}");
+
+    if disk_monitor.state() == crate::disk::DiskState::ReadOnly {
+        warn!("[{addr}] Refusing to edit {uuid}, the server is read-only");
+        return Response::builder()
+            .with_status(Status::ServiceUnavailable)
+            .with_content_type(ContentType::Text)
+            .with_content("The server is read-only, try again later")
+            .build();
+    }
+
+    let limit = limits.get("edit").unwrap_or(DEFAULT_LIMIT);
+
+    let body = match raw_data.open(limit).into_string().await {
+        Ok(body) if !body.is_complete() => {
+            return Response::builder()
+                .with_status(Status::PayloadTooLarge)
+                .with_content(format!("Edit too large, {limit} max"))
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+        Ok(body) => body.into_inner(),
+        Err(e) => {
+            debug!("[{uuid}] Failed to read the edit due to: {e}");
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content("Could not read the edit")
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+    };
+
+    let edit = match rocket::serde::json::serde_json::from_str::<Edit>(&body) {
+        Ok(edit) if edit == Edit::default() => {
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content("Nothing to edit")
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+        Ok(edit) => edit,
+        Err(e) => {
+            debug!("[{uuid}] Invalid edit: {e}");
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content(format!("Invalid edit: {e}"))
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+    };
+
+    let not_found = || {
+        Response::builder()
+            .with_status(Status::NotFound)
+            .with_content("The given id doesn't correspond to any cache entry")
+            .with_content_type(ContentType::Text)
+            .build()
+    };
+
+    let failed = || {
+        Response::builder()
+            .with_status(Status::InternalServerError)
+            .with_content(format!("Failed to edit {uuid}"))
+            .with_content_type(ContentType::Text)
+            .build()
+    };
+
+    let Some(handle) = cache.get(&uuid).map(|entry| entry.handle()) else {
+        return not_found();
+    };
+
+    // Waits for the recompression job and other edits, off the runtime
+    let Ok((handle, meta_guard)) = rocket::tokio::task::spawn_blocking(move || {
+        let guard = handle.lock_meta();
+        (handle, guard)
+    })
+    .await
+    else {
+        error!("[{uuid}] Locking task failed");
+        return failed();
+    };
+
+    // Deleted in between
+    let Some((etag, upload_info)) = cache
+        .get(&uuid)
+        .map(|entry| (super::validators(&entry).0, entry.upload_info().clone()))
+    else {
+        return not_found();
+    };
+
+    if !if_match.matches(&etag) {
+        debug!("[{uuid}] Refusing an edit of an older version");
+        return Response::builder()
+            .with_status(Status::PreconditionFailed)
+            .with_header("ETag", &etag)
+            .with_content("The file changed since, get its current ETag and try again")
+            .with_content_type(ContentType::Text)
+            .build();
+    }
+
+    let upload_info = match apply(uuid, edit, &upload_info) {
+        Ok(upload_info) => upload_info,
+        Err(e) => {
+            return Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content(e)
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+    };
+
+    // The meta is written without the map's guard, it's only taken to update the entry after
+    let config = config.inner().clone();
+    let (upload_info, modified, revision) = match rocket::tokio::task::spawn_blocking(move || {
+        handle
+            .edit(&config, &upload_info)
+            .map(|(modified, revision)| (upload_info, modified, revision))
+    })
+    .await
+    {
+        Ok(Ok(edited)) => edited,
+        Ok(Err(e)) => {
+            error!("[{uuid}] Failed to edit due to: {e}");
+            return failed();
+        }
+        Err(e) => {
+            error!("[{uuid}] Editing task failed due to: {e}");
+            return failed();
+        }
+    };
+
+    // Removed since, deletions only wait for the file lock once it's out of the map
+    let Some(mut entry) = cache.get_mut(&uuid) else {
+        return not_found();
+    };
+
+    entry.set_edited(upload_info, modified, revision);
+
+    debug!("[{uuid}] Edited, now '{}'", entry.upload_info().file_name());
+
+    let (etag, last_modified) = super::validators(&entry);
+
+    // Same as /info
+    let json = match rocket::serde::json::serde_json::to_string(&*entry) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[{uuid}] Failed to serialize due to: {e}");
+            return Response::builder()
+                .with_status(Status::InternalServerError)
+                .with_content_type(ContentType::Text)
+                .build();
+        }
+    };
+
+    drop(entry);
+    drop(meta_guard);
+
+    Response::builder()
+        .with_status(Status::Ok)
+        .with_header("ETag", &etag)
+        .with_header("Last-Modified", &last_modified)
+        .with_content(json)
+        .with_content_type(ContentType::JSON)
+        .build()
+}
+
+// The entry's info once edited, or why the edit is refused
+fn apply(
+    uuid: uuid::Uuid,
+    edit: Edit,
+    upload_info: &crate::cache::UploadInfo,
+) -> Result<crate::cache::UploadInfo, String> {
+    use rocket::http::ContentType;
+
+    let mut details = upload_info.details().clone();
+    let mut name = upload_info.name().clone();
+    let mut extension = upload_info.extension().clone();
+
+    if edit.name.is_some() || edit.extension.is_some() {
+        let requested = match (
+            edit.name.unwrap_or(name),
+            edit.extension.unwrap_or(extension),
+        ) {
+            (name, extension) if extension.is_empty() => name,
+            (name, extension) => format!("{name}.{extension}"),
+        };
+
+        let file_name = crate::filename::sanitise(&requested);
+        if file_name != requested {
+            debug!("[{uuid}] File name '{requested}' sanitised to '{file_name}'");
+        }
+
+        name = super::get_file_name(&file_name).unwrap_or_default();
+        extension = super::get_file_extension(&file_name).unwrap_or_default();
+        details.original_name = (file_name != requested).then_some(requested);
+    }
+
+    if let Some(content_type) = edit.content_type {
+        details.content_type = match content_type.trim() {
+            "" => None,
+            content_type => Some(
+                ContentType::parse_flexible(content_type)
+                    .ok_or_else(|| format!("Invalid content type: {content_type}"))?
+                    .to_string(),
+            ),
+        };
+    }
+
+    if let Some(language) = edit.language {
+        details.language = match language.trim() {
+            "" => None,
+            language if super::is_valid_language(language) => Some(language.to_lowercase()),
+            _ => {
+                return Err(String::from(
+                    "Invalid language, use its name or extension (rust, py, c++, ..)",
+                ))
+            }
+        };
+    }
+
+    Ok(crate::cache::UploadInfo::new(
+        name,
+        extension,
+        upload_info.client_encrypted(),
+        upload_info.password_hash().map(str::to_string),
+        edit.pinned.unwrap_or(upload_info.pinned()),
+        details,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::build_test_rocket,
+        rocket::{
+            http::{Header, Status},
+            local::asynchronous::Client,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn test_patch() {
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .put("/notse.txt")
+            .body("Some notes")
+            .header(Header::new("X-Alias", "edited-notes"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+
+        let response = client
+            .head(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+
+        let response = client
+            .patch(format!("/{uuid}"))
+            .body(r#"{"name":"notes","language":"Markdown"}"#)
+            .header(Header::new("If-Match", etag.clone()))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let new_etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_ne!(new_etag, etag);
+        let info = response.into_string().await.unwrap();
+        assert!(info.contains("\"name\":\"notes\""));
+        assert!(info.contains("\"language\":\"markdown\""));
+
+        let response = client
+            .get(format!("/{uuid}/notes.txt"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some(new_etag.as_str()));
+        assert_eq!(response.into_string().await.unwrap(), "Some notes");
+
+        // Edited in between
+        let response = client
+            .patch(format!("/{uuid}"))
+            .body(r#"{"extension":"md"}"#)
+            .header(Header::new("If-Match", etag))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        assert_eq!(response.headers().get_one("ETag"), Some(new_etag.as_str()));
+
+        for body in ["{}", r#"{"filename":"notes.md"}"#, "not json"] {
+            let response = client
+                .patch(format!("/{uuid}"))
+                .body(body)
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::BadRequest, "{body}");
+        }
+
+        // Through its alias, like deletions
+        let response = client
+            .patch("/edited-notes")
+            .body(r#"{"extension":"md"}"#)
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .contains(&format!("\"uuid\":\"{uuid}\"")));
+
+        let response = client
+            .get(format!("/{uuid}/notes.md"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
@@ -1,26 +1,161 @@
-lazy_static! {
-    static ref FILENAME_VALIDATION_REGEX: regex::Regex =
-        regex::Regex::new(r"^[a-zA-Z0-9_.-]{1,100}$").unwrap();
-    // This regex is really simple:
-    //
-    // All basic latin letters are allowed, can be uppercase,
-    // All arabic numbers are allowed
-    // undercore, dot, hyphen are allowed, all other symbols are denied (I don't like spaces in file names)
-    // Between 1 and 100 characters
+// Set by the web front end when it encrypted the file before sending it (see front/src/scene/upload.rs)
+// We can't check it, it's only stored so the download page knows what to do
+pub struct ClientEncrypted(bool);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for ClientEncrypted {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(ClientEncrypted(
+            req.headers().get_one("X-Client-Encrypted") == Some("1"),
+        ))
+    }
+}
+
+// Pinned entries are never evicted, see cache/eviction.rs
+pub struct Pinned(pub bool);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Pinned {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(Pinned(req.headers().get_one("X-Pinned") == Some("1")))
+    }
+}
+
+// Recorded in the meta, see cache::UploadDetails
+pub struct UserAgent(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for UserAgent {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(UserAgent(
+            req.headers().get_one("User-Agent").map(str::to_string),
+        ))
+    }
+}
+
+// Name asked by the uploader, on top of the short id every upload gets (see cache/ids.rs)
+pub struct Alias(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Alias {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(Alias(
+            req.headers()
+                .get_one("X-Alias")
+                .map(str::trim)
+                .filter(|alias| !alias.is_empty())
+                .map(str::to_string),
+        ))
+    }
+}
+
+// Checks the announced size before the body is read, so oversized uploads are refused before anything is compressed
+// Uploads without a Content-Length are still checked while streaming (see cache::stream_to_file)
+// Also refuses every upload while the disk is under its reserve (see disk.rs)
+//
+// Rocket reads the first few bytes of every body before routing, so clients sending 'Expect: 100-continue'
+// already got their '100 Continue' by then, they still stop sending once the 413 comes in
+pub struct UploadSize;
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for UploadSize {
+    type Error = ();
+
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        use {
+            crate::disk::{DiskMonitor, DiskState},
+            rocket::{http::Status, outcome::Outcome},
+        };
+
+        let (Some(config), Some(monitor)) = (
+            req.rocket().state::<crate::config::StorageConfig>(),
+            req.rocket().state::<std::sync::Arc<DiskMonitor>>(),
+        ) else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        let size = req
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|value| value.parse::<u64>().ok());
+
+        if let Some(size) = size {
+            if size > config.file_limit {
+                warn!(
+                    "Refusing an upload of {} (over the {} limit)",
+                    rocket::data::ByteUnit::Byte(size),
+                    config.file_limit
+                );
+                return Outcome::Error((Status::PayloadTooLarge, ()));
+            }
+        }
+
+        if monitor.check() != DiskState::Ok {
+            return Outcome::Error((Status::InsufficientStorage, ()));
+        }
+
+        // Compressed data is (almost always) smaller, so that's on the safe side
+        if let Some(size) = size {
+            if !monitor.can_store(size) {
+                warn!(
+                    "Refusing an upload of {} as only {} are left on disk",
+                    rocket::data::ByteUnit::Byte(size),
+                    rocket::data::ByteUnit::Byte(monitor.available_space())
+                );
+                return Outcome::Error((Status::InsufficientStorage, ()));
+            }
+        }
+
+        Outcome::Success(UploadSize)
+    }
 }
 
 #[rocket::put("/<filename>", data = "<raw_data>")]
+#[allow(clippy::too_many_arguments)] // Request guards
 pub async fn api_upload(
     filename: &str,
+    _size: UploadSize,
     raw_data: rocket::data::Data<'_>,
-    cache: &rocket::State<crate::cache::CacheEntryMap>,
+    cache: &rocket::State<std::sync::Arc<crate::cache::CacheEntryMap>>,
     duplicate_map: &rocket::State<
         std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::DuplicateMap>>,
     >,
+    chunk_map: &rocket::State<std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::ChunkMap>>>,
+    config: &rocket::State<crate::config::StorageConfig>,
+    client_encrypted: ClientEncrypted,
+    pinned: Pinned,
+    password: super::download_route::Password,
+    slot: crate::rate_limit::UploadSlot,
+    timeouts: &rocket::State<crate::config::TimeoutConfig>,
+    disk_monitor: &rocket::State<std::sync::Arc<crate::disk::DiskMonitor>>,
+    eviction: &rocket::State<crate::config::EvictionConfig>,
+    hot_cache: &rocket::State<crate::hot_cache::HotCache>,
+    user_agent: UserAgent,
+    content_type: Option<&rocket::http::ContentType>,
+    ids: &rocket::State<crate::cache::IdMap>,
+    alias: Alias,
     addr: rocket_client_addr::ClientAddr,
 ) -> crate::response::Response {
     use {
-        crate::{cache::CacheEntry, response::Response},
+        crate::response::Response,
         rocket::{
             data::ByteUnit,
             http::{ContentType, Status},
@@ -43,38 +178,276 @@ pub async fn api_upload(
         "NO_USER", filename,
     );
 
-    // Validation of user input
-    if !FILENAME_VALIDATION_REGEX.is_match(filename) {
-        error!("[{uuid}] The given filename doesn't match the validation regex");
-        return Response::builder()
-            .with_status(Status::BadRequest)
-            .with_content("The specified filename should only contain alphanumeric characters, underscores, dots and shouldn't be longer than 100 characters")
-            .with_content_type(ContentType::Text)
-            .build();
+    // Never refused, see filename.rs
+    let original_filename = filename;
+    let filename = crate::filename::sanitise(original_filename);
+    if filename != original_filename {
+        debug!("[{uuid}] File name '{original_filename}' sanitised to '{filename}'");
     }
 
+    let password_hash = match hash_password(uuid, password).await {
+        Ok(password_hash) => password_hash,
+        Err(resp) => return resp,
+    };
+
+    // Before reading anything, so taken aliases are refused right away
+    let (short_id, alias) = match claim_names(uuid, alias, ids, cache) {
+        Ok(names) => names,
+        Err(resp) => return resp,
+    };
+
     // File size check are done in the store data function in cache.rs
-    let data_stream = raw_data.open(ByteUnit::max_value());
+    let data_stream = crate::response::Throttled::new(
+        crate::timeout::Watchdog::new(
+            raw_data.open(ByteUnit::max_value()),
+            uuid,
+            crate::rate_limit::Direction::Upload,
+            timeouts.upload,
+        ),
+        Some(slot.into_inner()),
+    );
+
+    let extension = get_file_extension(&filename).unwrap_or_default();
+
+    let details = crate::cache::UploadDetails {
+        upload_time: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .ok(),
+        uploader_address: Some(addr.to_string()),
+        user_agent: user_agent.0,
+        content_type: content_type
+            .cloned()
+            .or_else(|| ContentType::from_extension(&extension))
+            .map(|content_type| content_type.to_string()),
+        original_name: (filename != original_filename).then(|| original_filename.to_string()),
+        short_id: Some(short_id.clone()),
+        alias,
+        ..Default::default()
+    };
+
+    let upload_info = crate::cache::UploadInfo::new(
+        get_file_name(&filename).unwrap_or_default(),
+        extension,
+        client_encrypted.0,
+        password_hash,
+        pinned.0,
+        details,
+    );
+
+    if let Err(resp) = store(
+        uuid,
+        upload_info,
+        data_stream,
+        cache,
+        duplicate_map,
+        chunk_map,
+        config,
+        disk_monitor,
+        eviction,
+        hot_cache,
+        ids,
+    )
+    .await
+    {
+        return resp;
+    }
+
+    info!(
+        "[{uuid}] Responded in {}",
+        time::format(start_timer.elapsed(), 2)
+    );
+
+    Response::builder()
+        .with_status(Status::Created)
+        // The body stays the bare uuid, scripts read it
+        .with_header("X-File-Name", &crate::filename::encode(&filename))
+        .with_header("X-Short-Id", &short_id)
+        .with_content(uuid.hyphenated().to_string())
+        .with_content_type(ContentType::Text)
+        .build()
+}
+
+// The hash stored for the given password, if there is one
+pub async fn hash_password(
+    uuid: uuid::Uuid,
+    password: super::download_route::Password,
+) -> Result<Option<String>, crate::response::Response> {
+    use {
+        crate::response::Response,
+        rocket::http::{ContentType, Status},
+    };
+
+    let Some(password) = password.into_inner() else {
+        return Ok(None);
+    };
+
+    // Argon2 is slow on purpose, don't block the runtime with it
+    match rocket::tokio::task::spawn_blocking(move || crate::cache::hash_password(&password)).await
+    {
+        Ok(Ok(hash)) => Ok(Some(hash)),
+        Ok(Err(e)) => {
+            error!("[{uuid}] {e}");
+            Err(Response::builder()
+                .with_status(Status::InternalServerError)
+                .with_content("An error occured while hashing the password")
+                .with_content_type(ContentType::Text)
+                .build())
+        }
+        Err(e) => {
+            error!("[{uuid}] Password hashing task failed due to: {e}");
+            Err(Response::builder()
+                .with_status(Status::InternalServerError)
+                .with_content("An error occured while hashing the password")
+                .with_content_type(ContentType::Text)
+                .build())
+        }
+    }
+}
+
+// Claims the alias (if one was asked for) and a new short id for the entry, see cache/ids.rs
+#[allow(clippy::result_large_err)] // The response is sent right away
+pub fn claim_names(
+    uuid: uuid::Uuid,
+    alias: Alias,
+    ids: &crate::cache::IdMap,
+    cache: &crate::cache::CacheEntryMap,
+) -> Result<(String, Option<String>), crate::response::Response> {
+    use {
+        crate::response::Response,
+        rocket::http::{ContentType, Status},
+    };
+
+    if let Some(alias) = &alias.0 {
+        if !crate::cache::is_valid_alias(alias) {
+            debug!("[{uuid}] Invalid alias: {alias}");
+            return Err(Response::builder()
+                .with_status(Status::BadRequest)
+                .with_content(format!(
+                    "Invalid alias, use 3 to 64 letters, digits, '-' or '_' (but not {} of them, short ids look like that)",
+                    crate::cache::SHORT_ID_LENGTH
+                ))
+                .with_content_type(ContentType::Text)
+                .build());
+        }
+
+        if !ids.claim(alias, uuid, cache) {
+            debug!("[{uuid}] The alias '{alias}' is already taken");
+            return Err(Response::builder()
+                .with_status(Status::Conflict)
+                .with_content("This alias is already taken")
+                .with_content_type(ContentType::Text)
+                .build());
+        }
+    }
+
+    Ok((ids.new_short_id(uuid, cache), alias.0))
+}
+
+// Stores a new entry and makes room for it, shared by uploads and pastes (see paste.rs)
+// On errors, the entry and its names are gone and the response to send is returned
+#[allow(clippy::too_many_arguments)]
+pub async fn store(
+    uuid: uuid::Uuid,
+    upload_info: crate::cache::UploadInfo,
+    data_stream: impl rocket::tokio::io::AsyncRead + Unpin,
+    cache: &crate::cache::CacheEntryMap,
+    duplicate_map: &std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::DuplicateMap>>,
+    chunk_map: &std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::ChunkMap>>,
+    config: &crate::config::StorageConfig,
+    disk_monitor: &crate::disk::DiskMonitor,
+    eviction: &crate::config::EvictionConfig,
+    hot_cache: &crate::hot_cache::HotCache,
+    ids: &crate::cache::IdMap,
+) -> Result<(), crate::response::Response> {
+    let details = upload_info.details().clone();
+
+    let stored = store_entry(
+        uuid,
+        upload_info,
+        data_stream,
+        cache,
+        duplicate_map,
+        chunk_map,
+        config,
+        disk_monitor,
+        eviction,
+        hot_cache,
+    )
+    .await;
+
+    match stored {
+        Ok(()) => ids.stored(uuid),
+        Err(_) => ids.remove(uuid, &details),
+    }
+
+    stored
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn store_entry(
+    uuid: uuid::Uuid,
+    upload_info: crate::cache::UploadInfo,
+    data_stream: impl rocket::tokio::io::AsyncRead + Unpin,
+    cache: &crate::cache::CacheEntryMap,
+    duplicate_map: &std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::DuplicateMap>>,
+    chunk_map: &std::sync::Arc<rocket::tokio::sync::Mutex<crate::cache::ChunkMap>>,
+    config: &crate::config::StorageConfig,
+    disk_monitor: &crate::disk::DiskMonitor,
+    eviction: &crate::config::EvictionConfig,
+    hot_cache: &crate::hot_cache::HotCache,
+) -> Result<(), crate::response::Response> {
+    use {
+        crate::{cache::CacheEntry, response::Response},
+        rocket::http::{ContentType, Status},
+    };
 
     let entry = match CacheEntry::store_new(
+        config,
         uuid,
-        crate::cache::UploadInfo::new(
-            get_file_name(filename).unwrap_or_default(),
-            get_file_extension(filename).unwrap_or_default(),
-        ),
+        upload_info,
         data_stream,
+        config.storage_mode,
         std::sync::Arc::clone(duplicate_map),
+        std::sync::Arc::clone(chunk_map),
     )
     .await
     {
         Ok(entry) => entry,
+        Err(e @ crate::error::CacheError::Timeout { .. }) => {
+            error!("[{uuid}] {e}");
+            return Err(Response::builder()
+                .with_status(Status::RequestTimeout)
+                .with_content("The upload was too slow")
+                .with_content_type(ContentType::Text)
+                .build());
+        }
+        // Without a Content-Length, UploadSize couldn't refuse it before
+        Err(e @ crate::error::CacheError::FileSizeExceeded { limit }) => {
+            warn!("[{uuid}] {e}");
+            return Err(Response::builder()
+                .with_status(Status::PayloadTooLarge)
+                .with_content(format!("Data too large, {limit} max"))
+                .with_content_type(ContentType::Text)
+                .build());
+        }
+        // Filled up while streaming (no Content-Length, or other uploads at the same time)
+        Err(e) if e.is_disk_full() => {
+            error!("[{uuid}] {e}");
+            disk_monitor.check();
+            return Err(Response::builder()
+                .with_status(Status::InsufficientStorage)
+                .with_content("Not enough space left to store this file")
+                .with_content_type(ContentType::Text)
+                .build());
+        }
         Err(e) => {
             error!("[{uuid}] An error occured while storing the given data: {e}");
-            return Response::builder()
+            return Err(Response::builder()
                 .with_status(Status::InternalServerError)
                 .with_content("An error occured while caching the data")
                 .with_content_type(ContentType::Text)
-                .build();
+                .build());
         }
     };
 
@@ -86,26 +459,51 @@ pub async fn api_upload(
         cache.insert(old.uuid(), old); // undo
         error!("Inserting a new cache returned an old one at uuid: {uuid}");
 
-        return Response::builder()
+        return Err(Response::builder()
             .with_status(Status::InternalServerError)
             .with_content("An error occured while caching the data")
             .with_content_type(ContentType::Text)
-            .build();
+            .build());
     }
 
-    info!(
-        "[{uuid}] Responded in {}",
-        time::format(start_timer.elapsed(), 2)
-    );
+    if let Err(e) = crate::cache::make_room(
+        config,
+        eviction,
+        cache,
+        uuid,
+        std::sync::Arc::clone(duplicate_map),
+        std::sync::Arc::clone(chunk_map),
+        hot_cache,
+    )
+    .await
+    {
+        error!("[{uuid}] {e}");
 
-    Response::builder()
-        .with_status(Status::Created)
-        .with_content(uuid.hyphenated().to_string())
-        .with_content_type(ContentType::Text)
-        .build()
+        // Everything else is pinned (or it's too big on its own), drop the new one instead
+        if let Some((_uuid, entry)) = cache.remove(&uuid) {
+            if let Err(e) = entry
+                .delete(
+                    config,
+                    std::sync::Arc::clone(duplicate_map),
+                    std::sync::Arc::clone(chunk_map),
+                )
+                .await
+            {
+                error!("[{uuid}] Failed to remove the upload due to: {e}");
+            }
+        }
+
+        return Err(Response::builder()
+            .with_status(Status::InsufficientStorage)
+            .with_content("Not enough space left to store this file")
+            .with_content_type(ContentType::Text)
+            .build());
+    }
+
+    Ok(())
 }
 
-fn get_file_name(name: &str) -> Option<String> {
+pub fn get_file_name(name: &str) -> Option<String> {
     if !name.contains(".") {
         return Some(name.to_string());
     }
@@ -115,7 +513,7 @@ fn get_file_name(name: &str) -> Option<String> {
     Some(String::from(&name[0..dot_index]))
 }
 
-fn get_file_extension(name: &str) -> Option<String> {
+pub fn get_file_extension(name: &str) -> Option<String> {
     if !name.contains(".") {
         return None;
     }
@@ -128,7 +526,7 @@ fn get_file_extension(name: &str) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use {
-        crate::build_rocket,
+        crate::build_test_rocket,
         rocket::{
             http::{Header, Status},
             local::asynchronous::Client,
@@ -138,7 +536,7 @@ mod tests {
 
     #[rocket::async_test]
     async fn test_upload() {
-        let client = Client::tracked(build_rocket().await)
+        let client = Client::tracked(build_test_rocket().await)
             .await
             .expect("valid rocket instance");
         let response = client
@@ -159,4 +557,246 @@ mod tests {
             .replace("Success: ", "");
         let _uuid = uuid::Uuid::from_str(&suuid).unwrap();
     }
+
+    #[rocket::async_test]
+    async fn test_upload_sanitised_filename() {
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+        let response = client
+            .put("/my%20%20report%0A..%5Cfinal__v2.txt")
+            .body("This is normal file content")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(
+            response.headers().get_one("X-File-Name"),
+            Some("my%20report%20._final_v2.txt")
+        );
+        let uuid = response.into_string().await.unwrap();
+
+        let info = client
+            .get(format!("/info/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(info.contains("\"name\":\"my report ._final_v2\""));
+        assert!(info.contains("\"original_name\":\"my  report\\n..\\\\final__v2.txt\""));
+    }
+
+    #[rocket::async_test]
+    async fn test_upload_client_encrypted() {
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+        let response = client
+            .put("/test.file")
+            .body("Not really encrypted")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .header(Header::new("x-client-encrypted", "1"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Created);
+        let suuid = response.into_string().await.unwrap();
+
+        let response = client
+            .get(format!("/info/{suuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .contains("\"client_encrypted\":true"));
+    }
+
+    #[rocket::async_test]
+    async fn test_upload_too_large() {
+        let client = Client::tracked(build_test_rocket().await)
+            .await
+            .expect("valid rocket instance");
+        let response = client
+            .put("/test.file")
+            .body("Small body, but the announced size is not")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .header(Header::new("Content-Length", u64::MAX.to_string()))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .starts_with("Data too large"));
+
+        // Only noticed while it's stored
+        let figment = rocket::Config::figment().merge(("storage.file_limit", 16));
+        let client = Client::tracked(crate::build_test_rocket_from(figment).await)
+            .await
+            .expect("valid rocket instance");
+        let response = client
+            .put("/test.file")
+            .body("A body over the 16 bytes limit")
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            "Data too large, 16B max"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_upload_eviction() {
+        let client = Client::tracked(
+            crate::build_test_rocket_from(
+                rocket::Config::figment().merge(("eviction.capacity", 2500)),
+            )
+            .await,
+        )
+        .await
+        .expect("valid rocket instance");
+
+        // Random hex, a bit over 1 KB each once compressed
+        let mut uuids = Vec::new();
+        for pinned in ["1", "0", "0"] {
+            let response = client
+                .put("/test.file")
+                .body(
+                    (0..64)
+                        .map(|_| uuid::Uuid::new_v4().simple().to_string())
+                        .collect::<String>(),
+                )
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .header(Header::new("x-pinned", pinned))
+                .dispatch()
+                .await;
+
+            assert_eq!(response.status(), Status::Created);
+            uuids.push(response.into_string().await.unwrap());
+
+            // Access times are in milliseconds
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // The pinned one stays, the oldest of the others goes
+        for (uuid, status) in uuids.iter().zip([Status::Ok, Status::NotFound, Status::Ok]) {
+            let response = client
+                .get(format!("/info/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_upload_duplicate_dictionary_encrypted() {
+        let client = Client::tracked(
+            crate::build_test_rocket_from(
+                rocket::Config::figment()
+                    .merge(("encryption.key", "07".repeat(32)))
+                    .merge(("rate_limit.upload.requests_per_minute", 0)),
+            )
+            .await,
+        )
+        .await
+        .expect("valid rocket instance");
+
+        // Samples for the dictionary
+        for i in 0..100 {
+            let response = client
+                .put(format!("/sample_{i}.json"))
+                .body(format!(
+                    r#"{{"id":{i},"name":"sample {i}","tags":["storage","server","{}"],"size":{}}}"#,
+                    i * 7,
+                    i * 1024
+                ))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+        }
+
+        let config = client
+            .rocket()
+            .state::<crate::config::StorageConfig>()
+            .unwrap();
+        crate::cache::train_dictionary(config, "json", Some(4096)).unwrap();
+
+        // Compressed with the dictionary, then encrypted, the duplicate has to find the dictionary in there
+        let content = r#"{"id":1000,"name":"with a dictionary","tags":["storage","server"]}"#;
+        let mut uuids = Vec::new();
+        for name in ["original.json", "duplicate.json"] {
+            let response = client
+                .put(format!("/{name}"))
+                .body(content)
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Created);
+            uuids.push(response.into_string().await.unwrap());
+        }
+
+        for uuid in uuids {
+            let response = client
+                .get(format!("/{uuid}"))
+                .header(Header::new("x-forwarded-for", "0.0.0.0"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_string().await.unwrap(), content);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_upload_chunked() {
+        let figment = rocket::Config::figment()
+            .merge(("storage.storage_mode", "chunked"))
+            .merge(("hot_cache.capacity", 0));
+        let client = Client::tracked(crate::build_test_rocket_from(figment).await)
+            .await
+            .expect("valid rocket instance");
+
+        // A few chunks worth of random hex
+        let content = (0..20_000)
+            .map(|_| uuid::Uuid::new_v4().simple().to_string())
+            .collect::<String>();
+
+        let response = client
+            .put("/test.file")
+            .body(&content)
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let uuid = response.into_string().await.unwrap();
+
+        let config = client
+            .rocket()
+            .state::<crate::config::StorageConfig>()
+            .unwrap();
+        assert!(config.cache_dir.join(format!("{uuid}.manifest")).exists());
+
+        let response = client
+            .get(format!("/{uuid}"))
+            .header(Header::new("x-forwarded-for", "0.0.0.0"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), content);
+    }
 }
@@ -0,0 +1,179 @@
+// Idle and minimum throughput timeouts of uploads and downloads, see config::TimeoutConfig
+//
+// A stream fails with a TimedOut io error when nothing came through for too long, or when (past the grace period)
+// its average throughput is under the minimum. Uploads are then cleaned up like any other failed upload (see CacheEntry::store_new)
+//
+// Downloads are only checked when the client reads, one that stops reading altogether is left to the OS's socket timeouts
+// Rocket also reads the first bytes of the body before routing, that part isn't covered either
+
+// Process wide, only reported in the logs
+static UPLOAD_TIMEOUTS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static DOWNLOAD_TIMEOUTS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+pub struct Watchdog<R> {
+    inner: R,
+    // For the logs
+    uuid: uuid::Uuid,
+    direction: crate::rate_limit::Direction,
+    timeouts: crate::config::StreamTimeouts,
+    start: std::time::Instant,
+    last_read: std::time::Instant,
+    total_read: u64,
+    deadline: std::pin::Pin<Box<tokio::time::Sleep>>,
+}
+
+impl<R> Watchdog<R> {
+    pub fn new(
+        inner: R,
+        uuid: uuid::Uuid,
+        direction: crate::rate_limit::Direction,
+        timeouts: crate::config::StreamTimeouts,
+    ) -> Self {
+        let now = std::time::Instant::now();
+
+        Self {
+            inner,
+            uuid,
+            direction,
+            timeouts,
+            start: now,
+            last_read: now,
+            total_read: 0,
+            // Reset before every wait
+            deadline: Box::pin(tokio::time::sleep(std::time::Duration::ZERO)),
+        }
+    }
+
+    // When the stream times out if nothing more comes through
+    fn next_deadline(&self) -> std::time::Instant {
+        use std::time::Duration;
+
+        // Far enough to never be reached
+        let never = self.start + Duration::from_secs(60 * 60 * 24 * 365);
+
+        let idle = match self.timeouts.idle {
+            0 => never,
+            idle => self.last_read + Duration::from_secs(idle),
+        };
+
+        let slow = match self.timeouts.min_bytes_per_second.as_u64() {
+            0 => never,
+            min => {
+                // Until then, the average stays over the minimum
+                let covered = Duration::from_secs_f64(self.total_read as f64 / min as f64);
+                self.start + covered.max(Duration::from_secs(self.timeouts.grace_period))
+            }
+        };
+
+        idle.min(slow)
+    }
+
+    fn time_out(&self) -> std::io::Error {
+        use {
+            crate::rate_limit::Direction,
+            std::{io::ErrorKind, sync::atomic::Ordering},
+        };
+
+        let why = if self.timeouts.idle != 0
+            && self.last_read.elapsed().as_secs() >= self.timeouts.idle
+        {
+            format!("nothing went through for {}s", self.timeouts.idle)
+        } else {
+            format!(
+                "slower than {}/s ({} in {})",
+                self.timeouts.min_bytes_per_second,
+                rocket::data::ByteUnit::Byte(self.total_read),
+                time::format(self.start.elapsed(), 1)
+            )
+        };
+
+        let count = match self.direction {
+            Direction::Upload => &UPLOAD_TIMEOUTS,
+            Direction::Download => &DOWNLOAD_TIMEOUTS,
+        }
+        .fetch_add(1, Ordering::Relaxed)
+            + 1;
+
+        warn!(
+            "[{}] {:?} timed out, {why} ({count} {:?} timeouts so far)",
+            self.uuid, self.direction, self.direction
+        );
+
+        std::io::Error::new(ErrorKind::TimedOut, why)
+    }
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Watchdog<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::{future::Future as _, pin::Pin, task::Poll, time::Instant};
+
+        let this = &mut *self;
+
+        // Downloads are only polled when the client reads, so it might be late already
+        if Instant::now() >= this.next_deadline() {
+            return Poll::Ready(Err(this.time_out()));
+        }
+
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let read = buf.filled().len() - filled;
+                if read != 0 {
+                    this.total_read += read as u64;
+                    this.last_read = Instant::now();
+                }
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => {
+                let deadline = this.next_deadline();
+                this.deadline.as_mut().reset(deadline.into());
+
+                if this.deadline.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(this.time_out()));
+                }
+
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Watchdog,
+        crate::{config::StreamTimeouts, rate_limit::Direction},
+        rocket::tokio::io::{AsyncReadExt as _, AsyncWriteExt as _},
+    };
+
+    #[rocket::async_test]
+    async fn test_idle_timeout() {
+        let (mut client, server) = rocket::tokio::io::duplex(64);
+
+        let mut watchdog = Watchdog::new(
+            server,
+            uuid::Uuid::new_v4(),
+            Direction::Upload,
+            StreamTimeouts {
+                idle: 1,
+                min_bytes_per_second: rocket::data::ByteUnit::Byte(0),
+                grace_period: 0,
+            },
+        );
+
+        let mut buffer = [0; 64];
+
+        client.write_all(b"hello").await.unwrap();
+        assert_eq!(watchdog.read(&mut buffer).await.unwrap(), 5);
+
+        // Nothing else comes
+        let e = watchdog.read(&mut buffer).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    }
+}
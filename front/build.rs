@@ -43,13 +43,28 @@ fn main() {
 
     let value: toml::Value = contents.parse().expect("Unable to parse TOML");
 
-    let max_upload_size = value
-        .get("default")
-        .and_then(|defaults| defaults.get("limits"))
-        .and_then(|limits| limits.get("file"))
+    // Same as the server, storage.file_limit falls back to limits.file
+    let defaults = value.get("default");
+    let max_upload_size = defaults
+        .and_then(|defaults| defaults.get("storage"))
+        .and_then(|storage| storage.get("file_limit"))
+        .or_else(|| {
+            defaults
+                .and_then(|defaults| defaults.get("limits"))
+                .and_then(|limits| limits.get("file"))
+        })
         .map(|value| value.to_string())
         .and_then(parse_memory_size)
         .expect("file upload size not found in Rocket.toml");
 
+    // Same default as the server, see back/src/routes/paste.rs
+    let max_paste_size = defaults
+        .and_then(|defaults| defaults.get("limits"))
+        .and_then(|limits| limits.get("paste"))
+        .map(|value| value.to_string())
+        .and_then(parse_memory_size)
+        .unwrap_or(1024 * 1024);
+
     println!("cargo:rustc-env=MAX_UPLOAD_SIZE={}", max_upload_size);
+    println!("cargo:rustc-env=MAX_PASTE_SIZE={}", max_paste_size);
 }
@@ -0,0 +1,174 @@
+// End to end encryption of uploads, using the browser's WebCrypto api
+//
+// The file is split in CHUNK_SIZE chunks, each encrypted with AES-GCM on its own, so we never have to
+// hold more than one plain chunk in memory (the browser's Blob takes care of the rest)
+//
+// Layout of an encrypted upload:
+//     MAGIC | 8 bytes nonce prefix | chunk 0 | chunk 1 | ... | last chunk
+//
+// Each chunk's nonce is the prefix followed by the chunk's index (u32, big endian)
+// The last chunk is always smaller than CHUNK_SIZE (it can be empty) and is authenticated as being the last,
+// so a truncated file fails to decrypt instead of silently missing its end
+//
+// The key never leaves the browser, it's put in the share link's fragment (the part after '#'),
+// which browsers don't send to the server
+
+use wasm_bindgen::{JsCast as _, JsValue};
+
+const MAGIC: &[u8; 4] = b"E2E1";
+const PREFIX_SIZE: usize = 8;
+const HEADER_SIZE: usize = MAGIC.len() + PREFIX_SIZE;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+
+// Returns the encrypted file and the hex encoded key
+pub async fn encrypt(file: &web_sys::Blob) -> Result<(web_sys::Blob, String), JsValue> {
+    let crypto = gloo::utils::window().crypto()?;
+    let subtle = crypto.subtle();
+
+    let key: web_sys::CryptoKey = await_promise(subtle.generate_key_with_object(
+        &algorithm(None, None)?,
+        true,
+        &js_sys::Array::of2(&"encrypt".into(), &"decrypt".into()),
+    )?)
+    .await?
+    .dyn_into()?;
+
+    let raw_key = js_sys::Uint8Array::new(&await_promise(subtle.export_key("raw", &key)?).await?);
+
+    let mut prefix = [0u8; PREFIX_SIZE];
+    crypto.get_random_values_with_u8_array(&mut prefix)?;
+
+    let parts = js_sys::Array::new();
+    parts.push(&js_sys::Uint8Array::from(
+        [MAGIC.as_slice(), &prefix[..]].concat().as_slice(),
+    ));
+
+    let size = file.size() as usize;
+    let chunk_count = size / CHUNK_SIZE + 1;
+
+    for index in 0..chunk_count {
+        let start = index * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min(size);
+
+        let plain = await_promise(
+            file.slice_with_f64_and_f64(start as f64, end as f64)?
+                .array_buffer(),
+        )
+        .await?;
+
+        let encrypted = await_promise(subtle.encrypt_with_object_and_buffer_source(
+            &algorithm(
+                Some(&nonce(&prefix, index as u32)),
+                Some(index + 1 == chunk_count),
+            )?,
+            &key,
+            plain.unchecked_ref(),
+        )?)
+        .await?;
+
+        parts.push(&encrypted);
+    }
+
+    Ok((
+        web_sys::Blob::new_with_buffer_source_sequence(&parts)?,
+        to_hex(&raw_key.to_vec()),
+    ))
+}
+
+pub async fn decrypt(data: &[u8], hex_key: &str) -> Result<web_sys::Blob, JsValue> {
+    let subtle = gloo::utils::window().crypto()?.subtle();
+
+    let Some(raw_key) = from_hex(hex_key) else {
+        return Err(JsValue::from("The key in the link is not valid"));
+    };
+
+    if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+        return Err(JsValue::from("This file was not encrypted by this website"));
+    }
+    let prefix = &data[MAGIC.len()..HEADER_SIZE];
+
+    let key: web_sys::CryptoKey = await_promise(subtle.import_key_with_object(
+        "raw",
+        &js_sys::Uint8Array::from(raw_key.as_slice()),
+        &algorithm(None, None)?,
+        false,
+        &js_sys::Array::of1(&"decrypt".into()),
+    )?)
+    .await?
+    .dyn_into()?;
+
+    // The last chunk is never a full one, if it is, the end is missing
+    let body = &data[HEADER_SIZE..];
+    if body.len().is_multiple_of(CHUNK_SIZE + TAG_SIZE) {
+        return Err(JsValue::from("The file is incomplete"));
+    }
+
+    let parts = js_sys::Array::new();
+
+    for (index, chunk) in body.chunks(CHUNK_SIZE + TAG_SIZE).enumerate() {
+        let last = chunk.len() < CHUNK_SIZE + TAG_SIZE;
+
+        let plain = await_promise(subtle.decrypt_with_object_and_buffer_source(
+            &algorithm(Some(&nonce(prefix, index as u32)), Some(last))?,
+            &key,
+            &js_sys::Uint8Array::from(chunk),
+        )?)
+        .await
+        .map_err(|_| JsValue::from("Could not decrypt the file, is the link complete?"))?;
+
+        parts.push(&plain);
+    }
+
+    web_sys::Blob::new_with_buffer_source_sequence(&parts)
+}
+
+// The AES-GCM parameters object, the nonce and additional data are only needed to encrypt / decrypt
+fn algorithm(iv: Option<&[u8]>, last: Option<bool>) -> Result<js_sys::Object, JsValue> {
+    use js_sys::{Object, Reflect, Uint8Array};
+
+    let algorithm = Object::new();
+    Reflect::set(&algorithm, &"name".into(), &"AES-GCM".into())?;
+
+    match iv {
+        Some(iv) => {
+            Reflect::set(&algorithm, &"iv".into(), &Uint8Array::from(iv))?;
+        }
+        None => {
+            Reflect::set(&algorithm, &"length".into(), &JsValue::from(256))?;
+        }
+    }
+
+    if let Some(last) = last {
+        Reflect::set(
+            &algorithm,
+            &"additionalData".into(),
+            &Uint8Array::from([last as u8].as_slice()),
+        )?;
+    }
+
+    Ok(algorithm)
+}
+
+fn nonce(prefix: &[u8], index: u32) -> Vec<u8> {
+    [prefix, &index.to_be_bytes()[..]].concat()
+}
+
+async fn await_promise(promise: js_sys::Promise) -> Result<JsValue, JsValue> {
+    wasm_bindgen_futures::JsFuture::from(promise).await
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,267 @@
+// Small syntax highlighter for the paste viewer (see scene/paste.rs)
+//
+// It only knows comments, strings, numbers and keywords, that's enough to read logs and snippets
+// without shipping a real highlighter. Unknown languages are shown as plain text
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Plain,
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+impl Kind {
+    // Styled in static/css/paste.css
+    pub fn class(&self) -> &'static str {
+        match self {
+            Kind::Plain => "paste_plain",
+            Kind::Keyword => "paste_keyword",
+            Kind::String => "paste_string",
+            Kind::Comment => "paste_comment",
+            Kind::Number => "paste_number",
+        }
+    }
+}
+
+struct Syntax {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    // Strings end at the same quote, backtick ones can span lines
+    quotes: &'static [char],
+}
+
+// Shown in the upload page's language list, the server takes anything that looks like a name
+#[rustfmt::skip]
+pub const LANGUAGES: &[&str] = &[
+    "rust", "c", "cpp", "go", "java", "javascript", "typescript", "python", "shell", "sql", "json",
+    "toml", "yaml",
+];
+
+#[rustfmt::skip]
+fn syntax(language: &str) -> Option<Syntax> {
+    Some(match language {
+        "rust" | "rs" => Syntax {
+            keywords: &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop",
+                "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+                "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"'],
+        },
+        "c" | "h" | "cpp" | "c++" | "cc" | "hpp" => Syntax {
+            keywords: &[
+                "auto", "bool", "break", "case", "char", "class", "const", "continue", "default",
+                "delete", "do", "double", "else", "enum", "extern", "false", "float", "for",
+                "if", "int", "long", "namespace", "new", "nullptr", "private", "protected",
+                "public", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+                "template", "this", "true", "typedef", "union", "unsigned", "using", "virtual",
+                "void", "volatile", "while", "#include", "#define", "#ifdef", "#ifndef",
+                "#endif",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\''],
+        },
+        "go" | "golang" => Syntax {
+            keywords: &[
+                "break", "case", "chan", "const", "continue", "default", "defer", "else",
+                "false", "fallthrough", "for", "func", "go", "goto", "if", "import", "interface",
+                "map", "nil", "package", "range", "return", "select", "struct", "switch", "true",
+                "type", "var",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\'', '`'],
+        },
+        "java" | "kotlin" | "kt" | "cs" | "c#" | "csharp" => Syntax {
+            keywords: &[
+                "abstract", "boolean", "break", "case", "catch", "class", "continue", "default",
+                "do", "else", "enum", "extends", "false", "final", "finally", "for", "fun", "if",
+                "implements", "import", "int", "interface", "namespace", "new", "null",
+                "override", "package", "private", "protected", "public", "return", "static",
+                "super", "switch", "this", "throw", "true", "try", "using", "val", "var", "void",
+                "while",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\''],
+        },
+        "javascript" | "js" | "typescript" | "ts" => Syntax {
+            keywords: &[
+                "async", "await", "break", "case", "catch", "class", "const", "continue",
+                "default", "delete", "else", "export", "extends", "false", "finally", "for",
+                "from", "function", "if", "import", "in", "instanceof", "interface", "let",
+                "new", "null", "of", "return", "switch", "this", "throw", "true", "try", "type",
+                "typeof", "undefined", "var", "while", "yield",
+            ],
+            line_comments: &["//"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['"', '\'', '`'],
+        },
+        "python" | "py" => Syntax {
+            keywords: &[
+                "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+                "del", "elif", "else", "except", "False", "finally", "for", "from", "global",
+                "if", "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass",
+                "raise", "return", "True", "try", "while", "with", "yield",
+            ],
+            line_comments: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+        },
+        "shell" | "sh" | "bash" | "zsh" => Syntax {
+            keywords: &[
+                "case", "do", "done", "echo", "elif", "else", "esac", "exit", "export", "fi",
+                "for", "function", "if", "in", "local", "return", "then", "while",
+            ],
+            line_comments: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+        },
+        "sql" => Syntax {
+            keywords: &[
+                "and", "as", "by", "create", "delete", "drop", "from", "group", "insert", "into",
+                "join", "left", "limit", "not", "null", "on", "or", "order", "select", "set",
+                "table", "update", "values", "where", "AND", "AS", "BY", "CREATE", "DELETE",
+                "DROP", "FROM", "GROUP", "INSERT", "INTO", "JOIN", "LEFT", "LIMIT", "NOT",
+                "NULL", "ON", "OR", "ORDER", "SELECT", "SET", "TABLE", "UPDATE", "VALUES",
+                "WHERE",
+            ],
+            line_comments: &["--"],
+            block_comment: Some(("/*", "*/")),
+            quotes: &['\''],
+        },
+        "json" => Syntax {
+            keywords: &["true", "false", "null"],
+            line_comments: &[],
+            block_comment: None,
+            quotes: &['"'],
+        },
+        "toml" | "yaml" | "yml" | "ini" => Syntax {
+            keywords: &["true", "false", "null"],
+            line_comments: &["#"],
+            block_comment: None,
+            quotes: &['"', '\''],
+        },
+        _ => return None,
+    })
+}
+
+// The text's lines, each split in the pieces to style
+pub fn highlight<'a>(text: &'a str, language: Option<&str>) -> Vec<Vec<(Kind, &'a str)>> {
+    let tokens = match language.and_then(syntax) {
+        Some(syntax) => tokenize(text, &syntax),
+        None => vec![(Kind::Plain, text)],
+    };
+
+    let mut lines = vec![Vec::new()];
+
+    // Comments and strings can span lines, they're cut at each one
+    for (kind, token) in tokens {
+        for (i, part) in token.split('\n').enumerate() {
+            if i != 0 {
+                lines.push(Vec::new());
+            }
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push((kind, part));
+            }
+        }
+    }
+
+    // No empty line after the last '\n'
+    if lines.len() > 1 && lines.last().is_some_and(Vec::is_empty) {
+        lines.pop();
+    }
+
+    lines
+}
+
+fn tokenize<'a>(text: &'a str, syntax: &Syntax) -> Vec<(Kind, &'a str)> {
+    let mut tokens = Vec::new();
+
+    // Start of the plain text that is not pushed yet
+    let mut plain = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+
+        let (kind, len) = if syntax
+            .line_comments
+            .iter()
+            .any(|prefix| rest.starts_with(prefix))
+        {
+            (Kind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some((open, close)) = syntax
+            .block_comment
+            .filter(|(open, _)| rest.starts_with(open))
+        {
+            let len = rest[open.len()..]
+                .find(close)
+                .map(|end| open.len() + end + close.len())
+                .unwrap_or(rest.len());
+            (Kind::Comment, len)
+        } else if syntax.quotes.contains(&c) {
+            (Kind::String, string_len(rest, c))
+        } else if c.is_alphabetic() || c == '_' || c == '#' {
+            // Whole words, so numbers and keywords in identifiers stay plain
+            let len = rest[c.len_utf8()..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map(|end| c.len_utf8() + end)
+                .unwrap_or(rest.len());
+
+            if !syntax.keywords.contains(&&rest[..len]) {
+                i += len;
+                continue;
+            }
+            (Kind::Keyword, len)
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            (Kind::Number, len)
+        } else {
+            i += c.len_utf8();
+            continue;
+        };
+
+        if plain < i {
+            tokens.push((Kind::Plain, &text[plain..i]));
+        }
+        tokens.push((kind, &text[i..i + len]));
+
+        i += len;
+        plain = i;
+    }
+
+    if plain < text.len() {
+        tokens.push((Kind::Plain, &text[plain..]));
+    }
+
+    tokens
+}
+
+// Length of the string starting at the beginning of rest, quote included
+fn string_len(rest: &str, quote: char) -> usize {
+    let mut escaped = false;
+
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return i + c.len_utf8(),
+            // Unclosed, don't color the rest of the paste
+            '\n' if quote != '`' => return i,
+            _ => (),
+        }
+    }
+
+    rest.len()
+}
@@ -4,10 +4,12 @@ extern crate gloo_console;
 
 mod app;
 mod component;
+mod crypto;
+mod highlight;
 mod scene;
 mod utils;
 
-#[derive(Debug, Clone, Copy, PartialEq, yew_router::Routable)]
+#[derive(Debug, Clone, PartialEq, yew_router::Routable)]
 pub enum Route {
     #[at("/")]
     Default,
@@ -17,6 +19,10 @@ pub enum Route {
     Upload,
     #[at("/contact")]
     Contact,
+    #[at("/download")]
+    Download,
+    #[at("/p/:id")]
+    Paste { id: String },
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -55,6 +61,22 @@ fn Router() -> yew::Html {
                             Scene::Contact,
                         ],2)
                     }
+                    Route::Download => {
+                        (vec![
+                            Scene::Home,
+                            Scene::Upload,
+                            Scene::Contact,
+                            Scene::Download,
+                        ],3)
+                    }
+                    Route::Paste { .. } => {
+                        (vec![
+                            Scene::Home,
+                            Scene::Upload,
+                            Scene::Contact,
+                            Scene::Paste,
+                        ],3)
+                    }
                     Route::NotFound => {
                         (vec![
                             Scene::NotFound
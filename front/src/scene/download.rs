@@ -0,0 +1,203 @@
+// Download page for files encrypted in the browser (see crypto.rs)
+//
+// Links look like /download?id=<uuid>#<key>, the key stays in the browser as the fragment is never sent
+
+use gloo::console::log;
+
+enum State {
+    Downloading,
+    Decrypting,
+    Done {
+        file_name: String,
+        data: web_sys::Blob,
+    },
+    Error(String),
+}
+
+pub enum Message {
+    Downloaded {
+        file_name: String,
+        data: Vec<u8>,
+    },
+    Decrypted {
+        file_name: String,
+        data: web_sys::Blob,
+    },
+    Save,
+    Error(String),
+}
+
+pub struct Download {
+    state: State,
+    key: String,
+}
+
+impl yew::Component for Download {
+    type Message = Message;
+    type Properties = ();
+
+    fn create(ctx: &yew::Context<Self>) -> Self {
+        let location = gloo::utils::window().location();
+
+        let id = location.search().ok().and_then(|search| {
+            search
+                .trim_start_matches('?')
+                .split('&')
+                .find_map(|param| param.strip_prefix("id="))
+                .map(str::to_string)
+        });
+        let key = location
+            .hash()
+            .ok()
+            .map(|hash| hash.trim_start_matches('#').to_string())
+            .unwrap_or_default();
+
+        let Some(id) = id else {
+            return Self {
+                state: State::Error(String::from("The link is missing the file's id")),
+                key,
+            };
+        };
+
+        if key.is_empty() {
+            return Self {
+                state: State::Error(String::from("The link is missing the file's key")),
+                key,
+            };
+        }
+
+        ctx.link().send_future(async move {
+            match fetch(&id).await {
+                Ok((file_name, data)) => Message::Downloaded { file_name, data },
+                Err(e) => Message::Error(
+                    e.as_string()
+                        .unwrap_or(format!("Unable to download the file due to: {e:?}")),
+                ),
+            }
+        });
+
+        Self {
+            state: State::Downloading,
+            key,
+        }
+    }
+
+    fn update(&mut self, ctx: &yew::Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Message::Downloaded { file_name, data } => {
+                let key = self.key.clone();
+                ctx.link().send_future(async move {
+                    match crate::crypto::decrypt(&data, &key).await {
+                        Ok(data) => Message::Decrypted { file_name, data },
+                        Err(e) => Message::Error(
+                            e.as_string()
+                                .unwrap_or(format!("Unable to decrypt the file due to: {e:?}")),
+                        ),
+                    }
+                });
+                self.state = State::Decrypting;
+                true
+            }
+            Message::Decrypted { file_name, data } => {
+                self.state = State::Done { file_name, data };
+                ctx.link().send_message(Message::Save);
+                true
+            }
+            Message::Save => {
+                let State::Done { file_name, data } = &self.state else {
+                    return false;
+                };
+
+                if let Err(e) = save(file_name, data) {
+                    log!(format!("Failed to save {file_name} due to: {e:?}"));
+                    crate::component::push_notification(crate::component::Notification::error(
+                        "Save error",
+                        vec![&format!("Could not save {file_name}")],
+                        5.,
+                    ));
+                }
+                false
+            }
+            Message::Error(e) => {
+                log!(format!("Download failed due to: {e}"));
+                crate::component::push_notification(crate::component::Notification::error(
+                    "Download error",
+                    vec![&e],
+                    10.,
+                ));
+                self.state = State::Error(e);
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &yew::Context<Self>) -> yew::Html {
+        yew::html! {<div class="download_view">
+            <h1>{ "Encrypted file" }</h1>
+            {
+                match &self.state {
+                    State::Downloading => yew::html! { <p>{ "Downloading . . ." }</p> },
+                    State::Decrypting => yew::html! { <p>{ "Decrypting . . ." }</p> },
+                    State::Done { file_name, .. } => yew::html! {<>
+                        <p>{ format!("{file_name} has been decrypted") }</p>
+                        <button class="download_button" onclick={ctx.link().callback(|_| Message::Save)}>
+                            { "Save" }
+                        </button>
+                    </>},
+                    State::Error(e) => yew::html! { <p class="download_error">{ e }</p> },
+                }
+            }
+        </div>}
+    }
+}
+
+// Returns the stored file name and the (still encrypted) content
+async fn fetch(id: &str) -> Result<(String, Vec<u8>), wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsValue;
+
+    let info = serde_json::from_str::<serde_json::Value>(
+        &crate::utils::get(&format!("/info/{id}"))
+            .await
+            .and_then(|resp| resp.text())
+            .map(wasm_bindgen_futures::JsFuture::from)?
+            .await?
+            .as_string()
+            .unwrap_or_default(),
+    )
+    .map_err(|e| JsValue::from(format!("Could not read the file's informations: {e}")))?;
+
+    if info["upload_info"]["client_encrypted"] != serde_json::Value::Bool(true) {
+        return Err(JsValue::from(format!(
+            "This file is not encrypted, you can download it directly at /{id}"
+        )));
+    }
+
+    let name = info["upload_info"]["name"].as_str().unwrap_or_default();
+    let file_name = match info["upload_info"]["extension"].as_str() {
+        Some(ext) if !ext.is_empty() => format!("{name}.{ext}"),
+        _ => name.to_string(),
+    };
+
+    let data = crate::utils::get(&format!("/{id}"))
+        .await
+        .and_then(|resp| resp.array_buffer())
+        .map(wasm_bindgen_futures::JsFuture::from)?
+        .await?;
+
+    Ok((file_name, js_sys::Uint8Array::new(&data).to_vec()))
+}
+
+// Makes the browser save the blob, through a temporary link
+fn save(file_name: &str, data: &web_sys::Blob) -> Result<(), wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast as _;
+
+    let url = web_sys::Url::create_object_url_with_blob(data)?;
+
+    let anchor: web_sys::HtmlAnchorElement =
+        gloo::utils::document().create_element("a")?.dyn_into()?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    web_sys::Url::revoke_object_url(&url)
+}
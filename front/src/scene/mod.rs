@@ -6,6 +6,10 @@ mod home;
 pub use home::Home;
 mod not_found;
 pub use not_found::NotFound;
+mod download;
+use download::Download;
+mod paste;
+use paste::Paste;
 use yew::Callback;
 
 use crate::Route;
@@ -15,6 +19,8 @@ pub enum Scene {
     Home,
     Upload,
     Contact,
+    Download,
+    Paste,
     NotFound,
 }
 
@@ -26,6 +32,8 @@ impl Scene {
             Scene::Home => html! {<Home {set_scene_cb}/>},
             Scene::Upload => html! {<Upload />},
             Scene::Contact => html! {<Contact />},
+            Scene::Download => html! {<Download />},
+            Scene::Paste => html! {<Paste />},
             Scene::NotFound => html! {<NotFound />},
         }
     }
@@ -34,6 +42,10 @@ impl Scene {
             Scene::Home => Route::Home,
             Scene::Upload => Route::Upload,
             Scene::Contact => Route::Contact,
+            Scene::Download => Route::Download,
+            Scene::Paste => Route::Paste {
+                id: paste::current_id(),
+            },
             Scene::NotFound => Route::NotFound,
         }
     }
@@ -45,6 +57,8 @@ impl std::fmt::Display for Scene {
             Scene::Home => write!(f, "Home"),
             Scene::Upload => write!(f, "Upload"),
             Scene::Contact => write!(f, "Contact"),
+            Scene::Download => write!(f, "Download"),
+            Scene::Paste => write!(f, "Paste"),
             Scene::NotFound => write!(f, "Not found"),
         }
     }
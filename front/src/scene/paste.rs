@@ -0,0 +1,172 @@
+// Viewer of the pastes sent from the upload page (or POST /paste), at /p/<uuid>
+//
+// The text comes from the normal download route (which is also the raw link), the language from /info
+
+enum State {
+    Loading,
+    Loaded {
+        text: String,
+        language: Option<String>,
+    },
+    Error(String),
+}
+
+pub enum Message {
+    Loaded {
+        text: String,
+        language: Option<String>,
+    },
+    ToggleWrap,
+    Error(String),
+}
+
+pub struct Paste {
+    id: String,
+    state: State,
+    wrap: bool,
+}
+
+// The id in the current url
+pub fn current_id() -> String {
+    gloo::utils::window()
+        .location()
+        .pathname()
+        .ok()
+        .and_then(|path| path.strip_prefix("/p/").map(str::to_string))
+        .unwrap_or_default()
+}
+
+impl yew::Component for Paste {
+    type Message = Message;
+    type Properties = ();
+
+    fn create(ctx: &yew::Context<Self>) -> Self {
+        let id = current_id();
+
+        if id.is_empty() {
+            return Self {
+                id,
+                state: State::Error(String::from("The link is missing the paste's id")),
+                wrap: false,
+            };
+        }
+
+        {
+            let id = id.clone();
+            ctx.link().send_future(async move {
+                match fetch(&id).await {
+                    Ok((text, language)) => Message::Loaded { text, language },
+                    Err(e) => Message::Error(
+                        e.as_string()
+                            .unwrap_or(format!("Unable to load the paste due to: {e:?}")),
+                    ),
+                }
+            });
+        }
+
+        Self {
+            id,
+            state: State::Loading,
+            wrap: false,
+        }
+    }
+
+    fn update(&mut self, _ctx: &yew::Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Message::Loaded { text, language } => {
+                self.state = State::Loaded { text, language };
+                true
+            }
+            Message::ToggleWrap => {
+                self.wrap = !self.wrap;
+                true
+            }
+            Message::Error(e) => {
+                gloo::console::log!(format!("Paste failed to load due to: {e}"));
+                crate::component::push_notification(crate::component::Notification::error(
+                    "Paste error",
+                    vec![&e],
+                    10.,
+                ));
+                self.state = State::Error(e);
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &yew::Context<Self>) -> yew::Html {
+        let (text, language) = match &self.state {
+            State::Loading => {
+                return yew::html! {<div class="paste_view"><p>{ "Loading . . ." }</p></div>}
+            }
+            State::Error(e) => {
+                return yew::html! {<div class="paste_view"><p class="paste_error">{ e }</p></div>}
+            }
+            State::Loaded { text, language } => (text, language),
+        };
+
+        let lines = crate::highlight::highlight(text, language.as_deref());
+
+        yew::html! {<div class="paste_view">
+            <div class="paste_toolbar">
+                <span class="paste_language">{ language.as_deref().unwrap_or("plain text") }</span>
+                <span>{ format!("{} lines", lines.len()) }</span>
+                <label>
+                    <input
+                        type="checkbox"
+                        checked={self.wrap}
+                        onchange={ctx.link().callback(|_| Message::ToggleWrap)}
+                    />
+                    { "Wrap" }
+                </label>
+                <a class="paste_raw" href={format!("/{}", self.id)}>{ "Raw" }</a>
+            </div>
+            <pre class={yew::classes!("paste_code", self.wrap.then_some("paste_wrap"))}>{
+                for lines.into_iter().enumerate().map(|(i, line)| yew::html! {
+                    <div class="paste_line">
+                        <span class="paste_line_number">{ (i + 1).to_string() }</span>
+                        <code class="paste_line_content">{
+                            for line.into_iter().map(|(kind, token)| yew::html! {
+                                <span class={kind.class()}>{ token }</span>
+                            })
+                        }</code>
+                    </div>
+                })
+            }</pre>
+        </div>}
+    }
+}
+
+// Returns the text and its language
+async fn fetch(id: &str) -> Result<(String, Option<String>), wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsValue;
+
+    let info = serde_json::from_str::<serde_json::Value>(
+        &crate::utils::get(&format!("/info/{id}"))
+            .await
+            .and_then(|resp| resp.text())
+            .map(wasm_bindgen_futures::JsFuture::from)?
+            .await?
+            .as_string()
+            .unwrap_or_default(),
+    )
+    .map_err(|e| JsValue::from(format!("Could not read the paste's informations: {e}")))?;
+
+    if info["upload_info"]["paste"] != serde_json::Value::Bool(true) {
+        return Err(JsValue::from(format!(
+            "This is not a paste, you can download it at /{id}"
+        )));
+    }
+
+    let language = info["upload_info"]["language"].as_str().map(str::to_string);
+
+    let text = crate::utils::get(&format!("/{id}"))
+        .await
+        .and_then(|resp| resp.text())
+        .map(wasm_bindgen_futures::JsFuture::from)?
+        .await?
+        .as_string()
+        .unwrap_or_default();
+
+    Ok((text, language))
+}
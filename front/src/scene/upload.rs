@@ -14,6 +14,7 @@ const fn parse_u64(s: &str) -> u64 {
 }
 
 pub const SIZE_LIMIT_BYTES: u64 = parse_u64(env!("MAX_UPLOAD_SIZE"));
+pub const PASTE_LIMIT_BYTES: u64 = parse_u64(env!("MAX_PASTE_SIZE"));
 
 fn new_local_id() -> u32 {
     use std::sync::atomic::Ordering;
@@ -26,7 +27,9 @@ enum FileState {
     Loading,
     Local,
     Uploading,
-    Uploaded(uuid::Uuid),
+    // The short id (the uuid if the server didn't send one), the key is only there if the file was encrypted
+    // before the upload
+    Uploaded(String, Option<String>),
     UploadError(String),
 }
 
@@ -45,10 +48,13 @@ pub enum Message {
     Loaded {
         local_id: u32,
     },
+    ToggleEncryption,
     Upload,
     Uploaded {
         local_id: u32,
         upload_uuid: uuid::Uuid,
+        short_id: Option<String>,
+        key: Option<String>,
     },
     UploadError {
         local_id: u32,
@@ -57,12 +63,31 @@ pub enum Message {
     RemoveLocal {
         local_id: u32,
     },
+    PasteInput(String),
+    PasteLanguage(String),
+    Paste,
+    Pasted(String),
+    PasteError(String),
     Error(String),
 }
 
 pub struct Upload {
     // readers: std::collections::HashMap<u32, gloo::file::callbacks::FileReader>,
     files: Vec<UserFile>,
+    // Encrypt files in the browser before uploading them, see crypto.rs
+    encrypt: bool,
+    // Text sent to /paste instead of a file, see scene/paste.rs
+    paste: String,
+    paste_language: String,
+    paste_state: PasteState,
+}
+
+#[derive(PartialEq)]
+enum PasteState {
+    Editing,
+    Sending,
+    // The short id, see send_paste
+    Sent(String),
 }
 
 impl yew::Component for Upload {
@@ -72,6 +97,10 @@ impl yew::Component for Upload {
     fn create(_ctx: &yew::Context<Self>) -> Self {
         Self {
             files: Vec::default(),
+            encrypt: false,
+            paste: String::new(),
+            paste_language: String::new(),
+            paste_state: PasteState::Editing,
         }
     }
 
@@ -104,9 +133,8 @@ impl yew::Component for Upload {
 
                 self.files.push(UserFile {
                     local_id,
-                    // This isn't a security, as client side security are dumb imo,
-                    // this is just to make sure that the normal user can upload it's file without too much trouble
-                    name: file.name().replace([' ', '/', '\\', '\'', '’'], "_"),
+                    // Sent as is, the server cleans it up
+                    name: file.name(),
                     inner: file,
                     state: FileState::Loading,
                 });
@@ -156,7 +184,7 @@ impl yew::Component for Upload {
                     component::push_notification(component::Notification::info(
                         "Loaded file",
                         vec![
-                            &format!("File name: {:?}", file.name()),
+                            &format!("File name: {:?}", file.name),
                             &format!(
                                 "File size: {}",
                                 mem::format(file.inner.size(), &mem::Prefix::Binary)
@@ -168,6 +196,10 @@ impl yew::Component for Upload {
 
                 true
             }
+            Message::ToggleEncryption => {
+                self.encrypt = !self.encrypt;
+                true
+            }
             Message::Upload => {
                 use std::str::FromStr as _;
 
@@ -181,8 +213,9 @@ impl yew::Component for Upload {
                     }
 
                     let gloofile = file.inner.clone();
-                    let name = file.name();
-                    let ext = file.extension().unwrap_or_default();
+                    // Any character can be in there, see back/src/filename.rs
+                    let name = String::from(js_sys::encode_uri_component(&file.name));
+                    let encrypt = self.encrypt;
 
                     file.state = FileState::Uploading;
                     count += 1;
@@ -193,10 +226,45 @@ impl yew::Component for Upload {
                         reqinit.set_method("PUT");
                         reqinit.set_mode(web_sys::RequestMode::Cors);
 
-                        reqinit.set_body(&(*gloofile).clone().into());
+                        let key = if encrypt {
+                            let (encrypted, key) =
+                                match crate::crypto::encrypt(gloofile.as_ref()).await {
+                                    Ok(encrypted) => encrypted,
+                                    Err(e) => {
+                                        return Message::UploadError {
+                                            local_id,
+                                            error: e.as_string().unwrap_or(format!(
+                                                "Unable to encrypt the file due to: {e:?}"
+                                            )),
+                                        }
+                                    }
+                                };
+
+                            // The server only stores this as a flag for the download page
+                            let headers = match web_sys::Headers::new().and_then(|headers| {
+                                headers.set("X-Client-Encrypted", "1").map(|_| headers)
+                            }) {
+                                Ok(headers) => headers,
+                                Err(e) => {
+                                    return Message::UploadError {
+                                        local_id,
+                                        error: format!(
+                                            "Unable to set the request headers due to: {e:?}"
+                                        ),
+                                    }
+                                }
+                            };
+                            reqinit.set_headers(&headers);
+
+                            reqinit.set_body(&encrypted);
+                            Some(key)
+                        } else {
+                            reqinit.set_body(&(*gloofile).clone().into());
+                            None
+                        };
 
                         let request = match web_sys::Request::new_with_str_and_init(
-                            &format!("/{name}.{ext}"),
+                            &format!("/{name}"),
                             &reqinit,
                         ) {
                             Ok(request) => request,
@@ -262,6 +330,9 @@ impl yew::Component for Upload {
                             }
                         }
 
+                        // Shorter links, the uuid still works
+                        let short_id = resp.headers().get("X-Short-Id").ok().flatten();
+
                         let Ok(resp_text_promise) = resp.text() else {
                             return Message::UploadError {
                                 local_id,
@@ -301,6 +372,8 @@ impl yew::Component for Upload {
                         Message::Uploaded {
                             local_id,
                             upload_uuid: uuid,
+                            short_id,
+                            key,
                         }
                     });
                 }
@@ -316,6 +389,8 @@ impl yew::Component for Upload {
             Message::Uploaded {
                 local_id,
                 upload_uuid,
+                short_id,
+                key,
             } => {
                 log!(format!("Succesfully uploaded with id: {local_id}"));
 
@@ -340,7 +415,10 @@ impl yew::Component for Upload {
                     5.,
                 ));
 
-                file.state = FileState::Uploaded(upload_uuid);
+                file.state = FileState::Uploaded(
+                    short_id.unwrap_or_else(|| upload_uuid.hyphenated().to_string()),
+                    key,
+                );
 
                 true
             }
@@ -388,6 +466,71 @@ impl yew::Component for Upload {
                 self.files.retain(|f| f.local_id != local_id);
                 true
             }
+            Message::PasteInput(text) => {
+                self.paste = text;
+                // A new paste, the link of the last one stays in the notifications
+                self.paste_state = PasteState::Editing;
+                true
+            }
+            Message::PasteLanguage(language) => {
+                self.paste_language = language;
+                false
+            }
+            Message::Paste => {
+                if self.paste.is_empty() || self.paste_state == PasteState::Sending {
+                    return false;
+                }
+
+                if self.paste.len() as u64 > PASTE_LIMIT_BYTES {
+                    component::push_notification(component::Notification::error(
+                        "Paste too large",
+                        vec![
+                            &format!(
+                                "Paste size: {}",
+                                mem::format(self.paste.len() as u64, &mem::Prefix::Binary)
+                            ),
+                            &format!(
+                                "Max size: {}",
+                                mem::format(PASTE_LIMIT_BYTES, &mem::Prefix::Binary)
+                            ),
+                        ],
+                        5.,
+                    ));
+                    return false;
+                }
+
+                let text = self.paste.clone();
+                let language = self.paste_language.trim().to_string();
+
+                ctx.link().send_future(async move {
+                    match send_paste(&text, &language).await {
+                        Ok(id) => Message::Pasted(id),
+                        Err(e) => Message::PasteError(e),
+                    }
+                });
+
+                self.paste_state = PasteState::Sending;
+                true
+            }
+            Message::Pasted(id) => {
+                component::push_notification(component::Notification::info(
+                    "Pasted",
+                    vec![&format!("Paste id: {id}")],
+                    5.,
+                ));
+                self.paste_state = PasteState::Sent(id);
+                true
+            }
+            Message::PasteError(error) => {
+                log!(format!("Paste failled due to: {error}"));
+                component::push_notification(component::Notification::error(
+                    "Paste error",
+                    vec![&error],
+                    10.,
+                ));
+                self.paste_state = PasteState::Editing;
+                true
+            }
             Message::Error(e) => {
                 crate::component::push_notification(crate::component::Notification::error(
                     "An error occured",
@@ -406,6 +549,15 @@ impl yew::Component for Upload {
             <button class="upload_button" onclick={ctx.link().callback(|_| Message::Upload)}>
                 { "Upload!" }
             </button>
+            <label class="upload_encrypt">
+                <input
+                    type="checkbox"
+                    checked={self.encrypt}
+                    onchange={ctx.link().callback(|_| Message::ToggleEncryption)}
+                />
+                { "Encrypt in my browser (only people with the link can read the file)" }
+            </label>
+            <div class="upload_sources">
             <label
                 class = "upload_dragdrop"
                 ondrop={ctx.link().batch_callback(|event: yew::DragEvent| {
@@ -433,6 +585,8 @@ impl yew::Component for Upload {
                 <p>{ "Drop your file(s) here or click to select" }</p>
                 <p class="upload_dragdrop_info">{ format!("{} maximum", mem::format(SIZE_LIMIT_BYTES, &mem::Prefix::Binary)) }</p>
             </label>
+            { self.view_paste(ctx) }
+            </div>
             <div>{
                 // .rev() Does fix the video issue see #13
                 for self.files.iter().map(|file: &UserFile|{
@@ -448,14 +602,18 @@ impl yew::Component for Upload {
                                     FileState::Loading => yew::html!{ <p class="preview-state">{ "Loading . . ." }</p>},
                                     FileState::Local => yew::html!{ <p class="preview-state">{ "Not yet uploaded" }</p>},
                                     FileState::Uploading => yew::html!{ <p class="preview-state">{ "Uploading . . ." }</p>},
-                                    FileState::Uploaded(uuid) => {
-                                        let uuid = *uuid;
-                                        if let Some(url) = web_sys::window().and_then(|window| window.location().host().ok()){
+                                    FileState::Uploaded(id, key) => {
+                                        if let Some(host) = web_sys::window().and_then(|window| window.location().host().ok()){
+                                            // Encrypted files can only be read through the download page, which needs the key
+                                            let url = match key {
+                                                Some(key) => format!("{host}/download?id={id}#{key}"),
+                                                None => format!("{host}/{id}"),
+                                            };
                                             yew::html!{<>
                                                 <p class="preview-state">
-                                                    { format!("Uploaded with id: {uuid}") }
+                                                    { format!("Uploaded with id: {id}") }
                                                     <button onclick={
-                                                        ctx.link().callback(move |_|Message::CopyToClipboard(format!("{url}/{uuid}")))}>{
+                                                        ctx.link().callback(move |_|Message::CopyToClipboard(url.clone()))}>{
                                                         "Copy"
                                                     }</button>
                                                 </p>
@@ -463,7 +621,7 @@ impl yew::Component for Upload {
                                         }else{
                                             yew::html!{<>
                                                 <p class="preview-state">
-                                                    { format!("Uploaded with id: {uuid}") }
+                                                    { format!("Uploaded with id: {id}") }
                                                 </p>
                                             </>}
                                         }
@@ -489,6 +647,67 @@ impl yew::Component for Upload {
 }
 
 impl Upload {
+    fn view_paste(&self, ctx: &yew::Context<Self>) -> yew::Html {
+        use yew::TargetCast as _;
+
+        yew::html! {<div class="upload_paste">
+            <textarea
+                class="upload_paste_text"
+                placeholder="Or paste some text (logs, code, ..) here"
+                spellcheck="false"
+                value={self.paste.clone()}
+                oninput={ctx.link().callback(|e: yew::InputEvent| {
+                    let textarea: web_sys::HtmlTextAreaElement = e.target_unchecked_into();
+                    Message::PasteInput(textarea.value())
+                })}
+            />
+            <div class="upload_paste_actions">
+                <input
+                    class="upload_paste_language"
+                    type="text"
+                    list="upload_paste_languages"
+                    placeholder="Language (optional)"
+                    value={self.paste_language.clone()}
+                    onchange={ctx.link().callback(|e: yew::Event| {
+                        let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                        Message::PasteLanguage(input.value())
+                    })}
+                />
+                <datalist id="upload_paste_languages">{
+                    for crate::highlight::LANGUAGES.iter().map(|language| yew::html! {
+                        <option value={*language} />
+                    })
+                }</datalist>
+                <button
+                    class="upload_paste_button"
+                    disabled={self.paste.is_empty() || self.paste_state == PasteState::Sending}
+                    onclick={ctx.link().callback(|_| Message::Paste)}
+                >
+                    { if self.paste_state == PasteState::Sending { "Sending . . ." } else { "Paste!" } }
+                </button>
+            </div>
+            <p class="upload_dragdrop_info">{ format!("{} maximum", mem::format(PASTE_LIMIT_BYTES, &mem::Prefix::Binary)) }</p>
+            if let PasteState::Sent(id) = &self.paste_state {
+                {{
+                    let path = format!("/p/{id}");
+                    let url = web_sys::window()
+                        .and_then(|window| window.location().host().ok())
+                        .map(|host| format!("{host}{path}"))
+                        .unwrap_or(path.clone());
+
+                    yew::html! {
+                        <p class="preview-state">
+                            <a href={path}>{ format!("Pasted with id: {id}") }</a>
+                            <button onclick={ctx.link().callback(move |_| Message::CopyToClipboard(url.clone()))}>
+                                { "Copy" }
+                            </button>
+                        </p>
+                    }
+                }}
+            }
+        </div>}
+    }
+
     // fn view_file(file: &UserFile) -> yew::Html {
     //     log!(format!(
     //         "Displaying file:\nType: {}\nName: {}\ndata64 size: {}",
@@ -562,26 +781,47 @@ impl Upload {
     }
 }
 
-impl UserFile {
-    // Returns the extension if any
-    fn extension(&self) -> Option<String> {
-        let name = &self.name;
+// Sends the text to /paste, returns the paste's short id (or its uuid if the server didn't send one)
+async fn send_paste(text: &str, language: &str) -> Result<String, String> {
+    use {std::str::FromStr as _, wasm_bindgen::JsCast as _};
 
-        if !name.contains(".") {
-            return None;
-        }
+    let reqinit = web_sys::RequestInit::new();
+    reqinit.set_method("POST");
+    reqinit.set_mode(web_sys::RequestMode::Cors);
+    reqinit.set_body(&wasm_bindgen::JsValue::from_str(text));
 
-        let dot_index = name.rfind(".").unwrap();
+    let url = if language.is_empty() {
+        String::from("/paste")
+    } else {
+        format!("/paste?lang={}", js_sys::encode_uri_component(language))
+    };
 
-        Some(String::from(&name[(dot_index + 1)..name.len()]))
-    }
+    let request = web_sys::Request::new_with_str_and_init(&url, &reqinit)
+        .map_err(|e| format!("Unable to create the request due to: {e:?}"))?;
 
-    // return the file name, till the first .
-    fn name(&self) -> String {
-        let name = &self.name;
+    let resp: web_sys::Response =
+        wasm_bindgen_futures::JsFuture::from(gloo::utils::window().fetch_with_request(&request))
+            .await
+            .and_then(|resp| resp.dyn_into())
+            .map_err(|e| format!("Unable to receive the response due to: {e:?}"))?;
 
-        let dot_index = name.rfind(".").unwrap_or(name.len());
+    let short_id = resp.headers().get("X-Short-Id").ok().flatten();
 
-        String::from(&name[0..dot_index])
+    let body = match resp.text().map(wasm_bindgen_futures::JsFuture::from) {
+        Ok(body) => body.await.ok().and_then(|body| body.as_string()),
+        Err(_) => None,
     }
+    .unwrap_or_default();
+
+    if !resp.ok() {
+        return Err(format!(
+            "Response error with status: {:?}\n{body}",
+            resp.status_text()
+        ));
+    }
+
+    let uuid = uuid::Uuid::from_str(&body)
+        .map_err(|_| String::from("Could not parse received id into a uuid"))?;
+
+    Ok(short_id.unwrap_or_else(|| uuid.hyphenated().to_string()))
 }
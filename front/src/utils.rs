@@ -81,6 +81,25 @@ pub fn remove_script(id: &str) {
     }
 }
 
+// Fetches the url, non 2xx responses are errors
+pub async fn get(url: &str) -> Result<web_sys::Response, wasm_bindgen::JsValue> {
+    use wasm_bindgen::{JsCast as _, JsValue};
+
+    let resp: web_sys::Response =
+        wasm_bindgen_futures::JsFuture::from(gloo::utils::window().fetch_with_str(url))
+            .await?
+            .dyn_into()?;
+
+    if !resp.ok() {
+        return Err(JsValue::from(format!(
+            "Request to {url} failed with status: {}",
+            resp.status()
+        )));
+    }
+
+    Ok(resp)
+}
+
 pub async fn copy_to_clipboard(text: &str) -> Result<(), wasm_bindgen::JsValue> {
     use wasm_bindgen::JsValue;
 